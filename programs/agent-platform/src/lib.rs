@@ -1,7 +1,16 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, Transfer};
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token;
+use anchor_spl::token_interface::{
+    self, Burn, CloseAccount, GetAccountDataSize, Mint, MintTo, TokenAccount, TokenInterface, TransferChecked,
+};
 use anchor_lang::solana_program;
 use anchor_lang::solana_program::system_instruction;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as sysvar_instructions, load_current_index_checked, load_instruction_at_checked,
+};
 
 declare_id!("3TNdmF3EC9yrJjm5fxfFrrBxur5ntiuoByCqYSgtrEbw");
 
@@ -13,10 +22,53 @@ declare_id!("3TNdmF3EC9yrJjm5fxfFrrBxur5ntiuoByCqYSgtrEbw");
 pub const FLAG_ACTIVE: u32 = 1 << 0;
 pub const FLAG_LOCKED: u32 = 1 << 1;
 pub const FLAG_HAS_STAKING: u32 = 1 << 2;
+pub const FLAG_INDEXED: u32 = 1 << 3;
 
-const AGENT_REGISTRY_SPACE: usize = 336;
-const MAX_URI: usize = 96;
-const MAX_CID_LEN: usize = 96;
+// Delegate permissions
+pub const PERM_CARD: u8 = 1 << 0;
+pub const PERM_MEMORY: u8 = 1 << 1;
+pub const PERM_ACTIVE: u8 = 1 << 2;
+pub const PERM_ALL: u8 = PERM_CARD | PERM_MEMORY | PERM_ACTIVE;
+
+// AgentRegistry layout versions: v1 = fixed 96-byte buffers (336 bytes),
+// v2 = fixed header (147 bytes) + length-prefixed card_uri / memory_ptr
+pub const AGENT_REGISTRY_VERSION: u8 = 2;
+pub const AGENT_REGISTRY_SPACE_V1: usize = 336;
+pub const AGENT_REGISTRY_BASE_SPACE_V2: usize = 147;
+const AGENT_COUNTER_SPACE: usize = 32 + 4 + 1;
+const PENDING_OWNER_SPACE: usize = 32 + 32 + 32 + 8 + 1;
+const DELEGATE_SPACE: usize = 32 + 32 + 32 + 1 + 8 + 1;
+const AGENT_KEY_SPACE: usize = 32 + 32 + 8 + 8 + 1;
+const MAX_URI: usize = 512;
+const MAX_CID_LEN: usize = 512;
+const SIGNED_MEMORY_DOMAIN: &[u8] = b"agent_platform:set_memory:v1";
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;  // Per weighted share
+pub const VIRTUAL_SHARES: u64 = 1_000;  // Virtual shares and tokens of the share price (inflation guard)
+pub const BPS_DENOMINATOR: u64 = 10_000;
+// Lockup tiers: (lock duration in seconds, reward weight in bps)
+pub const LOCKUP_TIERS: [(u32, u16); 4] = [
+    (0, 10_000),
+    (30 * 86_400, 12_500),
+    (90 * 86_400, 15_000),
+    (365 * 86_400, 20_000),
+];
+pub const MAX_EARLY_UNLOCK_PENALTY_BPS: u16 = 5_000;
+pub const MAX_OWNER_FEE_SHARE_BPS: u16 = 5_000;  // Part of an unstake fee a pool owner can take
+// Unstake fee modes (StakingPool.fee_mode overrides ProgramState.fee_mode unless DEFAULT)
+pub const FEE_MODE_DEFAULT: u8 = 0;
+pub const FEE_MODE_SOL: u8 = 1;    // Lamports from the staker
+pub const FEE_MODE_TOKEN: u8 = 2;  // Bps of the withdrawn tokens, into the pool fee vault
+pub const MAX_TOKEN_FEE_BPS: u16 = 1_000;
+pub const FEE_CONFIG_TIMELOCK_SECONDS: i64 = 2 * 86_400;  // propose_fee_config -> update_fee_config
+pub const STAKE_REOPEN_DELAY_SECONDS: i64 = 86_400;  // Last change of a position -> close_stake_account
+pub const MAX_UNBONDING_REQUESTS: usize = 4;  // Pending unstakes per position, each with its own cooldown
+pub const MAX_SLASH_BPS_PER_EPOCH: u16 = 5_000;
+pub const MIN_SLASH_EXIT_WINDOW_SECONDS: u32 = 86_400;  // Slash timelock - unbonding cooldown
+pub const SLASHING_CONFIG_TIMELOCK_SECONDS: i64 =
+    StakingPool::MAX_UNBONDING_PERIOD as i64 + MIN_SLASH_EXIT_WINDOW_SECONDS as i64;  // propose_slashing_config -> configure_slashing
+pub const POOL_FLAG_ACTIVE: u8 = 1 << 0;  // Cleared while paused: no new stake, exits still work
+pub const POOL_FLAG_SLASH_PENDING: u8 = 1 << 2;
+pub const POOL_FLAG_DEPRECATED: u8 = 1 << 3;  // Winding down for good: no new stake, fee- and lock-free exits
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryMode {
@@ -31,6 +83,29 @@ pub enum MemoryMode {
 // PROGRAM
 // ============================================================================
 
+// Agent fields chosen at creation (create_agent, create_indexed_agent)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AgentParams {
+    pub card_uri: String,
+    pub card_hash: [u8; 32],
+    pub has_staking: Option<bool>,  // Default = true
+    pub memory_mode: Option<u8>,
+    pub memory_ptr: Option<Vec<u8>>,
+    pub memory_hash: Option<[u8; 32]>,
+}
+
+// Fee configuration queued by propose_fee_config and applied by update_fee_config
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FeeConfigParams {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub fee_mode: u8,            // FEE_MODE_SOL or FEE_MODE_TOKEN
+    pub fee_immediate_bps: u16,
+    pub fee_regular_bps: u16,
+}
+
 #[program]
 pub mod agent_platform {
     use super::*;
@@ -39,75 +114,52 @@ pub mod agent_platform {
     // AGENT REGISTRY INSTRUCTIONS
     // ========================================================================
 
-    pub fn create_agent(
-        ctx: Context<CreateAgent>,
-        creator: Pubkey,
-        card_uri: String,
-        card_hash: [u8; 32],
-        has_staking: Option<bool>,
-        memory_mode: Option<u8>,
-        memory_ptr: Option<Vec<u8>>,
-        memory_hash: Option<[u8; 32]>,
-    ) -> Result<()> {
+    pub fn create_agent(ctx: Context<CreateAgent>, creator: Pubkey, params: AgentParams) -> Result<()> {
         let agent = &mut ctx.accounts.agent;
-        agent.version = 1;
-        agent.creator = creator;
-        agent.owner = creator;
-        
-        // Memory (optional)
-        if let Some(mode) = memory_mode {
-            if let Some(ptr) = &memory_ptr {
-                require!(ptr.len() > 0 && ptr.len() <= MAX_CID_LEN, PlatformError::InvalidLength);
-                require!(mode <= 3, PlatformError::InvalidMemoryFields);
-                
-                if mode == MemoryMode::Url as u8 {
-                    let url_str = core::str::from_utf8(ptr).map_err(|_| PlatformError::InvalidMemoryFields)?;
-                    require!(url_str.starts_with("https://"), PlatformError::InsecureUrl);
-                }
-                
-                agent.memory_mode = mode;
-                agent.memory_ptr_len = ptr.len() as u8;
-                write_fixed(&mut agent.memory_ptr, ptr);
-                agent.memory_hash = memory_hash.unwrap_or([0u8; 32]);
-            } else {
-                return Err(PlatformError::InvalidMemoryFields.into());
-            }
-        } else {
-            agent.memory_mode = MemoryMode::None as u8;
-            agent.memory_ptr_len = 0;
-            agent.memory_ptr = [0u8; 96];
-            agent.memory_hash = [0u8; 32];
-        }
-        
-        // Card (mandatory)
-        let bytes = card_uri.as_bytes();
-        require!(bytes.len() > 0 && bytes.len() <= MAX_URI, PlatformError::InvalidLength);
-        let ok_scheme = card_uri.starts_with("https://") || card_uri.starts_with("ipfs://");
-        require!(ok_scheme, PlatformError::InsecureUrl);
-        agent.card_uri_len = bytes.len() as u8;
-        write_fixed(&mut agent.card_uri, bytes);
-        agent.card_hash = card_hash;
-        
-        // Flags
-        agent.flags = FLAG_ACTIVE;
-        if has_staking.unwrap_or(true) {
-            agent.flags |= FLAG_HAS_STAKING;
-        }
-        
+        init_agent(agent, creator, params)?;
         agent.bump = ctx.bumps.agent;
-        agent._padding = [0u8; 7];
         emit!(AgentCreated { creator, owner: creator });
         Ok(())
     }
 
-    pub fn set_card(ctx: Context<UpdateAgent>, card_uri: String, card_hash: [u8; 32]) -> Result<()> {
+    pub fn init_agent_counter(ctx: Context<InitAgentCounter>) -> Result<()> {
+        let counter = &mut ctx.accounts.agent_counter;
+        counter.creator = ctx.accounts.creator.key();
+        counter.count = 0;
+        counter.bump = ctx.bumps.agent_counter;
+        Ok(())
+    }
+
+    // Additional agents per creator: seeds = ["agent", creator, index (u32 LE)]
+    pub fn create_indexed_agent(ctx: Context<CreateIndexedAgent>, creator: Pubkey, params: AgentParams) -> Result<()> {
+        let counter = &mut ctx.accounts.agent_counter;
+        let index = counter.count;
+        counter.count = index.checked_add(1).ok_or(PlatformError::CounterOverflow)?;
+
+        let agent = &mut ctx.accounts.agent;
+        init_agent(agent, creator, params)?;
+        agent.flags |= FLAG_INDEXED;
+        agent.agent_index = index.to_le_bytes();
+        agent.bump = ctx.bumps.agent;
+        emit!(IndexedAgentCreated { agent: agent.key(), creator, owner: creator, index });
+        Ok(())
+    }
+
+    pub fn set_card(ctx: Context<SetCard>, card_uri: String, card_hash: [u8; 32]) -> Result<()> {
+        authorize(&ctx.accounts.agent, &ctx.accounts.owner.key(), ctx.accounts.delegate.as_deref(), PERM_CARD)?;
         let agent = &mut ctx.accounts.agent;
         let bytes = card_uri.as_bytes();
-        require!(bytes.len() > 0 && bytes.len() <= MAX_URI, PlatformError::InvalidLength);
+        require!(!bytes.is_empty() && bytes.len() <= MAX_URI, PlatformError::InvalidLength);
         let ok_scheme = card_uri.starts_with("https://") || card_uri.starts_with("ipfs://");
         require!(ok_scheme, PlatformError::InsecureUrl);
-        agent.card_uri_len = bytes.len() as u8;
-        write_fixed(&mut agent.card_uri, bytes);
+        resize_account(
+            &agent.to_account_info(),
+            &ctx.accounts.owner.to_account_info(),
+            rent_refund_account(agent, &ctx.accounts.owner, &ctx.accounts.agent_owner).as_ref(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + AgentRegistry::space(bytes.len(), agent.memory_ptr.len()),
+        )?;
+        agent.card_uri = bytes.to_vec();
         agent.card_hash = card_hash;
         let preview = preview_str(bytes);
         emit!(CardSet {
@@ -119,82 +171,20 @@ pub mod agent_platform {
     }
 
     pub fn set_memory(
-        ctx: Context<UpdateAgent>,
+        ctx: Context<SetMemory>,
         mode: u8,
         ptr: Vec<u8>,
         hash_opt: Option<[u8; 32]>,
     ) -> Result<()> {
-        let agent = &mut ctx.accounts.agent;
-        require!(agent.flags & FLAG_LOCKED == 0, PlatformError::MemoryLocked);
-        require!(ptr.len() <= 96, PlatformError::InvalidLength);
-
-        let mode = match mode {
-            0 => MemoryMode::None,
-            1 => MemoryMode::Cid,
-            2 => MemoryMode::Ipns,
-            3 => MemoryMode::Url,
-            4 => MemoryMode::Manifest,
-            _ => return err!(PlatformError::InvalidMemoryFields),
-        };
-
-        let zero = [0u8; 32];
-        match mode {
-            MemoryMode::None => {
-                require!(ptr.is_empty(), PlatformError::InvalidMemoryFields);
-                require!(hash_opt.unwrap_or(zero) == zero, PlatformError::InvalidMemoryFields);
-                agent.memory_hash = zero;
-                agent.memory_ptr_len = 0;
-                agent.memory_ptr = [0u8; 96];
-            }
-            MemoryMode::Cid => {
-                require!(!ptr.is_empty(), PlatformError::InvalidMemoryFields);
-                require!(hash_opt.unwrap_or(zero) == zero, PlatformError::InvalidMemoryFields);
-                let is_cid_like = {
-                    if let Ok(s) = core::str::from_utf8(&ptr) {
-                        let bytes_ok = s.len() > 0 && s.len() <= MAX_CID_LEN;
-                        if s.starts_with("bafy") {
-                            bytes_ok && s.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'))
-                        } else if s.starts_with("Qm") {
-                            bytes_ok && s.chars().all(|c| match c {
-                                '1'..='9' | 'A'..='H' | 'J'..='N' | 'P'..='Z' | 'a'..='k' | 'm'..='z' => true,
-                                _ => false,
-                            }) && s.len() == 46
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                };
-                require!(is_cid_like, PlatformError::InvalidMemoryFields);
-                agent.memory_hash = zero;
-            }
-            MemoryMode::Ipns | MemoryMode::Manifest | MemoryMode::Url => {
-                require!(!ptr.is_empty(), PlatformError::InvalidMemoryFields);
-                let h = hash_opt.ok_or(PlatformError::InvalidMemoryFields)?;
-                require!(h != zero, PlatformError::InvalidMemoryFields);
-                if let MemoryMode::Url = mode {
-                    let s = core::str::from_utf8(&ptr).map_err(|_| error!(PlatformError::InvalidMemoryFields))?;
-                    require!(s.starts_with("https://"), PlatformError::InsecureUrl);
-                }
-                agent.memory_hash = h;
-            }
-        }
-
-        agent.memory_mode = mode as u8;
-        if !matches!(mode, MemoryMode::None) {
-            agent.memory_ptr_len = ptr.len() as u8;
-            write_fixed(&mut agent.memory_ptr, &ptr);
-        }
-
-        let preview = preview_str(&ptr);
-        emit!(MemoryUpdated {
-            creator: agent.creator,
-            mode: mode as u8,
-            ptr_preview: preview,
-            hash: agent.memory_hash,
-        });
-        Ok(())
+        authorize(&ctx.accounts.agent, &ctx.accounts.owner.key(), ctx.accounts.delegate.as_deref(), PERM_MEMORY)?;
+        resize_account(
+            &ctx.accounts.agent.to_account_info(),
+            &ctx.accounts.owner.to_account_info(),
+            rent_refund_account(&ctx.accounts.agent, &ctx.accounts.owner, &ctx.accounts.agent_owner).as_ref(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + AgentRegistry::space(ctx.accounts.agent.card_uri.len(), ptr.len()),
+        )?;
+        apply_memory_update(&mut ctx.accounts.agent, mode, &ptr, hash_opt)
     }
 
     pub fn lock_memory(ctx: Context<UpdateAgent>) -> Result<()> {
@@ -204,7 +194,8 @@ pub mod agent_platform {
         Ok(())
     }
 
-    pub fn set_active(ctx: Context<UpdateAgent>, is_active: bool) -> Result<()> {
+    pub fn set_active(ctx: Context<DelegatedUpdate>, is_active: bool) -> Result<()> {
+        authorize(&ctx.accounts.agent, &ctx.accounts.owner.key(), ctx.accounts.delegate.as_deref(), PERM_ACTIVE)?;
         let agent = &mut ctx.accounts.agent;
         if is_active {
             agent.flags |= FLAG_ACTIVE;
@@ -215,23 +206,249 @@ pub mod agent_platform {
         Ok(())
     }
 
+    // With staking_pool, an empty pool is closed along with the agent like close_staking_pool
+    // (leftover vault tokens go to owner_token_account, all rent to recipient).
     pub fn close_agent(ctx: Context<CloseAgent>) -> Result<()> {
-        let agent = &ctx.accounts.agent;
+        let accs = &ctx.accounts;
+        let agent = &accs.agent;
         require!(agent.flags & FLAG_ACTIVE == 0, PlatformError::AgentActive);
-        require!(agent.flags & FLAG_HAS_STAKING == 0, PlatformError::StakingEnabled);
+        let Some(pool) = &accs.staking_pool else {
+            require!(agent.flags & FLAG_HAS_STAKING == 0, PlatformError::StakingEnabled);
+            emit!(AgentClosed { creator: agent.creator });
+            return Ok(());
+        };
+        let (Some(token_vault), Some(token_mint), Some(owner_token_account), Some(token_program)) =
+            (&accs.token_vault, &accs.token_mint, &accs.owner_token_account, &accs.token_program)
+        else {
+            return err!(PlatformError::PoolVaultsRequired);
+        };
+        require_keys_eq!(token_vault.key(), pool.token_vault, PlatformError::InvalidVault);
+        require_keys_eq!(token_mint.key(), pool.token_mint, PlatformError::InvalidTokenMint);
+        require_keys_eq!(owner_token_account.mint, pool.token_mint, PlatformError::InvalidTokenAccountMint);
+        require_keys_eq!(owner_token_account.owner, accs.owner.key(), PlatformError::InvalidTokenAccountOwner);
+
+        if let Some(treasury_token_account) = &accs.treasury_token_account {
+            let state = accs.program_state.as_ref().ok_or(PlatformError::PoolVaultsRequired)?;
+            require_keys_eq!(treasury_token_account.mint, pool.token_mint, PlatformError::InvalidTokenAccountMint);
+            require_keys_eq!(treasury_token_account.owner, state.treasury, PlatformError::InvalidTokenAccountOwner);
+        }
+
+        let token_program = token_program.to_account_info();
+        let token_vault = token_vault.to_account_info();
+        let owner_token_account = owner_token_account.to_account_info();
+        let recipient = accs.recipient.to_account_info();
+        let leftover = sweep_pool_vault(pool, &token_program, &token_vault, token_mint, &owner_token_account)?;
+        close_pool_token_account(&token_program, &token_vault, &recipient, pool)?;
+        close_fee_vault(
+            pool,
+            &token_program,
+            token_mint,
+            accs.fee_vault.as_ref(),
+            &owner_token_account,
+            accs.treasury_token_account.as_ref(),
+            &recipient,
+        )?;
+        let reward_token_program = accs.reward_token_program.as_ref().map_or(token_program, |p| p.to_account_info());
+        close_reward_vault(
+            pool,
+            &reward_token_program,
+            accs.reward_vault.as_ref(),
+            accs.reward_mint.as_ref(),
+            accs.owner_reward_account.as_ref(),
+            &recipient,
+        )?;
+
+        emit!(StakingPoolClosed { agent_pda: pool.agent_pda, owner: accs.owner.key(), leftover });
         emit!(AgentClosed { creator: agent.creator });
         Ok(())
     }
 
-    pub fn transfer_owner(ctx: Context<UpdateAgent>, new_owner: Pubkey) -> Result<()> {
+    // Clear FLAG_HAS_STAKING so the agent can be closed without passing its pool;
+    // the pool must never have been created or already be closed.
+    pub fn disable_staking(ctx: Context<DisableStaking>) -> Result<()> {
+        require!(ctx.accounts.staking_pool.data_is_empty(), PlatformError::StakingPoolExists);
+        let agent = &mut ctx.accounts.agent;
+        agent.flags &= !FLAG_HAS_STAKING;
+        emit!(StakingDisabled { creator: agent.creator });
+        Ok(())
+    }
+
+    pub fn transfer_owner(ctx: Context<TransferOwner>, new_owner: Pubkey) -> Result<()> {
+        require!(new_owner != Pubkey::default(), PlatformError::InvalidOwner);
+        // A proposal made before the transfer must not become acceptable again if ownership
+        // comes back to its proposer: close it, rent back to the proposer
+        let info = ctx.accounts.pending_owner.to_account_info();
+        if !info.data_is_empty() {
+            let pending = PendingOwner::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            let proposer = ctx.accounts.proposer.as_ref().ok_or(PlatformError::ProposerRequired)?;
+            require_keys_eq!(proposer.key(), pending.proposer, PlatformError::Unauthorized);
+            **proposer.try_borrow_mut_lamports()? += info.lamports();
+            **info.try_borrow_mut_lamports()? = 0;
+            info.assign(&System::id());
+            info.resize(0)?;
+            emit!(OwnerTransferCancelled { agent: pending.agent, pending_owner: pending.pending_owner });
+        }
+        let agent = &mut ctx.accounts.agent;
+        let old_owner = agent.owner;
+        agent.owner = new_owner;
+        emit!(OwnerTransferred { creator: agent.creator, old_owner, new_owner });
+        Ok(())
+    }
+
+    pub fn propose_owner(ctx: Context<ProposeOwner>, new_owner: Pubkey, expires_at: Option<i64>) -> Result<()> {
         require!(new_owner != Pubkey::default(), PlatformError::InvalidOwner);
+        let expires_at = expires_at.unwrap_or(0);
+        if expires_at != 0 {
+            require!(expires_at > Clock::get()?.unix_timestamp, PlatformError::InvalidExpiry);
+        }
+        let pending = &mut ctx.accounts.pending_owner;
+        pending.agent = ctx.accounts.agent.key();
+        pending.proposer = ctx.accounts.owner.key();
+        pending.pending_owner = new_owner;
+        pending.expires_at = expires_at;
+        pending.bump = ctx.bumps.pending_owner;
+        emit!(OwnerTransferProposed { agent: pending.agent, owner: pending.proposer, pending_owner: new_owner, expires_at });
+        Ok(())
+    }
+
+    pub fn accept_owner(ctx: Context<AcceptOwner>) -> Result<()> {
+        let pending = &ctx.accounts.pending_owner;
         let agent = &mut ctx.accounts.agent;
+        require_keys_eq!(pending.proposer, agent.owner, PlatformError::StaleOwnerTransfer);
+        if pending.expires_at != 0 {
+            require!(Clock::get()?.unix_timestamp <= pending.expires_at, PlatformError::OwnerTransferExpired);
+        }
         let old_owner = agent.owner;
+        let new_owner = pending.pending_owner;
         agent.owner = new_owner;
+        emit!(OwnerTransferAccepted { agent: agent.key(), old_owner, new_owner });
         emit!(OwnerTransferred { creator: agent.creator, old_owner, new_owner });
         Ok(())
     }
 
+    pub fn cancel_owner_transfer(ctx: Context<CancelOwnerTransfer>) -> Result<()> {
+        let pending = &ctx.accounts.pending_owner;
+        emit!(OwnerTransferCancelled { agent: pending.agent, pending_owner: pending.pending_owner });
+        Ok(())
+    }
+
+    pub fn add_delegate(
+        ctx: Context<AddDelegate>,
+        delegate_key: Pubkey,
+        permissions: u8,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        let expires_at = validate_delegate_params(permissions, expires_at)?;
+        let d = &mut ctx.accounts.delegate;
+        d.agent = ctx.accounts.agent.key();
+        d.delegate = delegate_key;
+        d.granted_by = ctx.accounts.owner.key();
+        d.permissions = permissions;
+        d.expires_at = expires_at;
+        d.bump = ctx.bumps.delegate;
+        emit!(DelegateSet { agent: d.agent, delegate: delegate_key, permissions, expires_at });
+        Ok(())
+    }
+
+    pub fn update_delegate(ctx: Context<UpdateDelegate>, permissions: u8, expires_at: Option<i64>) -> Result<()> {
+        let expires_at = validate_delegate_params(permissions, expires_at)?;
+        let d = &mut ctx.accounts.delegate;
+        d.granted_by = ctx.accounts.owner.key();
+        d.permissions = permissions;
+        d.expires_at = expires_at;
+        emit!(DelegateSet { agent: d.agent, delegate: d.delegate, permissions, expires_at });
+        Ok(())
+    }
+
+    pub fn remove_delegate(ctx: Context<RemoveDelegate>) -> Result<()> {
+        let d = &ctx.accounts.delegate;
+        emit!(DelegateRemoved { agent: d.agent, delegate: d.delegate });
+        Ok(())
+    }
+
+    pub fn set_memory_signed(
+        ctx: Context<SetMemorySigned>,
+        mode: u8,
+        ptr: Vec<u8>,
+        hash_opt: Option<[u8; 32]>,
+        nonce: u64,
+    ) -> Result<()> {
+        let binding = &mut ctx.accounts.agent_key;
+        require!(nonce == binding.nonce, PlatformError::InvalidNonce);
+
+        let agent_key = ctx.accounts.agent.key();
+        let signer = binding.key;
+        let message = signed_memory_message(&agent_key, nonce, mode, &ptr, &hash_opt.unwrap_or([0u8; 32]));
+        verify_ed25519_ix(&ctx.accounts.instructions, &signer, &message)?;
+        binding.nonce = nonce.checked_add(1).ok_or(PlatformError::InvalidNonce)?;
+
+        resize_account(
+            &ctx.accounts.agent.to_account_info(),
+            &ctx.accounts.relayer.to_account_info(),
+            rent_refund_account(&ctx.accounts.agent, &ctx.accounts.relayer, &ctx.accounts.agent_owner).as_ref(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + AgentRegistry::space(ctx.accounts.agent.card_uri.len(), ptr.len()),
+        )?;
+        apply_memory_update(&mut ctx.accounts.agent, mode, &ptr, hash_opt)?;
+        emit!(SignedMemoryUpdate { agent: agent_key, signer, nonce });
+        Ok(())
+    }
+
+    pub fn bind_agent_key(ctx: Context<BindAgentKey>) -> Result<()> {
+        let binding = &mut ctx.accounts.agent_key;
+        binding.agent = ctx.accounts.agent.key();
+        binding.key = ctx.accounts.runtime_key.key();
+        binding.bound_at = Clock::get()?.unix_timestamp;
+        binding.nonce = 0;
+        binding.bump = ctx.bumps.agent_key;
+        emit!(AgentKeyBound { agent: binding.agent, key: binding.key });
+        Ok(())
+    }
+
+    pub fn rotate_agent_key(ctx: Context<RotateAgentKey>) -> Result<()> {
+        let binding = &mut ctx.accounts.agent_key;
+        let old_key = binding.key;
+        let new_key = ctx.accounts.new_key.key();
+        require_keys_neq!(old_key, new_key, PlatformError::InvalidAgentKey);
+        binding.key = new_key;
+        binding.bound_at = Clock::get()?.unix_timestamp;
+        emit!(AgentKeyRotated { agent: binding.agent, old_key, new_key });
+        Ok(())
+    }
+
+    pub fn revoke_agent_key(ctx: Context<RevokeAgentKey>) -> Result<()> {
+        let binding = &ctx.accounts.agent_key;
+        emit!(AgentKeyRevoked { agent: binding.agent, key: binding.key });
+        Ok(())
+    }
+
+    pub fn migrate_agent(ctx: Context<MigrateAgent>) -> Result<()> {
+        let info = ctx.accounts.agent.to_account_info();
+        let owner = &ctx.accounts.owner;
+
+        let (from_version, migrated) = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() > 8 && data[..8] == *AgentRegistry::DISCRIMINATOR,
+                PlatformError::UnsupportedVersion
+            );
+            match data[8] {
+                1 => (1u8, AgentRegistryV1::deserialize(&mut &data[8..])?.upgrade()),
+                AGENT_REGISTRY_VERSION => return err!(PlatformError::AlreadyMigrated),
+                _ => return err!(PlatformError::UnsupportedVersion),
+            }
+        };
+        require_keys_eq!(migrated.owner, owner.key(), PlatformError::Unauthorized);
+
+        let new_len = 8 + AgentRegistry::space(migrated.card_uri.len(), migrated.memory_ptr.len());
+        let owner_info = owner.to_account_info();
+        resize_account(&info, &owner_info, Some(&owner_info), &ctx.accounts.system_program.to_account_info(), new_len)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        emit!(AgentMigrated { agent: info.key(), from_version, to_version: AGENT_REGISTRY_VERSION });
+        Ok(())
+    }
+
     // ========================================================================
     // STAKING INSTRUCTIONS
     // ========================================================================
@@ -242,66 +459,159 @@ pub mod agent_platform {
         state.fee_regular_lamports = ProgramState::DEFAULT_REGULAR_FEE;
         state.fee_max_lamports = ProgramState::DEFAULT_MAX_FEE;
         state.decay_duration_seconds = ProgramState::DEFAULT_DECAY_DURATION;
+        state.fee_mode = FEE_MODE_SOL;
+        state.fee_immediate_bps = ProgramState::DEFAULT_IMMEDIATE_FEE_BPS;
+        state.fee_regular_bps = ProgramState::DEFAULT_REGULAR_FEE_BPS;
         state.treasury = ctx.accounts.treasury.key();
         state.bump = ctx.bumps.program_state;
         Ok(())
     }
 
+    // Fee parameters are governed by the program upgrade authority: a new config is queued
+    // here and applied by update_fee_config once FEE_CONFIG_TIMELOCK_SECONDS have passed.
+    pub fn propose_fee_config(ctx: Context<ProposeFeeConfig>, params: FeeConfigParams) -> Result<()> {
+        let FeeConfigParams {
+            fee_immediate_lamports,
+            fee_regular_lamports,
+            fee_max_lamports,
+            decay_duration_seconds,
+            fee_mode,
+            fee_immediate_bps,
+            fee_regular_bps,
+        } = params;
+        require!(
+            fee_regular_lamports <= fee_immediate_lamports
+                && fee_immediate_lamports <= fee_max_lamports
+                && decay_duration_seconds > 0
+                && (fee_mode == FEE_MODE_SOL || fee_mode == FEE_MODE_TOKEN)
+                && fee_regular_bps <= fee_immediate_bps
+                && fee_immediate_bps <= MAX_TOKEN_FEE_BPS,
+            PlatformError::InvalidFeeConfig
+        );
+        let now = Clock::get()?.unix_timestamp;
+        let execute_after = now.saturating_add(FEE_CONFIG_TIMELOCK_SECONDS);
+        let pending = &mut ctx.accounts.pending_fee_config;
+        pending.fee_immediate_lamports = fee_immediate_lamports;
+        pending.fee_regular_lamports = fee_regular_lamports;
+        pending.fee_max_lamports = fee_max_lamports;
+        pending.decay_duration_seconds = decay_duration_seconds;
+        pending.fee_mode = fee_mode;
+        pending.fee_immediate_bps = fee_immediate_bps;
+        pending.fee_regular_bps = fee_regular_bps;
+        pending.proposed_at = now;
+        pending.execute_after = execute_after;
+        pending.bump = ctx.bumps.pending_fee_config;
+        emit!(FeeConfigProposed {
+            fee_immediate_lamports,
+            fee_regular_lamports,
+            fee_max_lamports,
+            decay_duration_seconds,
+            fee_mode,
+            fee_immediate_bps,
+            fee_regular_bps,
+            execute_after,
+        });
+        Ok(())
+    }
+
+    pub fn update_fee_config(ctx: Context<UpdateFeeConfig>) -> Result<()> {
+        let pending = &ctx.accounts.pending_fee_config;
+        require!(Clock::get()?.unix_timestamp >= pending.execute_after, PlatformError::FeeConfigTimelockActive);
+        let state = &mut ctx.accounts.program_state;
+        state.fee_immediate_lamports = pending.fee_immediate_lamports;
+        state.fee_regular_lamports = pending.fee_regular_lamports;
+        state.fee_max_lamports = pending.fee_max_lamports;
+        state.decay_duration_seconds = pending.decay_duration_seconds;
+        state.fee_mode = pending.fee_mode;
+        state.fee_immediate_bps = pending.fee_immediate_bps;
+        state.fee_regular_bps = pending.fee_regular_bps;
+        emit!(FeeConfigUpdated {
+            fee_immediate_lamports: state.fee_immediate_lamports,
+            fee_regular_lamports: state.fee_regular_lamports,
+            fee_max_lamports: state.fee_max_lamports,
+            decay_duration_seconds: state.decay_duration_seconds,
+            fee_mode: state.fee_mode,
+            fee_immediate_bps: state.fee_immediate_bps,
+            fee_regular_bps: state.fee_regular_bps,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_fee_config(ctx: Context<CancelFeeConfig>) -> Result<()> {
+        emit!(FeeConfigCancelled { execute_after: ctx.accounts.pending_fee_config.execute_after });
+        Ok(())
+    }
+
     pub fn create_staking_pool(ctx: Context<CreateStakingPool>, min_stake_amount: u64) -> Result<()> {
         require!(min_stake_amount > 0, PlatformError::InvalidMinStakeAmount);
-        
-        // Initialize token vault via CPI
-        let pool_key = ctx.accounts.staking_pool.key();
-        let seeds = &[b"token_vault", pool_key.as_ref(), &[ctx.bumps.token_vault]];
-        let signer = &[&seeds[..]];
-        
-        let rent = &ctx.accounts.rent;
-        let vault_rent = rent.minimum_balance(anchor_spl::token::TokenAccount::LEN);
-        
-        solana_program::program::invoke_signed(
-            &system_instruction::create_account(
-                ctx.accounts.owner.key,
-                ctx.accounts.token_vault.key,
-                vault_rent,
-                165,
-                ctx.accounts.token_program.key,
-            ),
-            &[
-                ctx.accounts.owner.to_account_info(),
-                ctx.accounts.token_vault.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-            signer,
-        )?;
-        
-        let init_ix = anchor_spl::token::spl_token::instruction::initialize_account3(
-            ctx.accounts.token_program.key,
-            ctx.accounts.token_vault.key,
-            ctx.accounts.token_mint.key,
+        if let Some(receipt_mint) = &ctx.accounts.receipt_mint {
+            require!(receipt_mint.supply == 0, PlatformError::ReceiptsOutstanding);
+        }
+        setup_pool_vault(
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.token_vault,
+            &ctx.accounts.token_mint,
             &ctx.accounts.staking_pool.key(),
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.token_program,
+            ctx.program_id,
         )?;
-        
-        solana_program::program::invoke(
-            &init_ix,
-            &[
-                ctx.accounts.token_vault.to_account_info(),
-                ctx.accounts.token_mint.to_account_info(),
-            ],
+
+        let pool = &mut ctx.accounts.staking_pool;
+        init_pool(
+            pool,
+            ctx.accounts.agent.key(),
+            ctx.accounts.owner.key(),
+            ctx.accounts.token_mint.key(),
+            ctx.accounts.token_vault.key(),
+            min_stake_amount,
+            Clock::get()?.unix_timestamp,
+        );
+        pool.receipt_mint = ctx.accounts.receipt_mint.as_ref().map_or(Pubkey::default(), |m| m.key());
+        pool.bump = ctx.bumps.staking_pool;
+        emit!(PoolCreated { agent_pda: pool.agent_pda, owner: pool.owner, min_stake_amount, receipt_mint: pool.receipt_mint });
+        Ok(())
+    }
+
+    // create_agent + create_staking_pool in one instruction (params.has_staking is ignored,
+    // staking is always enabled)
+    pub fn create_agent_with_pool(
+        ctx: Context<CreateAgentWithPool>,
+        creator: Pubkey,
+        params: AgentParams,
+        min_stake_amount: u64,
+    ) -> Result<()> {
+        require!(min_stake_amount > 0, PlatformError::InvalidMinStakeAmount);
+        if let Some(receipt_mint) = &ctx.accounts.receipt_mint {
+            require!(receipt_mint.supply == 0, PlatformError::ReceiptsOutstanding);
+        }
+        let agent = &mut ctx.accounts.agent;
+        init_agent(agent, creator, AgentParams { has_staking: Some(true), ..params })?;
+        agent.bump = ctx.bumps.agent;
+        emit!(AgentCreated { creator, owner: creator });
+
+        setup_pool_vault(
+            &ctx.accounts.creator_signer.to_account_info(),
+            &ctx.accounts.token_vault,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.staking_pool.key(),
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.token_program,
+            ctx.program_id,
         )?;
-        
         let pool = &mut ctx.accounts.staking_pool;
-        let clock = Clock::get()?;
-        pool.agent_pda = ctx.accounts.agent.key();
-        pool.owner = ctx.accounts.owner.key();
-        pool.token_mint = ctx.accounts.token_mint.key();
-        pool.token_vault = ctx.accounts.token_vault.key();
-        pool.min_stake_amount = min_stake_amount;
-        pool.total_staked = 0;
-        pool.staker_count = 0;
-        pool.created_at = clock.unix_timestamp;
-        pool.flags = 1;
+        init_pool(
+            pool,
+            ctx.accounts.agent.key(),
+            creator,
+            ctx.accounts.token_mint.key(),
+            ctx.accounts.token_vault.key(),
+            min_stake_amount,
+            Clock::get()?.unix_timestamp,
+        );
+        pool.receipt_mint = ctx.accounts.receipt_mint.as_ref().map_or(Pubkey::default(), |m| m.key());
         pool.bump = ctx.bumps.staking_pool;
-        emit!(PoolCreated { agent_pda: pool.agent_pda, owner: pool.owner, min_stake_amount });
+        emit!(PoolCreated { agent_pda: pool.agent_pda, owner: pool.owner, min_stake_amount, receipt_mint: pool.receipt_mint });
         Ok(())
     }
 
@@ -315,17 +625,187 @@ pub mod agent_platform {
         Ok(())
     }
 
+    // Permissionless: point the pool at the agent's current owner after a transfer_owner
+    pub fn sync_pool_owner(ctx: Context<SyncPoolOwner>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let old_owner = pool.owner;
+        pool.owner = ctx.accounts.agent.owner;
+        emit!(PoolOwnerSynced { agent_pda: pool.agent_pda, old_owner, new_owner: pool.owner });
+        Ok(())
+    }
+
+    // Cooldown applied to new unstake requests. A non-zero cooldown disables withdraw_stake.
+    pub fn update_unbonding_period(ctx: Context<UpdateUnbondingPeriod>, new_period_seconds: u32) -> Result<()> {
+        require!(new_period_seconds <= StakingPool::MAX_UNBONDING_PERIOD, PlatformError::InvalidUnbondingPeriod);
+        let pool = &mut ctx.accounts.staking_pool;
+        // Stakers must still be able to leave within the slash timelock
+        if pool.slash_authority != Pubkey::default() {
+            require!(
+                new_period_seconds as u64 + MIN_SLASH_EXIT_WINDOW_SECONDS as u64 <= pool.slash_timelock_seconds as u64,
+                PlatformError::SlashTimelockTooShort
+            );
+        }
+        let old = pool.unbonding_period_seconds;
+        pool.unbonding_period_seconds = new_period_seconds;
+        emit!(UnbondingPeriodUpdated { agent_pda: pool.agent_pda, old_period_seconds: old, new_period_seconds });
+        Ok(())
+    }
+
+    // Burned share of early exits from a lockup (0 = rejected). Snapshotted by set_lockup.
+    pub fn update_early_unlock_penalty(ctx: Context<UpdateEarlyUnlockPenalty>, penalty_bps: u16) -> Result<()> {
+        require!(penalty_bps <= MAX_EARLY_UNLOCK_PENALTY_BPS, PlatformError::InvalidPenalty);
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.early_unlock_penalty_bps = penalty_bps;
+        emit!(EarlyUnlockPenaltyUpdated { agent_pda: pool.agent_pda, penalty_bps });
+        Ok(())
+    }
+
+    // Paused pools take no new stake; withdrawals and unstake requests keep working
+    pub fn set_pool_paused(ctx: Context<UpdatePoolStatus>, paused: bool) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(pool.flags & POOL_FLAG_DEPRECATED == 0, PlatformError::PoolDeprecated);
+        if paused {
+            pool.flags &= !POOL_FLAG_ACTIVE;
+        } else {
+            pool.flags |= POOL_FLAG_ACTIVE;
+        }
+        emit!(PoolPausedSet { agent_pda: pool.agent_pda, paused });
+        Ok(())
+    }
+
+    // Wind the pool down: stake is refused for good and exits no longer pay unstake fees
+    pub fn deprecate_pool(ctx: Context<UpdatePoolStatus>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(pool.flags & POOL_FLAG_DEPRECATED == 0, PlatformError::PoolDeprecated);
+        pool.flags = (pool.flags & !POOL_FLAG_ACTIVE) | POOL_FLAG_DEPRECATED;
+        emit!(PoolDeprecated { agent_pda: pool.agent_pda });
+        Ok(())
+    }
+
+    // Pool fee curve within [global regular, global max], owner_share_bps of each fee going
+    // to the pool owner. decay_duration_seconds = 0 restores the ProgramState curve.
+    pub fn update_pool_fees(
+        ctx: Context<UpdatePoolFees>,
+        fee_immediate_lamports: u64,
+        fee_regular_lamports: u64,
+        decay_duration_seconds: u32,
+        owner_share_bps: u16,
+    ) -> Result<()> {
+        let state = &ctx.accounts.program_state;
+        if decay_duration_seconds == 0 {
+            require!(
+                fee_immediate_lamports == 0 && fee_regular_lamports == 0 && owner_share_bps == 0,
+                PlatformError::InvalidFeeConfig
+            );
+        } else {
+            require!(
+                state.fee_regular_lamports <= fee_regular_lamports
+                    && fee_regular_lamports <= fee_immediate_lamports
+                    && fee_immediate_lamports <= state.fee_max_lamports
+                    && decay_duration_seconds <= StakingPool::MAX_FEE_DECAY_DURATION
+                    && owner_share_bps <= MAX_OWNER_FEE_SHARE_BPS,
+                PlatformError::InvalidFeeConfig
+            );
+        }
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.fee_immediate_lamports = fee_immediate_lamports;
+        pool.fee_regular_lamports = fee_regular_lamports;
+        pool.fee_decay_duration_seconds = decay_duration_seconds;
+        pool.fee_owner_share_bps = owner_share_bps;
+        emit!(PoolFeesUpdated {
+            agent_pda: pool.agent_pda,
+            fee_immediate_lamports,
+            fee_regular_lamports,
+            decay_duration_seconds,
+            owner_share_bps,
+        });
+        Ok(())
+    }
+
+    // FEE_MODE_DEFAULT follows ProgramState; token mode needs the fee vault
+    pub fn update_pool_fee_mode(ctx: Context<UpdatePoolFeeMode>, fee_mode: u8) -> Result<()> {
+        require!(fee_mode <= FEE_MODE_TOKEN, PlatformError::InvalidFeeConfig);
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(
+            fee_mode != FEE_MODE_TOKEN || pool.fee_vault != Pubkey::default(),
+            PlatformError::FeeVaultRequired
+        );
+        pool.fee_mode = fee_mode;
+        emit!(PoolFeeModeUpdated { agent_pda: pool.agent_pda, fee_mode });
+        Ok(())
+    }
+
+    // Permissionless: creates the ["fee_vault", pool] account for token-mode fees
+    pub fn init_fee_vault(ctx: Context<InitFeeVault>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.fee_vault = ctx.accounts.fee_vault.key();
+        Ok(())
+    }
+
+    // Permissionless: pays the owner's share of the fee vault to the pool owner, the rest to the treasury
+    pub fn collect_pool_fees(ctx: Context<CollectPoolFees>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let (owner_amount, treasury_amount) = pay_out_fee_vault(
+            pool,
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.fee_vault,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.owner_token_account.to_account_info(),
+            &ctx.accounts.treasury_token_account.to_account_info(),
+        )?;
+        pool.owner_token_fees = 0;
+        emit!(PoolFeesCollected { agent_pda: pool.agent_pda, owner_amount, treasury_amount });
+        Ok(())
+    }
+
     pub fn init_stake(ctx: Context<InitStake>) -> Result<()> {
-        let pool = &ctx.accounts.staking_pool;
+        let now = Clock::get()?.unix_timestamp;
+        init_stake_account(
+            &mut ctx.accounts.stake_account,
+            ctx.accounts.staker.key(),
+            &ctx.accounts.staking_pool,
+            now,
+            ctx.bumps.stake_account,
+        );
+        Ok(())
+    }
+
+    // Lock the whole position for a tier's duration, starting now. Can extend, never shorten.
+    pub fn set_lockup(ctx: Context<SetLockup>, tier: u8) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
         let stake_acc = &mut ctx.accounts.stake_account;
-        let clock = Clock::get()?;
-        
-        stake_acc.staker = ctx.accounts.staker.key();
-        stake_acc.agent_pda = pool.agent_pda;
-        stake_acc.staked_amount = 0;
-        stake_acc.staked_at = clock.unix_timestamp;
-        stake_acc.last_updated_at = clock.unix_timestamp;
-        stake_acc.bump = ctx.bumps.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        let (duration, _) = *LOCKUP_TIERS.get(tier as usize).ok_or(PlatformError::InvalidLockupTier)?;
+        require!(stake_acc.shares > 0, PlatformError::NoStake);
+        update_rewards(pool, now)?;
+        settle_rewards(pool, stake_acc, now)?;
+        end_lockup(pool, stake_acc, now)?;
+
+        let lock_ends_at = now.saturating_add(duration as i64);
+        require!(
+            tier >= stake_acc.lock_tier && lock_ends_at >= stake_acc.lock_ends_at,
+            PlatformError::LockupCannotBeShortened
+        );
+        stake_acc.lock_tier = tier;
+        stake_acc.lock_ends_at = lock_ends_at;
+        stake_acc.lock_penalty_bps = pool.early_unlock_penalty_bps;
+        reweight(pool, stake_acc)?;
+
+        emit!(LockupSet { staker: stake_acc.staker, agent_pda: pool.agent_pda, tier, lock_ends_at, weighted_amount: stake_acc.weighted_amount });
+        Ok(())
+    }
+
+    // Permissionless: end a lockup that has run out so its boost leaves total_weighted_stake
+    pub fn expire_lockup(ctx: Context<ExpireLockup>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        require!(stake_acc.lock_tier != 0 && now >= stake_acc.lock_ends_at, PlatformError::LockupNotEnded);
+        update_rewards(pool, now)?;
+        settle_rewards(pool, stake_acc, now)?;
+        end_lockup(pool, stake_acc, now)?;
+
+        emit!(LockupExpired { staker: stake_acc.staker, agent_pda: pool.agent_pda, weighted_amount: stake_acc.weighted_amount });
         Ok(())
     }
 
@@ -334,71 +814,98 @@ pub mod agent_platform {
         let pool = &mut ctx.accounts.staking_pool;
         let stake_acc = &mut ctx.accounts.stake_account;
         let clock = Clock::get()?;
-
-        // First-time stake: enforce min_stake
-        if stake_acc.staked_amount == 0 {
-            require!(amount >= pool.min_stake_amount, PlatformError::BelowMinimumStake);
-            pool.staker_count = pool.staker_count.saturating_add(1);
+        // No init_stake needed: a missing stake account is created on the first stake
+        if stake_acc.staker == Pubkey::default() {
+            init_stake_account(stake_acc, ctx.accounts.staker.key(), pool, clock.unix_timestamp, ctx.bumps.stake_account);
         }
+        update_rewards(pool, clock.unix_timestamp)?;
+        settle_rewards(pool, stake_acc, clock.unix_timestamp)?;
+        end_lockup(pool, stake_acc, clock.unix_timestamp)?;
 
         require!(stake_acc.staker == ctx.accounts.staker.key(), PlatformError::Unauthorized);
 
+        // Shares are priced against the vault before the deposit lands
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+
         // SPL transfer: staker → vault
         let cpi_ctx = CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
-            Transfer {
+            TransferChecked {
                 from: ctx.accounts.staker_token_account.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
                 to: ctx.accounts.token_vault.to_account_info(),
                 authority: ctx.accounts.staker.to_account_info(),
             },
         );
-        token::transfer(cpi_ctx, amount)?;
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+        // Transfer-fee mints withhold part of the transfer: only what reached the vault counts
+        let received = token::accessor::amount(&ctx.accounts.token_vault)?
+            .checked_sub(vault_balance)
+            .ok_or(PlatformError::MathOverflow)?;
 
-        stake_acc.staked_amount = stake_acc.staked_amount.saturating_add(amount);
+        let receipts = receipt_accounts(pool, &ctx.accounts.receipt_mint, &ctx.accounts.staker_receipt_account)?;
+        // First-time stake: enforce min_stake (every deposit in receipt pools)
+        if stake_acc.shares == 0 {
+            require!(received >= pool.min_stake_amount, PlatformError::BelowMinimumStake);
+            if receipts.is_none() {
+                pool.staker_count = pool.staker_count.saturating_add(1);
+            }
+        }
+        let shares = amount_to_shares(pool, received, vault_balance)?;
+        require!(shares > 0, PlatformError::InvalidStakeAmount);
+        // Receipt pools: the receipts carry the shares, the stake account holds none
+        if let Some((receipt_mint, receipt_account)) = receipts {
+            mint_receipts(&ctx.accounts.token_program, receipt_mint, receipt_account, pool, shares)?;
+        } else {
+            stake_acc.shares = stake_acc.shares.checked_add(shares).ok_or(PlatformError::MathOverflow)?;
+        }
         stake_acc.last_updated_at = clock.unix_timestamp;
-        pool.total_staked = pool.total_staked.saturating_add(amount);
+        pool.total_shares = pool.total_shares.checked_add(shares).ok_or(PlatformError::MathOverflow)?;
+        reweight(pool, stake_acc)?;
 
-        emit!(Staked { staker: stake_acc.staker, agent_pda: pool.agent_pda, amount, total: stake_acc.staked_amount });
+        emit!(Staked { staker: stake_acc.staker, agent_pda: pool.agent_pda, amount: received, shares, total: stake_acc.shares });
         Ok(())
     }
 
-    pub fn withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>, shares: u64) -> Result<()> {
         let pool = &mut ctx.accounts.staking_pool;
         let state = &ctx.accounts.program_state;
         let stake_acc = &mut ctx.accounts.stake_account;
         let clock = Clock::get()?;
 
-        require!(stake_acc.staked_amount > 0, PlatformError::NoStake);
-
-        let elapsed = (clock.unix_timestamp - stake_acc.staked_at).max(0) as u64;
-        let fee = calculate_unstake_fee(elapsed, state)?;
+        require!(pool.unbonding_period_seconds == 0, PlatformError::UnbondingRequired);
+        update_rewards(pool, clock.unix_timestamp)?;
+        settle_rewards(pool, stake_acc, clock.unix_timestamp)?;
+        end_lockup(pool, stake_acc, clock.unix_timestamp)?;
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let remaining = check_unstake_shares(pool, stake_acc, shares, vault_balance)?;
+        let amount = shares_to_amount(pool, shares, vault_balance)?;
+        // Exiting requires holding the receipts for the shares
+        if let Some((receipt_mint, receipt_account)) =
+            receipt_accounts(pool, &ctx.accounts.receipt_mint, &ctx.accounts.staker_receipt_account)?
+        {
+            burn_receipts(&ctx.accounts.token_program, receipt_mint, receipt_account, &ctx.accounts.staker, shares)?;
+        }
+        let penalty = early_unlock_penalty(pool, stake_acc, amount, clock.unix_timestamp)?;
 
-        // Check sufficient SOL before transfer
-        if fee > 0 {
-            let staker_lamports = ctx.accounts.staker.lamports();
-            let rent_exempt = Rent::get()?.minimum_balance(0);
-            
-            require!(
-                staker_lamports >= fee.saturating_add(rent_exempt),
-                PlatformError::InsufficientSolForFee
-            );
-            
-            let ix = anchor_lang::solana_program::system_instruction::transfer(
-                &ctx.accounts.staker.key(),
-                &state.treasury,
+        // SOL fee, or a share of the withdrawn tokens in token mode
+        let (fee, owner_fee, token_fee) = if fee_mode(state, pool) == FEE_MODE_TOKEN {
+            let token_fee = mul_div(amount, token_fee_bps(state, pool, stake_acc, clock.unix_timestamp)?, BPS_DENOMINATOR)?;
+            (0, 0, token_fee)
+        } else {
+            let fee = unstake_fee(state, pool, stake_acc, shares, clock.unix_timestamp)?;
+            let owner_fee = charge_unstake_fee(
+                state,
+                pool,
                 fee,
-            );
-            anchor_lang::solana_program::program::invoke(
-                &ix,
-                &[
-                    ctx.accounts.staker.to_account_info(),
-                    ctx.accounts.treasury.to_account_info(),
-                ],
+                &ctx.accounts.staker.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.agent_owner,
             )?;
-        }
+            (fee, owner_fee, 0)
+        };
 
         // SPL transfer: vault → staker (PDA must sign)
-        let amount = stake_acc.staked_amount;
         let agent_pda = pool.agent_pda;
         let seeds = &[
             b"staking_pool",
@@ -409,24 +916,761 @@ pub mod agent_platform {
 
         let cpi_ctx = CpiContext::new_with_signer(
             ctx.accounts.token_program.to_account_info(),
-            Transfer {
+            TransferChecked {
                 from: ctx.accounts.token_vault.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
                 to: ctx.accounts.staker_token_account.to_account_info(),
                 authority: pool.to_account_info(),
             },
             signer,
         );
-        token::transfer(cpi_ctx, amount)?;
-
-        // Reset stake amount but preserve staked_at
-        stake_acc.staked_amount = 0;
+        token_interface::transfer_checked(cpi_ctx, amount - penalty - token_fee, ctx.accounts.token_mint.decimals)?;
+        if penalty > 0 {
+            burn_from_vault(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_vault,
+                pool,
+                penalty,
+            )?;
+        }
+        if token_fee > 0 {
+            charge_token_fee(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_vault,
+                &ctx.accounts.fee_vault,
+                pool,
+                token_fee,
+            )?;
+        }
+
+        // Decrease shares but preserve staked_at (close_stake_account after STAKE_REOPEN_DELAY_SECONDS)
+        stake_acc.shares = remaining;
         stake_acc.last_updated_at = clock.unix_timestamp;
         
-        pool.total_staked = pool.total_staked.saturating_sub(amount);
+        pool.total_shares = pool.total_shares.saturating_sub(shares);
+        reweight(pool, stake_acc)?;
+        if remaining == 0 && pool.receipt_mint == Pubkey::default() {
+            pool.staker_count = pool.staker_count.saturating_sub(1);
+        }
+
+        emit!(Withdrawn { staker: stake_acc.staker, agent_pda: pool.agent_pda, amount, shares, fee, owner_fee, token_fee, penalty });
+        Ok(())
+    }
+
+    // Start unbonding `shares`: they stop earning immediately and are redeemed by
+    // complete_unstake after the pool cooldown, at that moment's price. Each request keeps its own.
+    pub fn unstake_request(ctx: Context<UnstakeRequest>, shares: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let state = &ctx.accounts.program_state;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let clock = Clock::get()?;
+
+        update_rewards(pool, clock.unix_timestamp)?;
+        settle_rewards(pool, stake_acc, clock.unix_timestamp)?;
+        end_lockup(pool, stake_acc, clock.unix_timestamp)?;
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let remaining = check_unstake_shares(pool, stake_acc, shares, vault_balance)?;
+        let slot = stake_acc
+            .unbonding
+            .iter()
+            .position(|request| request.shares == 0)
+            .ok_or(PlatformError::TooManyUnbondingRequests)?;
+        if let Some((receipt_mint, receipt_account)) =
+            receipt_accounts(pool, &ctx.accounts.receipt_mint, &ctx.accounts.staker_receipt_account)?
+        {
+            burn_receipts(&ctx.accounts.token_program, receipt_mint, receipt_account, &ctx.accounts.staker, shares)?;
+        }
+        // Charged now in shares, burned by complete_unstake
+        let penalty_shares = early_unlock_penalty(pool, stake_acc, shares, clock.unix_timestamp)?;
+
+        // Token mode: the fee shares are redeemed into the fee vault now, the rest is queued
+        let (fee, owner_fee, fee_shares, token_fee) = if fee_mode(state, pool) == FEE_MODE_TOKEN {
+            let fee_shares = mul_div(shares, token_fee_bps(state, pool, stake_acc, clock.unix_timestamp)?, BPS_DENOMINATOR)?;
+            let token_fee = shares_to_amount(pool, fee_shares, vault_balance)?;
+            (0, 0, fee_shares, token_fee)
+        } else {
+            let fee = unstake_fee(state, pool, stake_acc, shares, clock.unix_timestamp)?;
+            let owner_fee = charge_unstake_fee(
+                state,
+                pool,
+                fee,
+                &ctx.accounts.staker.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.agent_owner,
+            )?;
+            (fee, owner_fee, 0, 0)
+        };
+        if token_fee > 0 {
+            charge_token_fee(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_vault,
+                &ctx.accounts.fee_vault,
+                pool,
+                token_fee,
+            )?;
+        }
+        // Pending shares move to total_unbonding: still priced against the vault, no longer active
+        pool.total_shares = pool.total_shares.saturating_sub(shares);
+        pool.total_unbonding = pool.total_unbonding.checked_add(shares - fee_shares).ok_or(PlatformError::MathOverflow)?;
+        let unlock_at = clock.unix_timestamp.saturating_add(pool.unbonding_period_seconds as i64);
+        stake_acc.unbonding[slot] = UnbondingRequest { shares: shares - fee_shares, penalty_shares, ends_at: unlock_at };
+        stake_acc.shares = remaining;
+        stake_acc.last_updated_at = clock.unix_timestamp;
+
+        reweight(pool, stake_acc)?;
+        if remaining == 0 && pool.receipt_mint == Pubkey::default() {
+            pool.staker_count = pool.staker_count.saturating_sub(1);
+        }
+
+        emit!(UnstakeRequested { staker: stake_acc.staker, agent_pda: pool.agent_pda, shares, fee, owner_fee, token_fee, penalty_shares, unlock_at });
+        Ok(())
+    }
+
+    // Release every pending request whose cooldown has ended
+    pub fn complete_unstake(ctx: Context<CompleteUnstake>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        require!(stake_acc.unbonding.iter().any(|request| request.shares > 0), PlatformError::NoPendingUnstake);
+        let mut shares: u64 = 0;
+        let mut penalty_shares: u64 = 0;
+        for request in stake_acc.unbonding.iter_mut().filter(|request| request.shares > 0 && now >= request.ends_at) {
+            shares = shares.checked_add(request.shares).ok_or(PlatformError::MathOverflow)?;
+            penalty_shares = penalty_shares.saturating_add(request.penalty_shares);
+            *request = UnbondingRequest::default();
+        }
+        require!(shares > 0, PlatformError::UnbondingNotFinished);
+
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let amount = shares_to_amount(pool, shares, vault_balance)?;
+        // Penalties queued before the pool was deprecated are waived as well
+        let penalty = if pool.flags & POOL_FLAG_DEPRECATED != 0 {
+            0
+        } else {
+            shares_to_amount(pool, penalty_shares, vault_balance)?
+        };
+
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.token_vault.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+                to: ctx.accounts.staker_token_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount - penalty, ctx.accounts.token_mint.decimals)?;
+        if penalty > 0 {
+            burn_from_vault(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_vault,
+                pool,
+                penalty,
+            )?;
+        }
+
+        pool.total_unbonding = pool.total_unbonding.saturating_sub(shares);
+        emit!(UnstakeCompleted { staker: stake_acc.staker, agent_pda, shares, amount: amount - penalty, penalty });
+        Ok(())
+    }
+
+    // Reclaim the rent of an empty position. Re-opening (init_stake) restarts staked_at and is
+    // only possible STAKE_REOPEN_DELAY_SECONDS after the last change.
+    pub fn close_stake_account(ctx: Context<CloseStakeAccount>) -> Result<()> {
+        let stake_acc = &ctx.accounts.stake_account;
+        require!(
+            stake_acc.shares == 0
+                && stake_acc.unbonding.iter().all(|request| request.shares == 0)
+                && stake_acc.rewards_owed == 0,
+            PlatformError::StakeAccountNotEmpty
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= stake_acc.last_updated_at.saturating_add(STAKE_REOPEN_DELAY_SECONDS),
+            PlatformError::ReopenDelayActive
+        );
+        emit!(StakeAccountClosed { staker: stake_acc.staker, agent_pda: stake_acc.agent_pda });
+        Ok(())
+    }
+
+    // Close a pool without shares and its token accounts (PDA-signed), rent to the owner.
+    // Leftover vault tokens and reward vault balances go to the owner, the fee vault is paid
+    // out like collect_pool_fees. Rewards settled to stakers must have been claimed.
+    pub fn close_staking_pool(ctx: Context<CloseStakingPool>) -> Result<()> {
+        let pool = &ctx.accounts.staking_pool;
+        let owner = ctx.accounts.owner.to_account_info();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        let leftover = sweep_pool_vault(
+            pool,
+            &token_program,
+            &ctx.accounts.token_vault,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.owner_token_account.to_account_info(),
+        )?;
+        close_pool_token_account(&token_program, &ctx.accounts.token_vault, &owner, pool)?;
+        close_fee_vault(
+            pool,
+            &token_program,
+            &ctx.accounts.token_mint,
+            ctx.accounts.fee_vault.as_ref(),
+            &ctx.accounts.owner_token_account.to_account_info(),
+            ctx.accounts.treasury_token_account.as_ref(),
+            &owner,
+        )?;
+        // Rewards may use another token program than the staked mint
+        let reward_token_program = ctx.accounts.reward_token_program.as_ref().map_or(token_program, |p| p.to_account_info());
+        close_reward_vault(
+            pool,
+            &reward_token_program,
+            ctx.accounts.reward_vault.as_ref(),
+            ctx.accounts.reward_mint.as_ref(),
+            ctx.accounts.owner_reward_account.as_ref(),
+            &owner,
+        )?;
+
+        emit!(StakingPoolClosed { agent_pda: pool.agent_pda, owner: owner.key(), leftover });
+        Ok(())
+    }
+
+    // Create the pool's reward vault for `reward_mint` and start emitting `reward_rate`
+    // tokens per second, pro-rata to stake, as long as the vault is funded.
+    pub fn init_rewards(ctx: Context<InitRewards>, reward_rate: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(pool.reward_vault == Pubkey::default(), PlatformError::RewardsAlreadyInitialized);
+        // Rewards accrue to stake account positions, which receipt pools do not have
+        require!(pool.receipt_mint == Pubkey::default(), PlatformError::ReceiptPoolWithoutPositions);
+        pool.reward_mint = ctx.accounts.reward_mint.key();
+        pool.reward_vault = ctx.accounts.reward_vault.key();
+        pool.reward_rate = reward_rate;
+        pool.last_update_time = Clock::get()?.unix_timestamp;
+        emit!(RewardRateUpdated { agent_pda: pool.agent_pda, reward_rate });
+        Ok(())
+    }
+
+    // Take back reward reserve that has not been emitted yet. Rewards already emitted stay
+    // claimable by stakers.
+    pub fn withdraw_reward_reserve(ctx: Context<WithdrawRewardReserve>, amount: u64) -> Result<()> {
+        require!(amount > 0, PlatformError::InvalidRewardAmount);
+        let pool = &mut ctx.accounts.staking_pool;
+        update_rewards(pool, Clock::get()?.unix_timestamp)?;
+        require!(amount <= pool.reward_reserve, PlatformError::InsufficientRewardReserve);
+
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                mint: ctx.accounts.reward_mint.to_account_info(),
+                to: ctx.accounts.owner_reward_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+
+        pool.reward_reserve -= amount;
+        emit!(RewardReserveWithdrawn { agent_pda, amount, reward_reserve: pool.reward_reserve });
+        Ok(())
+    }
+
+    pub fn set_reward_rate(ctx: Context<SetRewardRate>, reward_rate: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        update_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.reward_rate = reward_rate;
+        emit!(RewardRateUpdated { agent_pda: pool.agent_pda, reward_rate });
+        Ok(())
+    }
+
+    pub fn fund_rewards(ctx: Context<FundRewards>, amount: u64) -> Result<()> {
+        require!(amount > 0, PlatformError::InvalidRewardAmount);
+        let pool = &mut ctx.accounts.staking_pool;
+        update_rewards(pool, Clock::get()?.unix_timestamp)?;
+
+        let reserve_before = token::accessor::amount(&ctx.accounts.reward_vault)?;
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.funder_token_account.to_account_info(),
+                mint: ctx.accounts.reward_mint.to_account_info(),
+                to: ctx.accounts.reward_vault.to_account_info(),
+                authority: ctx.accounts.funder.to_account_info(),
+            },
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+        let amount = token::accessor::amount(&ctx.accounts.reward_vault)?
+            .checked_sub(reserve_before)
+            .ok_or(PlatformError::MathOverflow)?;
+
+        pool.reward_reserve = pool.reward_reserve.checked_add(amount).ok_or(PlatformError::MathOverflow)?;
+        emit!(RewardsFunded { agent_pda: pool.agent_pda, funder: ctx.accounts.funder.key(), amount });
+        Ok(())
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        update_rewards(pool, now)?;
+        settle_rewards(pool, stake_acc, now)?;
+        end_lockup(pool, stake_acc, now)?;
+
+        let amount = stake_acc.rewards_owed;
+        require!(amount > 0, PlatformError::NoRewards);
+
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                mint: ctx.accounts.reward_mint.to_account_info(),
+                to: ctx.accounts.staker_reward_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+
+        stake_acc.rewards_owed = 0;
+        pool.rewards_owed_total = pool.rewards_owed_total.saturating_sub(amount);
+        emit!(RewardsClaimed { staker: stake_acc.staker, agent_pda, amount });
+        Ok(())
+    }
 
-        emit!(Withdrawn { staker: stake_acc.staker, agent_pda: pool.agent_pda, amount, fee });
+    // Slashing config is set by the upgrade authority and applied after
+    // SLASHING_CONFIG_TIMELOCK_SECONDS. `authority` may take up to `max_bps_per_epoch` of the
+    // pool per epoch, after a `timelock_seconds` delay. Pubkey::default() disables it.
+    pub fn propose_slashing_config(
+        ctx: Context<ProposeSlashingConfig>,
+        authority: Pubkey,
+        max_bps_per_epoch: u16,
+        timelock_seconds: u32,
+    ) -> Result<()> {
+        require!(
+            max_bps_per_epoch > 0 && max_bps_per_epoch <= MAX_SLASH_BPS_PER_EPOCH,
+            PlatformError::InvalidSlashConfig
+        );
+        let pool = &ctx.accounts.staking_pool;
+        check_slash_config(pool, authority, timelock_seconds)?;
+        let now = Clock::get()?.unix_timestamp;
+        let execute_after = now.saturating_add(SLASHING_CONFIG_TIMELOCK_SECONDS);
+        let pending = &mut ctx.accounts.pending_slashing_config;
+        pending.pool = pool.key();
+        pending.authority = authority;
+        pending.max_bps_per_epoch = max_bps_per_epoch;
+        pending.timelock_seconds = timelock_seconds;
+        pending.proposed_at = now;
+        pending.execute_after = execute_after;
+        pending.bump = ctx.bumps.pending_slashing_config;
+        emit!(SlashingConfigProposed {
+            agent_pda: pool.agent_pda,
+            authority,
+            max_bps_per_epoch,
+            timelock_seconds,
+            execute_after,
+        });
+        Ok(())
+    }
+
+    pub fn configure_slashing(ctx: Context<ConfigureSlashing>) -> Result<()> {
+        let pending = &ctx.accounts.pending_slashing_config;
+        require!(Clock::get()?.unix_timestamp >= pending.execute_after, PlatformError::SlashingConfigTimelockActive);
+        let pool = &mut ctx.accounts.staking_pool;
+        // The owner or the cooldown may have changed while the config was queued
+        check_slash_config(pool, pending.authority, pending.timelock_seconds)?;
+        pool.slash_authority = pending.authority;
+        pool.slash_max_bps_per_epoch = pending.max_bps_per_epoch;
+        pool.slash_timelock_seconds = pending.timelock_seconds;
+        emit!(SlashingConfigured {
+            agent_pda: pool.agent_pda,
+            authority: pool.slash_authority,
+            max_bps_per_epoch: pool.slash_max_bps_per_epoch,
+            timelock_seconds: pool.slash_timelock_seconds,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_slashing_config(ctx: Context<CancelSlashingConfig>) -> Result<()> {
+        emit!(SlashingConfigCancelled {
+            agent_pda: ctx.accounts.staking_pool.agent_pda,
+            execute_after: ctx.accounts.pending_slashing_config.execute_after,
+        });
+        Ok(())
+    }
+
+    pub fn propose_slash(ctx: Context<ProposeSlash>, bps: u16, reason_hash: [u8; 32]) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(bps > 0 && bps <= pool.slash_max_bps_per_epoch, PlatformError::InvalidSlashAmount);
+        // Pools configured before the exit window existed are held to it here
+        check_slash_config(pool, pool.slash_authority, pool.slash_timelock_seconds)?;
+        let now = Clock::get()?.unix_timestamp;
+        let execute_after = now.saturating_add(pool.slash_timelock_seconds as i64);
+
+        let pending = &mut ctx.accounts.pending_slash;
+        pending.pool = pool.key();
+        pending.authority = ctx.accounts.authority.key();
+        pending.destination = ctx.accounts.destination.key();
+        pending.bps = bps;
+        pending.reason_hash = reason_hash;
+        pending.proposed_at = now;
+        pending.execute_after = execute_after;
+        pending.bump = ctx.bumps.pending_slash;
+        pool.flags |= POOL_FLAG_SLASH_PENDING;
+
+        emit!(SlashProposed {
+            agent_pda: pool.agent_pda,
+            authority: pending.authority,
+            destination: pending.destination,
+            bps,
+            reason_hash,
+            execute_after,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_slash(ctx: Context<CancelSlash>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.flags &= !POOL_FLAG_SLASH_PENDING;
+        emit!(SlashCancelled { agent_pda: pool.agent_pda, bps: ctx.accounts.pending_slash.bps });
+        Ok(())
+    }
+
+    // Pay the slashed share of the vault out; every share loses the same value
+    pub fn execute_slash(ctx: Context<ExecuteSlash>) -> Result<()> {
+        let pending = &ctx.accounts.pending_slash;
+        let pool = &mut ctx.accounts.staking_pool;
+        let clock = Clock::get()?;
+        require!(clock.unix_timestamp >= pending.execute_after, PlatformError::SlashTimelockActive);
+
+        if clock.epoch != pool.slash_epoch {
+            pool.slash_epoch = clock.epoch;
+            pool.slashed_bps_in_epoch = 0;
+        }
+        let slashed_bps = pool.slashed_bps_in_epoch.saturating_add(pending.bps);
+        require!(slashed_bps <= pool.slash_max_bps_per_epoch, PlatformError::SlashCapExceeded);
+
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let amount = (vault_balance as u128 * pending.bps as u128 / BPS_DENOMINATOR as u128) as u64;
+
+        if amount > 0 {
+            let agent_pda = pool.agent_pda;
+            let seeds = &[
+                b"staking_pool",
+                agent_pda.as_ref(),
+                &[pool.bump],
+            ];
+            let signer = &[&seeds[..]];
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.token_vault.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: ctx.accounts.destination.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer,
+            );
+            token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+        }
+
+        pool.slashed_bps_in_epoch = slashed_bps;
+        pool.flags &= !POOL_FLAG_SLASH_PENDING;
+
+        emit!(Slashed {
+            agent_pda: pool.agent_pda,
+            destination: pending.destination,
+            bps: pending.bps,
+            amount,
+            reason_hash: pending.reason_hash,
+        });
+        Ok(())
+    }
+
+    // v1 accounts (no version field) are recognised by their size. Permissionless: the payer
+    // only covers the extra rent.
+    pub fn migrate_program_state(ctx: Context<MigrateProgramState>) -> Result<()> {
+        let info = ctx.accounts.program_state.to_account_info();
+        let migrated = read_v1::<ProgramStateV1>(&info, ProgramState::DISCRIMINATOR, ProgramStateV1::SPACE, ProgramState::SPACE)?.upgrade();
+        resize_account(&info, &ctx.accounts.payer.to_account_info(), None, &ctx.accounts.system_program.to_account_info(), 8 + ProgramState::SPACE)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        emit!(AccountMigrated { account: info.key() });
+        Ok(())
+    }
+
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+        let info = ctx.accounts.staking_pool.to_account_info();
+        let now = Clock::get()?.unix_timestamp;
+        let migrated = read_v1::<StakingPoolV1>(&info, StakingPool::DISCRIMINATOR, StakingPoolV1::SPACE, StakingPool::SPACE)?.upgrade(now);
+        resize_account(&info, &ctx.accounts.payer.to_account_info(), None, &ctx.accounts.system_program.to_account_info(), 8 + StakingPool::SPACE)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        emit!(AccountMigrated { account: info.key() });
+        Ok(())
+    }
+
+    pub fn migrate_stake_account(ctx: Context<MigrateStakeAccount>) -> Result<()> {
+        let info = ctx.accounts.stake_account.to_account_info();
+        let migrated = read_v1::<StakeAccountV1>(&info, StakeAccount::DISCRIMINATOR, StakeAccountV1::SPACE, StakeAccount::SPACE)?.upgrade();
+        resize_account(&info, &ctx.accounts.payer.to_account_info(), None, &ctx.accounts.system_program.to_account_info(), 8 + StakeAccount::SPACE)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        emit!(AccountMigrated { account: info.key() });
+        Ok(())
+    }
+
+    // ========================================================================
+    // MIGRATION FROM agent_registry / agent_staking
+    // ========================================================================
+
+    // Recreate a legacy agent under the same seeds (creator + index) in this program.
+    // Delegates, pending transfers and runtime keys are not carried over.
+    pub fn import_agent(ctx: Context<ImportAgent>) -> Result<()> {
+        let legacy = &ctx.accounts.legacy_agent;
+        let agent = &mut ctx.accounts.agent;
+        agent.version = AGENT_REGISTRY_VERSION;
+        agent.creator = legacy.creator;
+        agent.owner = legacy.owner;
+        agent.memory_mode = legacy.memory_mode;
+        agent.memory_hash = legacy.memory_hash;
+        agent.card_hash = legacy.card_hash;
+        agent.flags = legacy.flags;
+        agent.bump = ctx.bumps.agent;
+        agent.agent_index = legacy.agent_index;
+        agent.card_uri = legacy.card_uri.clone();
+        agent.memory_ptr = legacy.memory_ptr.clone();
+
+        // Keep the creator's counter past imported indexes so create_indexed_agent cannot collide
+        if legacy.flags & FLAG_INDEXED != 0 {
+            let counter = ctx.accounts.agent_counter.as_mut().ok_or(PlatformError::MissingAgentCounter)?;
+            let next = u32::from_le_bytes(legacy.agent_index)
+                .checked_add(1)
+                .ok_or(PlatformError::CounterOverflow)?;
+            counter.count = counter.count.max(next);
+        }
+
+        emit!(AgentImported { agent: agent.key(), legacy_agent: legacy.key(), owner: agent.owner });
+        Ok(())
+    }
+
+    // Create the pool for an imported agent and pull the legacy vault into it. Legacy shares
+    // come over 1:1 with the whole vault, so the share price is unchanged; weights and
+    // staker_count are rebuilt by import_stake as positions are carried over. The reward
+    // vault moves too, with the reserve and the stakers' claims; emissions restart at 0.
+    pub fn import_staking_pool(ctx: Context<ImportStakingPool>) -> Result<()> {
+        let pool_key = ctx.accounts.staking_pool.key();
+        init_pool_vault(
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.token_vault,
+            &ctx.accounts.token_mint.to_account_info(),
+            &pool_key,
+            &[b"token_vault", pool_key.as_ref(), &[ctx.bumps.token_vault]],
+            &ctx.accounts.system_program.to_account_info(),
+            &ctx.accounts.token_program.to_account_info(),
+        )?;
+        let has_rewards = ctx.accounts.legacy_pool.reward_vault != Pubkey::default();
+        if has_rewards {
+            let (Some(reward_vault), Some(reward_mint)) = (&ctx.accounts.reward_vault, &ctx.accounts.reward_mint) else {
+                return err!(PlatformError::PoolVaultsRequired);
+            };
+            let (reward_vault_pda, reward_vault_bump) =
+                Pubkey::find_program_address(&[b"reward_vault", pool_key.as_ref()], ctx.program_id);
+            require_keys_eq!(reward_vault.key(), reward_vault_pda, PlatformError::InvalidRewardVault);
+            // Rewards may use another token program than the staked mint
+            let reward_token_program = ctx
+                .accounts
+                .reward_token_program
+                .as_ref()
+                .map_or(ctx.accounts.token_program.to_account_info(), |p| p.to_account_info());
+            init_pool_vault(
+                &ctx.accounts.owner.to_account_info(),
+                reward_vault,
+                &reward_mint.to_account_info(),
+                &pool_key,
+                &[b"reward_vault", pool_key.as_ref(), &[reward_vault_bump]],
+                &ctx.accounts.system_program.to_account_info(),
+                &reward_token_program,
+            )?;
+        }
+
+        agent_staking::cpi::export_pool(CpiContext::new(
+            ctx.accounts.staking_program.to_account_info(),
+            agent_staking::cpi::accounts::ExportPool {
+                agent: ctx.accounts.legacy_agent.to_account_info(),
+                staking_pool: ctx.accounts.legacy_pool.to_account_info(),
+                token_vault: ctx.accounts.legacy_vault.to_account_info(),
+                token_mint: ctx.accounts.token_mint.to_account_info(),
+                destination: ctx.accounts.token_vault.to_account_info(),
+                owner: ctx.accounts.owner.to_account_info(),
+                token_program: ctx.accounts.token_program.to_account_info(),
+                fee_vault: ctx.accounts.legacy_fee_vault.as_ref().map(|a| a.to_account_info()),
+                receipt_mint: ctx.accounts.legacy_receipt_mint.as_ref().map(|a| a.to_account_info()),
+                reward_vault: ctx.accounts.legacy_reward_vault.as_ref().map(|a| a.to_account_info()),
+                reward_mint: ctx.accounts.reward_mint.as_ref().map(|a| a.to_account_info()),
+                reward_destination: ctx.accounts.reward_vault.as_ref().map(|a| a.to_account_info()),
+                reward_token_program: ctx.accounts.reward_token_program.as_ref().map(|a| a.to_account_info()),
+            },
+        ))?;
+        let amount = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let reward_amount = match &ctx.accounts.reward_vault {
+            Some(reward_vault) if has_rewards => token::accessor::amount(reward_vault)?,
+            _ => 0,
+        };
+        // export_pool settled the legacy reward accumulator
+        ctx.accounts.legacy_pool.reload()?;
+
+        let legacy = &ctx.accounts.legacy_pool;
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.agent_pda = ctx.accounts.agent.key();
+        pool.owner = ctx.accounts.owner.key();
+        pool.token_mint = legacy.token_mint;
+        pool.token_vault = ctx.accounts.token_vault.key();
+        pool.min_stake_amount = legacy.min_stake_amount;
+        pool.total_shares = legacy.total_shares;
+        pool.staker_count = 0;
+        pool.created_at = legacy.created_at;
+        pool.flags = legacy.flags & (agent_staking::POOL_FLAG_ACTIVE | agent_staking::POOL_FLAG_DEPRECATED);  // Paused or deprecated state carries over
+        pool.reward_mint = legacy.reward_mint;
+        pool.reward_vault = if has_rewards {
+            ctx.accounts.reward_vault.as_ref().map_or(Pubkey::default(), |v| v.key())
+        } else {
+            Pubkey::default()
+        };
+        pool.reward_per_token_stored = 0;
+        pool.reward_rate = 0;  // Restarted with set_reward_rate once positions are imported
+        pool.reward_reserve = legacy.reward_reserve;
+        pool.last_update_time = Clock::get()?.unix_timestamp;
+        pool.unbonding_period_seconds = legacy.unbonding_period_seconds;
+        pool.total_weighted_stake = 0;
+        pool.early_unlock_penalty_bps = legacy.early_unlock_penalty_bps;
+        pool.slash_authority = legacy.slash_authority;
+        pool.slash_max_bps_per_epoch = legacy.slash_max_bps_per_epoch;
+        pool.slash_timelock_seconds = legacy.slash_timelock_seconds;
+        pool.slash_epoch = 0;
+        pool.slashed_bps_in_epoch = 0;
+        pool.receipt_mint = Pubkey::default();  // export_pool requires every legacy receipt redeemed
+        pool.fee_immediate_lamports = legacy.fee_immediate_lamports;
+        pool.fee_regular_lamports = legacy.fee_regular_lamports;
+        pool.fee_decay_duration_seconds = legacy.fee_decay_duration_seconds;
+        pool.fee_owner_share_bps = legacy.fee_owner_share_bps;
+        pool.fee_mode = legacy.fee_mode;
+        pool.fee_vault = Pubkey::default();  // export_pool requires the legacy fee vault collected
+        pool.owner_token_fees = 0;
+        pool.rewards_owed_total = legacy.rewards_owed_total;  // Unsettled claims are added by import_stake
+        pool.total_unbonding = legacy.total_unbonding;
+        pool.bump = ctx.bumps.staking_pool;
+        emit!(StakingPoolImported { agent_pda: pool.agent_pda, legacy_pool: legacy.key(), amount, reward_amount });
         Ok(())
     }
+
+    // Carry one legacy position over: shares and staked_at (so fee decay is preserved), pending
+    // unstakes (already counted in total_shares and total_unbonding), the lockup and unclaimed
+    // rewards. A position already opened here is merged with it. Permissionless: the legacy
+    // pool is frozen once exported, so the position can only be claimed here, and only once.
+    pub fn import_stake(ctx: Context<ImportStake>) -> Result<()> {
+        let legacy = &ctx.accounts.legacy_stake;
+        let legacy_pool = &ctx.accounts.legacy_pool;
+        let pool = &mut ctx.accounts.staking_pool;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        update_rewards(pool, now)?;
+
+        if stake_acc.staker == Pubkey::default() {
+            init_stake_account(stake_acc, legacy.staker, pool, now, ctx.bumps.stake_account);
+            stake_acc.staked_at = legacy.staked_at;
+            stake_acc.last_updated_at = legacy.last_updated_at;
+        } else {
+            require!(!stake_acc.imported, PlatformError::StakeAlreadyImported);
+            settle_rewards(pool, stake_acc, now)?;
+            // The later start wins, so the import cannot shorten the fee decay of newer stake
+            stake_acc.staked_at = stake_acc.staked_at.max(legacy.staked_at);
+            stake_acc.last_updated_at = now;
+        }
+
+        if stake_acc.shares == 0 && legacy.shares > 0 {
+            pool.staker_count = pool.staker_count.saturating_add(1);
+        }
+        stake_acc.shares = stake_acc.shares.checked_add(legacy.shares).ok_or(PlatformError::MathOverflow)?;
+        for request in legacy.unbonding.iter().filter(|request| request.shares > 0) {
+            let slot = stake_acc
+                .unbonding
+                .iter_mut()
+                .find(|slot| slot.shares == 0)
+                .ok_or(PlatformError::TooManyUnbondingRequests)?;
+            *slot = UnbondingRequest { shares: request.shares, penalty_shares: request.penalty_shares, ends_at: request.ends_at };
+        }
+        // The later lockup covers the merged position
+        if legacy.lock_ends_at > stake_acc.lock_ends_at {
+            stake_acc.lock_tier = legacy.lock_tier;
+            stake_acc.lock_ends_at = legacy.lock_ends_at;
+            stake_acc.lock_penalty_bps = legacy.lock_penalty_bps;
+        }
+
+        // Legacy rewards moved with the reward vault: settled ones are already in
+        // rewards_owed_total, the rest accrued since the position's last settlement (an ended
+        // lockup's boost after lock_ends_at goes back to the reserve)
+        if legacy_pool.reward_vault != Pubkey::default() {
+            let delta = legacy_pool.reward_per_token_stored.saturating_sub(legacy.reward_per_token_paid);
+            let (unsettled, forfeited) = accrued_rewards(
+                legacy.weighted_amount,
+                legacy.shares,
+                (legacy.lock_tier != 0).then_some(legacy.lock_ends_at),
+                legacy.rewards_checkpoint_at,
+                delta,
+                legacy_pool.last_update_time,
+            )?;
+            pool.reward_reserve = pool.reward_reserve.checked_add(forfeited).ok_or(PlatformError::MathOverflow)?;
+            stake_acc.rewards_owed = stake_acc
+                .rewards_owed
+                .checked_add(legacy.rewards_owed)
+                .and_then(|owed| owed.checked_add(unsettled))
+                .ok_or(PlatformError::MathOverflow)?;
+            pool.rewards_owed_total = pool.rewards_owed_total.checked_add(unsettled).ok_or(PlatformError::MathOverflow)?;
+        }
+        stake_acc.imported = true;
+        end_lockup(pool, stake_acc, now)?;
+        reweight(pool, stake_acc)?;
+        emit!(StakeImported { staker: legacy.staker, agent_pda: pool.agent_pda, shares: legacy.shares });
+
+        // Close the legacy position, signed by this pool, so it cannot be imported again
+        let agent_key = ctx.accounts.agent.key();
+        let seeds = &[
+            b"staking_pool",
+            agent_key.as_ref(),
+            &[ctx.accounts.staking_pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        agent_staking::cpi::export_stake(CpiContext::new_with_signer(
+            ctx.accounts.staking_program.to_account_info(),
+            agent_staking::cpi::accounts::ExportStake {
+                agent: ctx.accounts.legacy_agent.to_account_info(),
+                staking_pool: ctx.accounts.legacy_pool.to_account_info(),
+                stake_account: ctx.accounts.legacy_stake.to_account_info(),
+                staker: ctx.accounts.staker.to_account_info(),
+                platform_pool: ctx.accounts.staking_pool.to_account_info(),
+            },
+            signer,
+        ))
+    }
 }
 
 // ============================================================================
@@ -436,12 +1680,12 @@ pub mod agent_platform {
 // Agent Registry Contexts
 
 #[derive(Accounts)]
-#[instruction(creator: Pubkey)]
+#[instruction(creator: Pubkey, params: AgentParams)]
 pub struct CreateAgent<'info> {
     #[account(
         init,
         payer = creator_signer,
-        space = 8 + AGENT_REGISTRY_SPACE,
+        space = 8 + AgentRegistry::space(params.card_uri.len(), params.memory_ptr.as_ref().map_or(0, |p| p.len())),
         seeds = [b"agent", creator.as_ref()],
         bump
     )]
@@ -454,235 +1698,1807 @@ pub struct CreateAgent<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct InitAgentCounter<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + AGENT_COUNTER_SPACE,
+        seeds = [b"agent_counter", creator.key().as_ref()],
+        bump
+    )]
+    pub agent_counter: Account<'info, AgentCounter>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(creator: Pubkey, params: AgentParams)]
+pub struct CreateIndexedAgent<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent_counter", creator.as_ref()],
+        bump = agent_counter.bump
+    )]
+    pub agent_counter: Account<'info, AgentCounter>,
+    #[account(
+        init,
+        payer = creator_signer,
+        space = 8 + AgentRegistry::space(params.card_uri.len(), params.memory_ptr.as_ref().map_or(0, |p| p.len())),
+        seeds = [b"agent", creator.as_ref(), &agent_counter.count.to_le_bytes()],
+        bump
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        constraint = creator_signer.key() == creator @ PlatformError::Unauthorized
+    )]
+    pub creator_signer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct UpdateAgent<'info> {
     #[account(
         mut,
-        seeds = [b"agent", agent.creator.as_ref()],
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
         bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
         constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
     )]
     pub agent: Account<'info, AgentRegistry>,
     pub owner: Signer<'info>,
 }
 
+// transfer_owner: also closes a pending two-step proposal, if any
 #[derive(Accounts)]
-pub struct CloseAgent<'info> {
+pub struct TransferOwner<'info> {
     #[account(
         mut,
-        close = recipient,
-        seeds = [b"agent", agent.creator.as_ref()],
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
         bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
         constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
     )]
     pub agent: Account<'info, AgentRegistry>,
+    /// CHECK: pending_owner PDA of the agent, closed if it holds a proposal
+    #[account(mut, seeds = [b"pending_owner", agent.key().as_ref()], bump)]
+    pub pending_owner: UncheckedAccount<'info>,
+    /// CHECK: proposer of the pending proposal, gets its rent back - checked in the handler
+    #[account(mut)]
+    pub proposer: Option<UncheckedAccount<'info>>,  // Required only when a proposal is pending
     pub owner: Signer<'info>,
-    /// CHECK: recipient receives lamports on close
+}
+
+#[derive(Accounts)]
+pub struct DelegatedUpdate<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"delegate", agent.key().as_ref(), owner.key().as_ref()],
+        bump = delegate.bump
+    )]
+    pub delegate: Option<Account<'info, Delegate>>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetCard<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"delegate", agent.key().as_ref(), owner.key().as_ref()],
+        bump = delegate.bump
+    )]
+    pub delegate: Option<Account<'info, Delegate>>,
     #[account(mut)]
-    pub recipient: UncheckedAccount<'info>,
+    pub owner: Signer<'info>,
+    /// CHECK: agent owner, receives the rent freed when the account shrinks (optional when the owner signs)
+    #[account(mut, address = agent.owner @ PlatformError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
     pub system_program: Program<'info, System>,
 }
 
-// Staking Contexts
+#[derive(Accounts)]
+pub struct SetMemory<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"delegate", agent.key().as_ref(), owner.key().as_ref()],
+        bump = delegate.bump
+    )]
+    pub delegate: Option<Account<'info, Delegate>>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: agent owner, receives the rent freed when the account shrinks (optional when the owner signs)
+    #[account(mut, address = agent.owner @ PlatformError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub system_program: Program<'info, System>,
+}
 
 #[derive(Accounts)]
-pub struct InitProgramState<'info> {
+pub struct CloseAgent<'info> {
     #[account(
-        init,
-        payer = initializer,
-        space = 8 + ProgramState::SPACE,
-        seeds = [b"program_state"],
-        bump
+        mut,
+        close = recipient,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
     )]
-    pub program_state: Account<'info, ProgramState>,
+    pub agent: Account<'info, AgentRegistry>,
+    pub owner: Signer<'info>,
+    /// CHECK: recipient receives lamports on close
     #[account(mut)]
-    pub initializer: Signer<'info>,
-    /// CHECK: treasury system account
-    pub treasury: UncheckedAccount<'info>,
+    pub recipient: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
+    // Optional: close the agent's staking pool in the same instruction
+    #[account(
+        mut,
+        close = recipient,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.total_shares == 0 && staking_pool.total_unbonding == 0 @ PlatformError::PoolNotEmpty,
+        constraint = staking_pool.flags & POOL_FLAG_SLASH_PENDING == 0 @ PlatformError::SlashPending
+    )]
+    pub staking_pool: Option<Account<'info, StakingPool>>,
+    /// CHECK: pool token vault (closed), checked against staking_pool
+    #[account(mut)]
+    pub token_vault: Option<UncheckedAccount<'info>>,
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub owner_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(seeds = [b"program_state"], bump = program_state.bump)]
+    pub program_state: Option<Account<'info, ProgramState>>,  // With treasury_token_account
+    #[account(mut)]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,  // With a non-empty fee vault
+    #[account(mut)]
+    pub reward_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub reward_mint: Option<InterfaceAccount<'info, Mint>>,  // With a non-empty reward vault
+    #[account(mut)]
+    pub owner_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,  // Receives the reward vault balance
+    pub reward_token_program: Option<Interface<'info, TokenInterface>>,  // Defaults to token_program
+    pub token_program: Option<Interface<'info, TokenInterface>>,
 }
 
 #[derive(Accounts)]
-pub struct CreateStakingPool<'info> {
-    // No more CPI validation, agent is in the same program!
+pub struct DisableStaking<'info> {
     #[account(
-        constraint = agent.flags & FLAG_HAS_STAKING != 0 @ PlatformError::StakingNotEnabled,
-        seeds = [b"agent", agent.creator.as_ref()],
-        bump = agent.bump
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    /// CHECK: staking pool PDA of the agent, must hold no data
+    #[account(seeds = [b"staking_pool", agent.key().as_ref()], bump)]
+    pub staking_pool: UncheckedAccount<'info>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeOwner<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
     )]
     pub agent: Account<'info, AgentRegistry>,
     #[account(
         init,
         payer = owner,
-        space = 8 + StakingPool::SPACE,
-        seeds = [b"staking_pool", agent.key().as_ref()],
+        space = 8 + PENDING_OWNER_SPACE,
+        seeds = [b"pending_owner", agent.key().as_ref()],
         bump
     )]
-    pub staking_pool: Account<'info, StakingPool>,
-    /// CHECK: Token vault PDA
+    pub pending_owner: Account<'info, PendingOwner>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOwner<'info> {
     #[account(
         mut,
-        seeds = [b"token_vault", staking_pool.key().as_ref()],
-        bump
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion
     )]
-    pub token_vault: AccountInfo<'info>,
-    /// CHECK: Token mint
-    pub token_mint: AccountInfo<'info>,
+    pub agent: Account<'info, AgentRegistry>,
     #[account(
         mut,
-        constraint = owner.key() == agent.owner @ PlatformError::Unauthorized
+        close = proposer,
+        seeds = [b"pending_owner", agent.key().as_ref()],
+        bump = pending_owner.bump,
+        constraint = pending_owner.pending_owner == new_owner.key() @ PlatformError::Unauthorized
     )]
-    pub owner: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
+    pub pending_owner: Account<'info, PendingOwner>,
+    pub new_owner: Signer<'info>,
+    /// CHECK: proposer gets the proposal rent back
+    #[account(mut, address = pending_owner.proposer)]
+    pub proposer: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct UpdateMinStake<'info> {
+pub struct CancelOwnerTransfer<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
     #[account(
         mut,
-        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
-        bump = staking_pool.bump
+        close = proposer,
+        seeds = [b"pending_owner", agent.key().as_ref()],
+        bump = pending_owner.bump
     )]
-    pub staking_pool: Account<'info, StakingPool>,
+    pub pending_owner: Account<'info, PendingOwner>,
     pub owner: Signer<'info>,
+    /// CHECK: proposer gets the proposal rent back
+    #[account(mut, address = pending_owner.proposer)]
+    pub proposer: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct InitStake<'info> {
+#[instruction(delegate_key: Pubkey)]
+pub struct AddDelegate<'info> {
     #[account(
-        seeds = [b"staking_pool", agent_pda.key().as_ref()],
-        bump = staking_pool.bump
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
     )]
-    pub staking_pool: Account<'info, StakingPool>,
-    /// CHECK: agent pda
-    pub agent_pda: UncheckedAccount<'info>,
+    pub agent: Account<'info, AgentRegistry>,
     #[account(
         init,
-        payer = staker,
-        space = 8 + StakeAccount::SPACE,
-        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        payer = owner,
+        space = 8 + DELEGATE_SPACE,
+        seeds = [b"delegate", agent.key().as_ref(), delegate_key.as_ref()],
         bump
     )]
-    pub stake_account: Account<'info, StakeAccount>,
+    pub delegate: Account<'info, Delegate>,
     #[account(mut)]
-    pub staker: Signer<'info>,
+    pub owner: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Stake<'info> {
+pub struct UpdateDelegate<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
     #[account(
         mut,
-        seeds = [b"staking_pool", agent_pda.key().as_ref()],
-        bump = staking_pool.bump
+        seeds = [b"delegate", agent.key().as_ref(), delegate.delegate.as_ref()],
+        bump = delegate.bump
     )]
-    pub staking_pool: Account<'info, StakingPool>,
-    /// CHECK: agent pda
-    pub agent_pda: UncheckedAccount<'info>,
+    pub delegate: Account<'info, Delegate>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveDelegate<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
     #[account(
         mut,
-        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
-        bump = stake_account.bump,
-        constraint = stake_account.staker == staker.key() @ PlatformError::Unauthorized
+        close = owner,
+        seeds = [b"delegate", agent.key().as_ref(), delegate.delegate.as_ref()],
+        bump = delegate.bump
     )]
-    pub stake_account: Account<'info, StakeAccount>,
-    /// CHECK: Token vault
+    pub delegate: Account<'info, Delegate>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMemorySigned<'info> {
     #[account(
         mut,
-        constraint = token_vault.key() == staking_pool.token_vault @ PlatformError::InvalidVault
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion
     )]
-    pub token_vault: AccountInfo<'info>,
-    /// CHECK: Staker's token account
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"agent_key", agent.key().as_ref()],
+        bump = agent_key.bump
+    )]
+    pub agent_key: Account<'info, AgentKey>,  // Its key must have signed the update
     #[account(mut)]
-    pub staker_token_account: AccountInfo<'info>,
+    pub relayer: Signer<'info>,
+    /// CHECK: instructions sysvar, read to find the Ed25519 verification
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    /// CHECK: agent owner, receives the rent freed when the account shrinks (optional when the owner signs)
+    #[account(mut, address = agent.owner @ PlatformError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BindAgentKey<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + AGENT_KEY_SPACE,
+        seeds = [b"agent_key", agent.key().as_ref()],
+        bump
+    )]
+    pub agent_key: Account<'info, AgentKey>,
     #[account(mut)]
-    pub staker: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+    pub owner: Signer<'info>,
+    pub runtime_key: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawStake<'info> {
+pub struct RotateAgentKey<'info> {
     #[account(
-        seeds = [b"program_state"],
-        bump = program_state.bump
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
     )]
-    pub program_state: Account<'info, ProgramState>,
+    pub agent: Account<'info, AgentRegistry>,
     #[account(
         mut,
-        seeds = [b"staking_pool", agent_pda.key().as_ref()],
-        bump = staking_pool.bump
+        seeds = [b"agent_key", agent.key().as_ref()],
+        bump = agent_key.bump
     )]
-    pub staking_pool: Account<'info, StakingPool>,
-    /// CHECK: agent pda
-    pub agent_pda: UncheckedAccount<'info>,
+    pub agent_key: Account<'info, AgentKey>,
+    pub owner: Signer<'info>,
+    pub new_key: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAgentKey<'info> {
     #[account(
-        mut,
-        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
-        bump = stake_account.bump,
-        constraint = stake_account.staker == staker.key() @ PlatformError::Unauthorized
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ PlatformError::Unauthorized
     )]
-    pub stake_account: Account<'info, StakeAccount>,
-    /// CHECK: Token vault
+    pub agent: Account<'info, AgentRegistry>,
     #[account(
         mut,
-        constraint = token_vault.key() == staking_pool.token_vault @ PlatformError::InvalidVault
+        close = owner,
+        seeds = [b"agent_key", agent.key().as_ref()],
+        bump = agent_key.bump
     )]
-    pub token_vault: AccountInfo<'info>,
-    /// CHECK: Staker's token account
+    pub agent_key: Account<'info, AgentKey>,
     #[account(mut)]
-    pub staker_token_account: AccountInfo<'info>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateAgent<'info> {
+    /// CHECK: raw agent account in a previous layout; discriminator, version and owner are checked in the handler
+    #[account(mut, owner = crate::ID)]
+    pub agent: UncheckedAccount<'info>,
     #[account(mut)]
-    pub staker: Signer<'info>,
-    /// CHECK: treasury account
-    #[account(mut, address = program_state.treasury)]
-    pub treasury: UncheckedAccount<'info>,
-    pub token_program: Program<'info, Token>,
+    pub owner: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
-// ============================================================================
-// ACCOUNT STRUCTS
-// ============================================================================
+// Staking Contexts
 
-#[account]
-pub struct AgentRegistry {
-    pub version: u8,
-    pub creator: Pubkey,
-    pub owner: Pubkey,
-    pub memory_mode: u8,
-    pub memory_ptr_len: u8,
-    pub memory_ptr: [u8; 96],
-    pub memory_hash: [u8; 32],
-    pub card_uri_len: u8,
-    pub card_uri: [u8; 96],
-    pub card_hash: [u8; 32],
-    pub flags: u32,
-    pub bump: u8,
-    pub _padding: [u8; 7],
+#[derive(Accounts)]
+pub struct InitProgramState<'info> {
+    #[account(
+        init,
+        payer = initializer,
+        space = 8 + ProgramState::SPACE,
+        seeds = [b"program_state"],
+        bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(initializer.key()) @ PlatformError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub initializer: Signer<'info>,  // Program upgrade authority
+    /// CHECK: treasury system account
+    pub treasury: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
 }
 
-#[account]
-pub struct ProgramState {
-    pub fee_immediate_lamports: u64,
-    pub fee_regular_lamports: u64,
-    pub fee_max_lamports: u64,
+#[derive(Accounts)]
+pub struct ProposeFeeConfig<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingFeeConfig::SPACE,
+        seeds = [b"pending_fee_config"],
+        bump
+    )]
+    pub pending_fee_config: Account<'info, PendingFeeConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ PlatformError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFeeConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_fee_config"],
+        bump = pending_fee_config.bump
+    )]
+    pub pending_fee_config: Account<'info, PendingFeeConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ PlatformError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelFeeConfig<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_fee_config"],
+        bump = pending_fee_config.bump
+    )]
+    pub pending_fee_config: Account<'info, PendingFeeConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ PlatformError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateStakingPool<'info> {
+    // No more CPI validation, agent is in the same program!
+    #[account(
+        constraint = agent.flags & FLAG_HAS_STAKING != 0 @ PlatformError::StakingNotEnabled,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + StakingPool::SPACE,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Token vault PDA or existing ATA of the pool, validated in the handler
+    #[account(mut)]
+    pub token_vault: AccountInfo<'info>,
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Optional liquid receipt mint (same decimals as the staked token, pool PDA as authority).
+    /// Mints cannot be closed, so a pool recreated at the same address reuses its empty mint
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [b"receipt_mint", staking_pool.key().as_ref()],
+        bump,
+        mint::decimals = token_mint.decimals,
+        mint::authority = staking_pool
+    )]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = owner.key() == agent.owner @ PlatformError::Unauthorized
+    )]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(creator: Pubkey, params: AgentParams)]
+pub struct CreateAgentWithPool<'info> {
+    #[account(
+        init,
+        payer = creator_signer,
+        space = 8 + AgentRegistry::space(params.card_uri.len(), params.memory_ptr.as_ref().map_or(0, |p| p.len())),
+        seeds = [b"agent", creator.as_ref()],
+        bump
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        init,
+        payer = creator_signer,
+        space = 8 + StakingPool::SPACE,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Token vault PDA or existing ATA of the pool, validated in the handler
+    #[account(mut)]
+    pub token_vault: AccountInfo<'info>,
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Optional liquid receipt mint (same decimals as the staked token, pool PDA as authority).
+    /// Mints cannot be closed, so a pool recreated at the same address reuses its empty mint
+    #[account(
+        init_if_needed,
+        payer = creator_signer,
+        seeds = [b"receipt_mint", staking_pool.key().as_ref()],
+        bump,
+        mint::decimals = token_mint.decimals,
+        mint::authority = staking_pool
+    )]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = creator_signer.key() == creator @ PlatformError::Unauthorized
+    )]
+    pub creator_signer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMinStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SyncPoolOwner<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateEarlyUnlockPenalty<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolStatus<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolFees<'info> {
+    #[account(seeds = [b"program_state"], bump = program_state.bump)]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolFeeMode<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitFeeVault<'info> {
+    #[account(mut, seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()], bump = staking_pool.bump)]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(address = staking_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"fee_vault", staking_pool.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = staking_pool,
+        token::token_program = token_program
+    )]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CollectPoolFees<'info> {
+    #[account(seeds = [b"program_state"], bump = program_state.bump)]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(mut, seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()], bump = staking_pool.bump)]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(mut, address = staking_pool.fee_vault @ PlatformError::InvalidVault)]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = staking_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == staking_pool.token_mint @ PlatformError::InvalidTokenAccountMint,
+        constraint = owner_token_account.owner == staking_pool.owner @ PlatformError::InvalidTokenAccountOwner
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = treasury_token_account.mint == staking_pool.token_mint @ PlatformError::InvalidTokenAccountMint,
+        constraint = treasury_token_account.owner == program_state.treasury @ PlatformError::InvalidTokenAccountOwner
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateUnbondingPeriod<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitStake<'info> {
+    #[account(
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = staker,
+        space = 8 + StakeAccount::SPACE,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetLockup<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ PlatformError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    pub staker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireLockup<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", stake_account.staker.as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+}
+
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_ACTIVE != 0 @ PlatformError::PoolPaused
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(constraint = agent_pda.flags & FLAG_ACTIVE != 0 @ PlatformError::AgentInactive)]
+    pub agent_pda: Account<'info, AgentRegistry>,
+    #[account(
+        init_if_needed,
+        payer = staker,
+        space = 8 + StakeAccount::SPACE,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Token vault
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ PlatformError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Staker's token account
+    #[account(
+        mut,
+        constraint = staker_token_account.mint == staking_pool.token_mint @ PlatformError::InvalidTokenAccountMint
+    )]
+    pub staker_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Receipt mint, required when the pool has one
+    #[account(mut, address = staking_pool.receipt_mint @ PlatformError::InvalidReceiptMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, constraint = staker_receipt_account.mint == staking_pool.receipt_mint @ PlatformError::InvalidReceiptMint)]
+    pub staker_receipt_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ PlatformError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Token vault
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ PlatformError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    /// Staked token mint (early unlock penalties are burned)
+    #[account(mut, address = staking_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Staker's token account
+    #[account(
+        mut,
+        constraint = staker_token_account.mint == staking_pool.token_mint @ PlatformError::InvalidTokenAccountMint,
+        constraint = staker_token_account.owner == staker.key() @ PlatformError::InvalidTokenAccountOwner
+    )]
+    pub staker_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Pool fee vault, required when a token-mode fee is charged
+    #[account(mut, address = staking_pool.fee_vault @ PlatformError::InvalidVault)]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Receipt mint, required when the pool has one
+    #[account(mut, address = staking_pool.receipt_mint @ PlatformError::InvalidReceiptMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, constraint = staker_receipt_account.mint == staking_pool.receipt_mint @ PlatformError::InvalidReceiptMint)]
+    pub staker_receipt_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    /// CHECK: treasury account
+    #[account(mut, address = program_state.treasury)]
+    pub treasury: UncheckedAccount<'info>,
+    /// CHECK: pool owner, required when the pool gives it a fee share
+    #[account(mut, address = staking_pool.owner @ PlatformError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeRequest<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ PlatformError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Token vault (prices the shares, pays token-mode fees)
+    #[account(mut, constraint = token_vault.key() == staking_pool.token_vault @ PlatformError::InvalidVault)]
+    pub token_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Pool fee vault, required when a token-mode fee is charged
+    #[account(mut, address = staking_pool.fee_vault @ PlatformError::InvalidVault)]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Receipt mint, required when the pool has one
+    #[account(mut, address = staking_pool.receipt_mint @ PlatformError::InvalidReceiptMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, constraint = staker_receipt_account.mint == staking_pool.receipt_mint @ PlatformError::InvalidReceiptMint)]
+    pub staker_receipt_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    /// CHECK: treasury account
+    #[account(mut, address = program_state.treasury)]
+    pub treasury: UncheckedAccount<'info>,
+    /// CHECK: pool owner, required when the pool gives it a fee share
+    #[account(mut, address = staking_pool.owner @ PlatformError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteUnstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ PlatformError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Token vault
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ PlatformError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    /// Staked token mint (early unlock penalties are burned)
+    #[account(mut, address = staking_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Staker's token account
+    #[account(
+        mut,
+        constraint = staker_token_account.mint == staking_pool.token_mint @ PlatformError::InvalidTokenAccountMint,
+        constraint = staker_token_account.owner == staker.key() @ PlatformError::InvalidTokenAccountOwner
+    )]
+    pub staker_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub staker: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseStakeAccount<'info> {
+    #[account(
+        mut,
+        close = staker,
+        seeds = [b"stake_account", staker.key().as_ref(), stake_account.agent_pda.as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ PlatformError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseStakingPool<'info> {
+    #[account(seeds = [b"program_state"], bump = program_state.bump)]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized,
+        constraint = staking_pool.total_shares == 0 && staking_pool.total_unbonding == 0 @ PlatformError::PoolNotEmpty,
+        constraint = staking_pool.flags & POOL_FLAG_SLASH_PENDING == 0 @ PlatformError::SlashPending
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: token vault (closed)
+    #[account(mut, constraint = token_vault.key() == staking_pool.token_vault @ PlatformError::InvalidVault)]
+    pub token_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == staking_pool.token_mint @ PlatformError::InvalidTokenAccountMint,
+        constraint = owner_token_account.owner == owner.key() @ PlatformError::InvalidTokenAccountOwner
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = staking_pool.fee_vault @ PlatformError::InvalidVault)]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = treasury_token_account.mint == staking_pool.token_mint @ PlatformError::InvalidTokenAccountMint,
+        constraint = treasury_token_account.owner == program_state.treasury @ PlatformError::InvalidTokenAccountOwner
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,  // With a non-empty fee vault
+    #[account(mut, address = staking_pool.reward_vault @ PlatformError::InvalidRewardVault)]
+    pub reward_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub reward_mint: Option<InterfaceAccount<'info, Mint>>,  // With a non-empty reward vault
+    #[account(mut)]
+    pub owner_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,  // Receives the reward vault balance
+    pub reward_token_program: Option<Interface<'info, TokenInterface>>,  // Defaults to token_program
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct InitRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [b"reward_vault", staking_pool.key().as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = staking_pool
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawRewardReserve<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized,
+        constraint = staking_pool.reward_vault != Pubkey::default() @ PlatformError::RewardsNotInitialized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Reward vault - validated manually
+    #[account(
+        mut,
+        constraint = reward_vault.key() == staking_pool.reward_vault @ PlatformError::InvalidRewardVault
+    )]
+    pub reward_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.reward_mint @ PlatformError::InvalidTokenMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = owner_reward_account.mint == staking_pool.reward_mint @ PlatformError::InvalidTokenAccountMint,
+        constraint = owner_reward_account.owner == owner.key() @ PlatformError::InvalidTokenAccountOwner
+    )]
+    pub owner_reward_account: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardRate<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ PlatformError::Unauthorized,
+        constraint = staking_pool.reward_vault != Pubkey::default() @ PlatformError::RewardsNotInitialized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.reward_vault != Pubkey::default() @ PlatformError::RewardsNotInitialized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Reward vault
+    #[account(
+        mut,
+        constraint = reward_vault.key() == staking_pool.reward_vault @ PlatformError::InvalidRewardVault
+    )]
+    pub reward_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.reward_mint @ PlatformError::InvalidTokenMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    /// Funder's token account (source)
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == staking_pool.reward_mint @ PlatformError::InvalidTokenAccountMint
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub funder: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.reward_vault != Pubkey::default() @ PlatformError::RewardsNotInitialized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ PlatformError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Reward vault
+    #[account(
+        mut,
+        constraint = reward_vault.key() == staking_pool.reward_vault @ PlatformError::InvalidRewardVault
+    )]
+    pub reward_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.reward_mint @ PlatformError::InvalidTokenMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    /// Staker's reward token account (destination)
+    #[account(
+        mut,
+        constraint = staker_reward_account.mint == staking_pool.reward_mint @ PlatformError::InvalidTokenAccountMint,
+        constraint = staker_reward_account.owner == staker.key() @ PlatformError::InvalidTokenAccountOwner
+    )]
+    pub staker_reward_account: InterfaceAccount<'info, TokenAccount>,
+    pub staker: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeSlashingConfig<'info> {
+    #[account(
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingSlashingConfig::SPACE,
+        seeds = [b"pending_slashing_config", staking_pool.key().as_ref()],
+        bump
+    )]
+    pub pending_slashing_config: Account<'info, PendingSlashingConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ PlatformError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureSlashing<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_SLASH_PENDING == 0 @ PlatformError::SlashPending
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_slashing_config", staking_pool.key().as_ref()],
+        bump = pending_slashing_config.bump
+    )]
+    pub pending_slashing_config: Account<'info, PendingSlashingConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ PlatformError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelSlashingConfig<'info> {
+    #[account(
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_slashing_config", staking_pool.key().as_ref()],
+        bump = pending_slashing_config.bump
+    )]
+    pub pending_slashing_config: Account<'info, PendingSlashingConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ PlatformError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeSlash<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.slash_authority != Pubkey::default() @ PlatformError::SlashingDisabled,
+        constraint = staking_pool.slash_authority == authority.key() @ PlatformError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingSlash::SPACE,
+        seeds = [b"pending_slash", staking_pool.key().as_ref()],
+        bump
+    )]
+    pub pending_slash: Account<'info, PendingSlash>,
+    #[account(constraint = destination.mint == staking_pool.token_mint @ PlatformError::InvalidSlashDestination)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelSlash<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_slash", staking_pool.key().as_ref()],
+        bump = pending_slash.bump,
+        constraint = pending_slash.authority == authority.key() @ PlatformError::Unauthorized
+    )]
+    pub pending_slash: Account<'info, PendingSlash>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteSlash<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_slash", staking_pool.key().as_ref()],
+        bump = pending_slash.bump,
+        constraint = pending_slash.authority == authority.key() @ PlatformError::Unauthorized
+    )]
+    pub pending_slash: Account<'info, PendingSlash>,
+    /// CHECK: Token vault
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ PlatformError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Destination chosen at propose_slash
+    #[account(mut, address = pending_slash.destination @ PlatformError::InvalidSlashDestination)]
+    pub destination: AccountInfo<'info>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateProgramState<'info> {
+    /// CHECK: raw program state in the v1 layout; discriminator and size are checked in the handler
+    #[account(mut, owner = crate::ID, seeds = [b"program_state"], bump)]
+    pub program_state: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    /// CHECK: raw staking pool in the v1 layout; discriminator and size are checked in the handler
+    #[account(mut, owner = crate::ID)]
+    pub staking_pool: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateStakeAccount<'info> {
+    /// CHECK: raw stake account in the v1 layout; discriminator and size are checked in the handler
+    #[account(mut, owner = crate::ID)]
+    pub stake_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// Migration Contexts
+
+#[derive(Accounts)]
+pub struct ImportAgent<'info> {
+    #[account(
+        seeds = [b"agent", legacy_agent.creator.as_ref(), legacy_agent.index_seed()],
+        bump = legacy_agent.bump,
+        seeds::program = agent_registry::ID,
+        constraint = legacy_agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = legacy_agent.owner == owner.key() @ PlatformError::Unauthorized
+    )]
+    pub legacy_agent: Account<'info, LegacyAgent>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + AgentRegistry::space(legacy_agent.card_uri.len(), legacy_agent.memory_ptr.len()),
+        seeds = [b"agent", legacy_agent.creator.as_ref(), legacy_agent.index_seed()],
+        bump
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"agent_counter", legacy_agent.creator.as_ref()],
+        bump = agent_counter.bump
+    )]
+    pub agent_counter: Option<Account<'info, AgentCounter>>,  // Required for indexed agents
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ImportStakingPool<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ PlatformError::UnsupportedVersion,
+        constraint = agent.flags & FLAG_HAS_STAKING != 0 @ PlatformError::StakingNotEnabled
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = legacy_agent.bump,
+        seeds::program = agent_registry::ID
+    )]
+    pub legacy_agent: Account<'info, LegacyAgent>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", legacy_agent.key().as_ref()],
+        bump = legacy_pool.bump,
+        seeds::program = agent_staking::ID
+    )]
+    pub legacy_pool: Account<'info, LegacyStakingPool>,
+    /// CHECK: Legacy token vault - validated by agent_staking::export_pool
+    #[account(mut)]
+    pub legacy_vault: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + StakingPool::SPACE,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Token vault PDA
+    #[account(
+        mut,
+        seeds = [b"token_vault", staking_pool.key().as_ref()],
+        bump
+    )]
+    pub token_vault: AccountInfo<'info>,
+    /// Token mint of the legacy pool
+    #[account(address = legacy_pool.token_mint @ PlatformError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = owner.key() == agent.owner @ PlatformError::Unauthorized
+    )]
+    pub owner: Signer<'info>,
+    pub staking_program: Program<'info, agent_staking::program::AgentStaking>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: Legacy fee vault, required when the legacy pool has one - validated by agent_staking::export_pool
+    pub legacy_fee_vault: Option<AccountInfo<'info>>,
+    /// CHECK: Legacy receipt mint, required in receipt mode - validated by agent_staking::export_pool
+    pub legacy_receipt_mint: Option<AccountInfo<'info>>,
+    /// CHECK: Legacy reward vault, required when the legacy pool has rewards - validated by agent_staking::export_pool
+    #[account(mut)]
+    pub legacy_reward_vault: Option<AccountInfo<'info>>,
+    #[account(address = legacy_pool.reward_mint @ PlatformError::InvalidTokenMint)]
+    pub reward_mint: Option<InterfaceAccount<'info, Mint>>,
+    /// CHECK: Reward vault PDA, created in the handler when the legacy pool has rewards
+    #[account(mut)]
+    pub reward_vault: Option<AccountInfo<'info>>,
+    /// Token program of the reward vault, when it differs from token_program
+    pub reward_token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct ImportStake<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = legacy_agent.bump,
+        seeds::program = agent_registry::ID
+    )]
+    pub legacy_agent: Account<'info, LegacyAgent>,
+    #[account(
+        seeds = [b"staking_pool", legacy_agent.key().as_ref()],
+        bump = legacy_pool.bump,
+        seeds::program = agent_staking::ID,
+        constraint = legacy_pool.flags & agent_staking::POOL_FLAG_MIGRATED != 0 @ PlatformError::PoolNotMigrated
+    )]
+    pub legacy_pool: Account<'info, LegacyStakingPool>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), legacy_agent.key().as_ref()],
+        bump = legacy_stake.bump,
+        seeds::program = agent_staking::ID
+    )]
+    pub legacy_stake: Account<'info, LegacyStakeAccount>,  // Closed by agent_staking::export_stake
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + StakeAccount::SPACE,
+        seeds = [b"stake_account", staker.key().as_ref(), agent.key().as_ref()],
+        bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,  // Merged with the legacy position if it exists
+    /// CHECK: owner of the position, receives the rent of the legacy position
+    #[account(mut)]
+    pub staker: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub staking_program: Program<'info, agent_staking::program::AgentStaking>,
+    pub system_program: Program<'info, System>,
+}
+
+// ============================================================================
+// ACCOUNT STRUCTS
+// ============================================================================
+
+#[account]
+pub struct AgentRegistry {
+    pub version: u8,
+    pub creator: Pubkey,
+    pub owner: Pubkey,
+    pub memory_mode: u8,
+    pub memory_hash: [u8; 32],
+    pub card_hash: [u8; 32],
+    pub flags: u32,
+    pub bump: u8,
+    pub agent_index: [u8; 4],
+    pub card_uri: Vec<u8>,
+    pub memory_ptr: Vec<u8>,
+}
+
+impl AgentRegistry {
+    pub fn space(card_uri_len: usize, memory_ptr_len: usize) -> usize {
+        AGENT_REGISTRY_BASE_SPACE_V2 + card_uri_len + memory_ptr_len
+    }
+
+    // Extra PDA seed: index for FLAG_INDEXED agents, empty (same address as ["agent", creator]) otherwise
+    pub fn index_seed(&self) -> &[u8] {
+        if self.flags & FLAG_INDEXED != 0 {
+            &self.agent_index
+        } else {
+            &[]
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct AgentRegistryV1 {
+    pub version: u8,
+    pub creator: Pubkey,
+    pub owner: Pubkey,
+    pub memory_mode: u8,
+    pub memory_ptr_len: u8,
+    pub memory_ptr: [u8; 96],
+    pub memory_hash: [u8; 32],
+    pub card_uri_len: u8,
+    pub card_uri: [u8; 96],
+    pub card_hash: [u8; 32],
+    pub flags: u32,
+    pub bump: u8,
+    pub agent_index: [u8; 4],
+    pub _padding: [u8; 3],
+}
+
+impl AgentRegistryV1 {
+    pub fn upgrade(self) -> AgentRegistry {
+        AgentRegistry {
+            version: AGENT_REGISTRY_VERSION,
+            creator: self.creator,
+            owner: self.owner,
+            memory_mode: self.memory_mode,
+            memory_hash: self.memory_hash,
+            card_hash: self.card_hash,
+            flags: self.flags,
+            bump: self.bump,
+            agent_index: self.agent_index,
+            card_uri: self.card_uri[..(self.card_uri_len as usize).min(96)].to_vec(),
+            memory_ptr: self.memory_ptr[..(self.memory_ptr_len as usize).min(96)].to_vec(),
+        }
+    }
+}
+
+#[account]
+pub struct AgentCounter {
+    pub creator: Pubkey,
+    pub count: u32,
+    pub bump: u8,
+}
+
+#[account]
+pub struct PendingOwner {
+    pub agent: Pubkey,
+    pub proposer: Pubkey,
+    pub pending_owner: Pubkey,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+#[account]
+pub struct Delegate {
+    pub agent: Pubkey,
+    pub delegate: Pubkey,
+    pub granted_by: Pubkey,
+    pub permissions: u8,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+#[account]
+pub struct AgentKey {
+    pub agent: Pubkey,
+    pub key: Pubkey,
+    pub bound_at: i64,
+    pub nonce: u64,
+    pub bump: u8,
+}
+
+#[account]
+pub struct ProgramState {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub treasury: Pubkey,
+    pub bump: u8,
+    pub fee_mode: u8,
+    pub fee_immediate_bps: u16,  // Token mode curve
+    pub fee_regular_bps: u16,
+}
+
+impl ProgramState {
+    pub const SPACE: usize = 8 + 8 + 8 + 4 + 32 + 1 + 1 + 2 + 2;
+    pub const DEFAULT_IMMEDIATE_FEE: u64 = 100_000_000;
+    pub const DEFAULT_REGULAR_FEE: u64 = 1_000_000;
+    pub const DEFAULT_MAX_FEE: u64 = 100_000_000;
+    pub const DEFAULT_DECAY_DURATION: u32 = 86_400;
+    pub const DEFAULT_IMMEDIATE_FEE_BPS: u16 = 500;
+    pub const DEFAULT_REGULAR_FEE_BPS: u16 = 10;
+}
+
+#[account]
+pub struct PendingFeeConfig {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub proposed_at: i64,
+    pub execute_after: i64,
+    pub bump: u8,
+    pub fee_mode: u8,
+    pub fee_immediate_bps: u16,
+    pub fee_regular_bps: u16,
+}
+
+impl PendingFeeConfig {
+    pub const SPACE: usize = 8 + 8 + 8 + 4 + 8 + 8 + 1 + 1 + 2 + 2;
+}
+
+#[account]
+pub struct StakingPool {
+    pub agent_pda: Pubkey,
+    pub owner: Pubkey,
+    pub token_mint: Pubkey,
+    pub token_vault: Pubkey,
+    pub min_stake_amount: u64,
+    pub total_shares: u64,  // Active shares, redeemable pro-rata against the vault with total_unbonding
+    pub staker_count: u32,
+    pub created_at: i64,
+    pub flags: u8,
+    pub bump: u8,
+    pub reward_mint: Pubkey,
+    pub reward_vault: Pubkey,
+    pub reward_per_token_stored: u128,
+    pub reward_rate: u64,
+    pub reward_reserve: u64,
+    pub last_update_time: i64,
+    pub unbonding_period_seconds: u32,
+    pub total_weighted_stake: u64,
+    pub early_unlock_penalty_bps: u16,
+    pub slash_authority: Pubkey,
+    pub slash_max_bps_per_epoch: u16,
+    pub slash_timelock_seconds: u32,
+    pub slash_epoch: u64,
+    pub slashed_bps_in_epoch: u16,
+    pub receipt_mint: Pubkey,  // Pubkey::default() = no receipts
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_decay_duration_seconds: u32,  // 0 = ProgramState curve
+    pub fee_owner_share_bps: u16,
+    pub fee_mode: u8,  // FEE_MODE_DEFAULT = ProgramState.fee_mode
+    pub fee_vault: Pubkey,
+    pub owner_token_fees: u64,  // Owner's share of the fee vault
+    pub rewards_owed_total: u64,  // Sum of StakeAccount.rewards_owed: settled, not claimed yet
+    pub total_unbonding: u64,  // Shares queued by unstake_request, out of total_shares
+}
+
+impl StakingPool {
+    pub const SPACE: usize = 32 + 32 + 32 + 32 + 8 + 8 + 4 + 8 + 1 + 1 + 32 + 32 + 16 + 8 + 8 + 8 + 4 + 8 + 2
+        + 32 + 2 + 4 + 8 + 2 + 32 + 8 + 8 + 4 + 2 + 1 + 32 + 8 + 8 + 8;
+    pub const MAX_UNBONDING_PERIOD: u32 = 30 * 86_400;
+    pub const MAX_FEE_DECAY_DURATION: u32 = 30 * 86_400;
+}
+
+#[account]
+pub struct StakeAccount {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub shares: u64,
+    pub staked_at: i64,
+    pub last_updated_at: i64,
+    pub bump: u8,
+    pub reward_per_token_paid: u128,
+    pub rewards_owed: u64,
+    pub unbonding: [UnbondingRequest; MAX_UNBONDING_REQUESTS],  // Pending unstakes, in total_unbonding
+    pub lock_tier: u8,
+    pub lock_ends_at: i64,
+    pub lock_penalty_bps: u16,
+    pub weighted_amount: u64,   // shares scaled by the lockup tier weight
+    pub rewards_checkpoint_at: i64,  // When reward_per_token_paid was taken
+    pub imported: bool,         // Carries a legacy position (import_stake)
+}
+
+impl StakeAccount {
+    pub const SPACE: usize = 32 + 32 + 8 + 8 + 8 + 1 + 16 + 8
+        + UnbondingRequest::SPACE * MAX_UNBONDING_REQUESTS + 1 + 8 + 2 + 8 + 8 + 1;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct UnbondingRequest {
+    pub shares: u64,  // 0 = free slot
+    pub penalty_shares: u64,
+    pub ends_at: i64,
+}
+
+impl UnbondingRequest {
+    pub const SPACE: usize = 8 + 8 + 8;
+}
+
+#[account]
+pub struct PendingSlash {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub destination: Pubkey,
+    pub bps: u16,
+    pub reason_hash: [u8; 32],
+    pub proposed_at: i64,
+    pub execute_after: i64,
+    pub bump: u8,
+}
+
+impl PendingSlash {
+    pub const SPACE: usize = 32 + 32 + 32 + 2 + 32 + 8 + 8 + 1;
+}
+
+#[account]
+pub struct PendingSlashingConfig {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub max_bps_per_epoch: u16,
+    pub timelock_seconds: u32,
+    pub proposed_at: i64,
+    pub execute_after: i64,
+    pub bump: u8,
+}
+
+impl PendingSlashingConfig {
+    pub const SPACE: usize = 32 + 32 + 2 + 4 + 8 + 8 + 1;
+}
+
+// Frozen v1 layouts, only read by the migrate_* instructions
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct ProgramStateV1 {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
     pub decay_duration_seconds: u32,
     pub treasury: Pubkey,
     pub bump: u8,
 }
 
-impl ProgramState {
+impl ProgramStateV1 {
     pub const SPACE: usize = 8 + 8 + 8 + 4 + 32 + 1;
-    pub const DEFAULT_IMMEDIATE_FEE: u64 = 100_000_000;
-    pub const DEFAULT_REGULAR_FEE: u64 = 1_000_000;
-    pub const DEFAULT_MAX_FEE: u64 = 100_000_000;
-    pub const DEFAULT_DECAY_DURATION: u32 = 86_400;
+
+    pub fn upgrade(self) -> ProgramState {
+        ProgramState {
+            fee_immediate_lamports: self.fee_immediate_lamports,
+            fee_regular_lamports: self.fee_regular_lamports,
+            fee_max_lamports: self.fee_max_lamports,
+            decay_duration_seconds: self.decay_duration_seconds,
+            treasury: self.treasury,
+            bump: self.bump,
+            fee_mode: FEE_MODE_SOL,
+            fee_immediate_bps: ProgramState::DEFAULT_IMMEDIATE_FEE_BPS,
+            fee_regular_bps: ProgramState::DEFAULT_REGULAR_FEE_BPS,
+        }
+    }
 }
 
-#[account]
-pub struct StakingPool {
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct StakingPoolV1 {
     pub agent_pda: Pubkey,
     pub owner: Pubkey,
     pub token_mint: Pubkey,
@@ -695,102 +3511,531 @@ pub struct StakingPool {
     pub bump: u8,
 }
 
-impl StakingPool {
-    pub const SPACE: usize = 32 + 32 + 32 + 32 + 8 + 8 + 4 + 8 + 1 + 1;
+impl StakingPoolV1 {
+    pub const SPACE: usize = 32 + 32 + 32 + 32 + 8 + 8 + 4 + 8 + 1 + 1;
+
+    // One share per staked token, all unlocked at 1x weight
+    pub fn upgrade(self, now: i64) -> StakingPool {
+        StakingPool {
+            agent_pda: self.agent_pda,
+            owner: self.owner,
+            token_mint: self.token_mint,
+            token_vault: self.token_vault,
+            min_stake_amount: self.min_stake_amount,
+            total_shares: self.total_staked,
+            staker_count: self.staker_count,
+            created_at: self.created_at,
+            flags: self.flags,
+            bump: self.bump,
+            reward_mint: Pubkey::default(),
+            reward_vault: Pubkey::default(),
+            reward_per_token_stored: 0,
+            reward_rate: 0,
+            reward_reserve: 0,
+            last_update_time: now,
+            unbonding_period_seconds: 0,
+            total_weighted_stake: self.total_staked,
+            early_unlock_penalty_bps: 0,
+            slash_authority: Pubkey::default(),
+            slash_max_bps_per_epoch: 0,
+            slash_timelock_seconds: 0,
+            slash_epoch: 0,
+            slashed_bps_in_epoch: 0,
+            receipt_mint: Pubkey::default(),
+            fee_immediate_lamports: 0,
+            fee_regular_lamports: 0,
+            fee_decay_duration_seconds: 0,
+            fee_owner_share_bps: 0,
+            fee_mode: FEE_MODE_DEFAULT,
+            fee_vault: Pubkey::default(),
+            owner_token_fees: 0,
+            rewards_owed_total: 0,
+            total_unbonding: 0,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct StakeAccountV1 {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub staked_amount: u64,
+    pub staked_at: i64,
+    pub last_updated_at: i64,
+    pub bump: u8,
+}
+
+impl StakeAccountV1 {
+    pub const SPACE: usize = 32 + 32 + 8 + 8 + 8 + 1;
+
+    pub fn upgrade(self) -> StakeAccount {
+        StakeAccount {
+            staker: self.staker,
+            agent_pda: self.agent_pda,
+            shares: self.staked_amount,
+            staked_at: self.staked_at,
+            last_updated_at: self.last_updated_at,
+            bump: self.bump,
+            reward_per_token_paid: 0,  // In total_weighted_stake since migrate_pool
+            rewards_owed: 0,
+            unbonding: [UnbondingRequest::default(); MAX_UNBONDING_REQUESTS],
+            lock_tier: 0,
+            lock_ends_at: 0,
+            lock_penalty_bps: 0,
+            weighted_amount: self.staked_amount,
+            rewards_checkpoint_at: 0,
+            imported: false,
+        }
+    }
+}
+
+// Read-only views of agent_registry / agent_staking accounts used by the import
+// instructions. Wrapped so the foreign types (same names and discriminators as ours)
+// stay out of this program's IDL.
+macro_rules! legacy_account {
+    ($name:ident, $inner:ty, $program:path) => {
+        #[derive(Clone)]
+        pub struct $name($inner);
+
+        impl AccountDeserialize for $name {
+            fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+                <$inner>::try_deserialize_unchecked(buf).map($name)
+            }
+
+            fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+                <$inner>::try_deserialize(buf).map($name)
+            }
+        }
+
+        impl AccountSerialize for $name {}
+
+        impl Owner for $name {
+            fn owner() -> Pubkey {
+                $program
+            }
+        }
+
+        impl Discriminator for $name {
+            const DISCRIMINATOR: &'static [u8] = <$inner>::DISCRIMINATOR;
+        }
+
+        #[cfg(feature = "idl-build")]
+        impl anchor_lang::IdlBuild for $name {}
+
+        impl core::ops::Deref for $name {
+            type Target = $inner;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+    };
+}
+
+legacy_account!(LegacyAgent, agent_registry::AgentRegistry, agent_registry::ID);
+legacy_account!(LegacyStakingPool, agent_staking::StakingPool, agent_staking::ID);
+legacy_account!(LegacyStakeAccount, agent_staking::StakeAccount, agent_staking::ID);
+
+// ============================================================================
+// EVENTS
+// ============================================================================
+
+// Agent Events
+#[event]
+pub struct AgentCreated {
+    pub creator: Pubkey,
+    pub owner: Pubkey,
+}
+
+#[event]
+pub struct IndexedAgentCreated {
+    pub agent: Pubkey,
+    pub creator: Pubkey,
+    pub owner: Pubkey,
+    pub index: u32,
+}
+
+#[event]
+pub struct CardSet {
+    pub creator: Pubkey,
+    pub card_uri: String,
+    pub card_hash: [u8; 32],
+}
+
+#[event]
+pub struct MemoryUpdated {
+    pub creator: Pubkey,
+    pub mode: u8,
+    pub ptr_preview: String,
+    pub hash: [u8; 32],
+}
+
+#[event]
+pub struct MemoryLocked {
+    pub creator: Pubkey,
+}
+
+#[event]
+pub struct AgentActiveSet {
+    pub creator: Pubkey,
+    pub is_active: bool,
+}
+
+#[event]
+pub struct AgentClosed {
+    pub creator: Pubkey,
+}
+
+#[event]
+pub struct StakingDisabled {
+    pub creator: Pubkey,
+}
+
+#[event]
+pub struct OwnerTransferred {
+    pub creator: Pubkey,
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+#[event]
+pub struct OwnerTransferProposed {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct OwnerTransferAccepted {
+    pub agent: Pubkey,
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+#[event]
+pub struct OwnerTransferCancelled {
+    pub agent: Pubkey,
+    pub pending_owner: Pubkey,
+}
+
+#[event]
+pub struct DelegateSet {
+    pub agent: Pubkey,
+    pub delegate: Pubkey,
+    pub permissions: u8,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct DelegateRemoved {
+    pub agent: Pubkey,
+    pub delegate: Pubkey,
+}
+
+#[event]
+pub struct SignedMemoryUpdate {
+    pub agent: Pubkey,
+    pub signer: Pubkey,
+    pub nonce: u64,
+}
+
+#[event]
+pub struct AgentKeyBound {
+    pub agent: Pubkey,
+    pub key: Pubkey,
+}
+
+#[event]
+pub struct AgentKeyRotated {
+    pub agent: Pubkey,
+    pub old_key: Pubkey,
+    pub new_key: Pubkey,
+}
+
+#[event]
+pub struct AgentKeyRevoked {
+    pub agent: Pubkey,
+    pub key: Pubkey,
+}
+
+#[event]
+pub struct AgentMigrated {
+    pub agent: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+}
+
+// Staking Events
+#[event]
+pub struct FeeConfigProposed {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub fee_mode: u8,
+    pub fee_immediate_bps: u16,
+    pub fee_regular_bps: u16,
+    pub execute_after: i64,
+}
+
+#[event]
+pub struct FeeConfigUpdated {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub fee_mode: u8,
+    pub fee_immediate_bps: u16,
+    pub fee_regular_bps: u16,
+}
+
+#[event]
+pub struct FeeConfigCancelled {
+    pub execute_after: i64,
+}
+
+#[event]
+pub struct PoolCreated {
+    pub agent_pda: Pubkey,
+    pub owner: Pubkey,
+    pub min_stake_amount: u64,
+    pub receipt_mint: Pubkey,
+}
+
+#[event]
+pub struct PoolOwnerSynced {
+    pub agent_pda: Pubkey,
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+#[event]
+pub struct MinStakeUpdated {
+    pub agent_pda: Pubkey,
+    pub old_amount: u64,
+    pub new_amount: u64,
+}
+
+#[event]
+pub struct Staked {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub total: u64,  // Shares held by the position
+}
+
+#[event]
+pub struct Withdrawn {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub fee: u64,
+    pub owner_fee: u64,
+    pub token_fee: u64,
+    pub penalty: u64,
+}
+
+#[event]
+pub struct UnbondingPeriodUpdated {
+    pub agent_pda: Pubkey,
+    pub old_period_seconds: u32,
+    pub new_period_seconds: u32,
+}
+
+#[event]
+pub struct UnstakeRequested {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub shares: u64,
+    pub fee: u64,
+    pub owner_fee: u64,
+    pub token_fee: u64,
+    pub penalty_shares: u64,
+    pub unlock_at: i64,
+}
+
+#[event]
+pub struct UnstakeCompleted {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub shares: u64,
+    pub amount: u64,
+    pub penalty: u64,
+}
+
+#[event]
+pub struct EarlyUnlockPenaltyUpdated {
+    pub agent_pda: Pubkey,
+    pub penalty_bps: u16,
+}
+
+#[event]
+pub struct PoolPausedSet {
+    pub agent_pda: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct PoolDeprecated {
+    pub agent_pda: Pubkey,
+}
+
+#[event]
+pub struct PoolFeesUpdated {
+    pub agent_pda: Pubkey,
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub owner_share_bps: u16,
+}
+
+#[event]
+pub struct PoolFeeModeUpdated {
+    pub agent_pda: Pubkey,
+    pub fee_mode: u8,
+}
+
+#[event]
+pub struct PoolFeesCollected {
+    pub agent_pda: Pubkey,
+    pub owner_amount: u64,
+    pub treasury_amount: u64,
+}
+
+#[event]
+pub struct LockupSet {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub tier: u8,
+    pub lock_ends_at: i64,
+    pub weighted_amount: u64,
 }
 
-#[account]
-pub struct StakeAccount {
+#[event]
+pub struct LockupExpired {
     pub staker: Pubkey,
     pub agent_pda: Pubkey,
-    pub staked_amount: u64,
-    pub staked_at: i64,
-    pub last_updated_at: i64,
-    pub bump: u8,
+    pub weighted_amount: u64,
 }
 
-impl StakeAccount {
-    pub const SPACE: usize = 32 + 32 + 8 + 8 + 8 + 1;
+#[event]
+pub struct RewardRateUpdated {
+    pub agent_pda: Pubkey,
+    pub reward_rate: u64,
 }
 
-// ============================================================================
-// EVENTS
-// ============================================================================
-
-// Agent Events
 #[event]
-pub struct AgentCreated {
-    pub creator: Pubkey,
-    pub owner: Pubkey,
+pub struct RewardReserveWithdrawn {
+    pub agent_pda: Pubkey,
+    pub amount: u64,
+    pub reward_reserve: u64,  // Left after the withdrawal
 }
 
 #[event]
-pub struct CardSet {
-    pub creator: Pubkey,
-    pub card_uri: String,
-    pub card_hash: [u8; 32],
+pub struct RewardsFunded {
+    pub agent_pda: Pubkey,
+    pub funder: Pubkey,
+    pub amount: u64,
 }
 
 #[event]
-pub struct MemoryUpdated {
-    pub creator: Pubkey,
-    pub mode: u8,
-    pub ptr_preview: String,
-    pub hash: [u8; 32],
+pub struct RewardsClaimed {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub amount: u64,
 }
 
 #[event]
-pub struct MemoryLocked {
-    pub creator: Pubkey,
+pub struct SlashingConfigProposed {
+    pub agent_pda: Pubkey,
+    pub authority: Pubkey,
+    pub max_bps_per_epoch: u16,
+    pub timelock_seconds: u32,
+    pub execute_after: i64,
 }
 
 #[event]
-pub struct AgentActiveSet {
-    pub creator: Pubkey,
-    pub is_active: bool,
+pub struct SlashingConfigured {
+    pub agent_pda: Pubkey,
+    pub authority: Pubkey,
+    pub max_bps_per_epoch: u16,
+    pub timelock_seconds: u32,
 }
 
 #[event]
-pub struct AgentClosed {
-    pub creator: Pubkey,
+pub struct SlashingConfigCancelled {
+    pub agent_pda: Pubkey,
+    pub execute_after: i64,
 }
 
 #[event]
-pub struct OwnerTransferred {
-    pub creator: Pubkey,
-    pub old_owner: Pubkey,
-    pub new_owner: Pubkey,
+pub struct SlashProposed {
+    pub agent_pda: Pubkey,
+    pub authority: Pubkey,
+    pub destination: Pubkey,
+    pub bps: u16,
+    pub reason_hash: [u8; 32],
+    pub execute_after: i64,
 }
 
-// Staking Events
 #[event]
-pub struct PoolCreated {
+pub struct SlashCancelled {
     pub agent_pda: Pubkey,
-    pub owner: Pubkey,
-    pub min_stake_amount: u64,
+    pub bps: u16,
 }
 
 #[event]
-pub struct MinStakeUpdated {
+pub struct Slashed {
     pub agent_pda: Pubkey,
-    pub old_amount: u64,
-    pub new_amount: u64,
+    pub destination: Pubkey,
+    pub bps: u16,
+    pub amount: u64,
+    pub reason_hash: [u8; 32],
 }
 
 #[event]
-pub struct Staked {
+pub struct StakeAccountClosed {
     pub staker: Pubkey,
     pub agent_pda: Pubkey,
+}
+
+#[event]
+pub struct StakingPoolClosed {
+    pub agent_pda: Pubkey,
+    pub owner: Pubkey,
+    pub leftover: u64,
+}
+
+// Migration Events
+
+#[event]
+pub struct AgentImported {
+    pub agent: Pubkey,
+    pub legacy_agent: Pubkey,
+    pub owner: Pubkey,
+}
+
+#[event]
+pub struct StakingPoolImported {
+    pub agent_pda: Pubkey,
+    pub legacy_pool: Pubkey,
     pub amount: u64,
-    pub total: u64,
+    pub reward_amount: u64,
 }
 
 #[event]
-pub struct Withdrawn {
+pub struct StakeImported {
     pub staker: Pubkey,
     pub agent_pda: Pubkey,
-    pub amount: u64,
-    pub fee: u64,
+    pub shares: u64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub account: Pubkey,
 }
 
 // ============================================================================
@@ -816,12 +4061,36 @@ pub enum PlatformError {
     StakingEnabled,
     #[msg("URI must be https:// for Url mode")]
     InsecureUrl,
+    #[msg("Agent counter overflow")]
+    CounterOverflow,
+    #[msg("Invalid expiry timestamp")]
+    InvalidExpiry,
+    #[msg("Pending ownership transfer has expired")]
+    OwnerTransferExpired,
+    #[msg("Pending ownership transfer was proposed by a previous owner")]
+    StaleOwnerTransfer,
+    #[msg("Invalid delegate permissions")]
+    InvalidPermissions,
+    #[msg("Delegate lacks the required permission")]
+    MissingPermission,
+    #[msg("Delegate has expired")]
+    DelegateExpired,
+    #[msg("Missing Ed25519 signature verification instruction")]
+    MissingEd25519Instruction,
+    #[msg("Ed25519 signature does not match the expected signer or message")]
+    InvalidSignature,
+    #[msg("Invalid or already used nonce")]
+    InvalidNonce,
+    #[msg("Invalid agent key")]
+    InvalidAgentKey,
     
     // Staking errors
     #[msg("Invalid minimum stake amount")]
     InvalidMinStakeAmount,
     #[msg("Invalid fee configuration")]
     InvalidFeeConfig,
+    #[msg("Fee config timelock has not elapsed")]
+    FeeConfigTimelockActive,
     #[msg("Invalid stake amount")]
     InvalidStakeAmount,
     #[msg("No stake to withdraw")]
@@ -832,39 +4101,1039 @@ pub enum PlatformError {
     BelowMinimumStake,
     #[msg("Insufficient SOL to pay unstaking fee")]
     InsufficientSolForFee,
+    #[msg("Pool owner account is required for the fee split")]
+    PoolOwnerAccountRequired,
+    #[msg("Fee vault is required in token fee mode")]
+    FeeVaultRequired,
     #[msg("Agent does not have staking enabled")]
     StakingNotEnabled,
+    #[msg("Unsupported agent account version, run migrate_agent")]
+    UnsupportedVersion,
+    #[msg("Account already uses the current layout")]
+    AlreadyMigrated,
+    #[msg("Agent counter is required to import an indexed agent")]
+    MissingAgentCounter,
+    #[msg("Legacy staking pool has not been migrated")]
+    PoolNotMigrated,
+    #[msg("Rewards are not initialized for this pool")]
+    RewardsNotInitialized,
+    #[msg("Rewards are already initialized for this pool")]
+    RewardsAlreadyInitialized,
+    #[msg("Invalid reward vault")]
+    InvalidRewardVault,
+    #[msg("Invalid reward amount")]
+    InvalidRewardAmount,
+    #[msg("No rewards to claim")]
+    NoRewards,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Withdraw amount exceeds staked amount")]
+    InsufficientStake,
+    #[msg("Pool has an unbonding period, use unstake_request")]
+    UnbondingRequired,
+    #[msg("Unbonding period exceeds the maximum")]
+    InvalidUnbondingPeriod,
+    #[msg("No pending unstake")]
+    NoPendingUnstake,
+    #[msg("Unbonding period has not ended")]
+    UnbondingNotFinished,
+    #[msg("Invalid lockup tier")]
+    InvalidLockupTier,
+    #[msg("Lockup cannot be shortened")]
+    LockupCannotBeShortened,
+    #[msg("Stake is locked")]
+    StakeLocked,
+    #[msg("Invalid early unlock penalty")]
+    InvalidPenalty,
+    #[msg("Slashing is not configured for this pool")]
+    SlashingDisabled,
+    #[msg("Invalid slashing configuration")]
+    InvalidSlashConfig,
+    #[msg("Invalid slash amount")]
+    InvalidSlashAmount,
+    #[msg("Slash destination must hold the pool token")]
+    InvalidSlashDestination,
+    #[msg("Slash timelock has not elapsed")]
+    SlashTimelockActive,
+    #[msg("Slash exceeds the per-epoch cap")]
+    SlashCapExceeded,
+    #[msg("A slash is pending for this pool")]
+    SlashPending,
+    #[msg("Slash authority cannot be the pool owner")]
+    SlashAuthorityIsOwner,
+    #[msg("Slash timelock must exceed the unbonding period by the exit window")]
+    SlashTimelockTooShort,
+    #[msg("Slashing config timelock has not elapsed")]
+    SlashingConfigTimelockActive,
+    #[msg("Receipt mint and receipt token account are required for this pool")]
+    ReceiptAccountsRequired,
+    #[msg("Invalid receipt mint")]
+    InvalidReceiptMint,
+    #[msg("Token mint does not match the pool")]
+    InvalidTokenMint,
+    #[msg("Token account does not hold the pool token")]
+    InvalidTokenAccountMint,
+    #[msg("Token account is not owned by the staker")]
+    InvalidTokenAccountOwner,
+    #[msg("Stake account still holds shares, a pending unstake or rewards")]
+    StakeAccountNotEmpty,
+    #[msg("Stake account changed too recently to be closed")]
+    ReopenDelayActive,
+    #[msg("Pool still has shares")]
+    PoolNotEmpty,
+    #[msg("Token accounts of the pool are required")]
+    PoolVaultsRequired,
+    #[msg("Stakers still have unclaimed rewards")]
+    RewardsUnclaimed,
+    #[msg("Staking pool still exists, close it first")]
+    StakingPoolExists,
+    #[msg("Pool is paused, no new stake accepted")]
+    PoolPaused,
+    #[msg("Pool is deprecated")]
+    PoolDeprecated,
+    #[msg("Agent is not active")]
+    AgentInactive,
+    #[msg("Amount exceeds the unemitted reward reserve")]
+    InsufficientRewardReserve,
+    #[msg("Too many pending unstake requests, complete one first")]
+    TooManyUnbondingRequests,
+    #[msg("Receipt pools have no stake account positions")]
+    ReceiptPoolWithoutPositions,
+    #[msg("Legacy position has already been imported")]
+    StakeAlreadyImported,
+    #[msg("Account layout is not supported, expected a v1 account")]
+    UnsupportedLayout,
+    #[msg("Lockup has not ended")]
+    LockupNotEnded,
+    #[msg("Receipts of the pool are still outstanding")]
+    ReceiptsOutstanding,
+    #[msg("Proposer of the pending owner transfer is required")]
+    ProposerRequired,
 }
 
 // ============================================================================
 // HELPERS
 // ============================================================================
 
-fn write_fixed<const N: usize>(dst: &mut [u8; N], src: &[u8]) {
-    let len = core::cmp::min(N, src.len());
-    dst[..len].copy_from_slice(&src[..len]);
-    if len < N {
-        for b in &mut dst[len..] {
-            *b = 0;
+fn preview_str(bytes: &[u8]) -> String {
+    let take = core::cmp::min(32, bytes.len());
+    String::from_utf8_lossy(&bytes[..take]).to_string()
+}
+
+fn authorize(agent: &AgentRegistry, signer: &Pubkey, delegate: Option<&Delegate>, perm: u8) -> Result<()> {
+    if *signer == agent.owner {
+        return Ok(());
+    }
+    let d = delegate.ok_or(PlatformError::Unauthorized)?;
+    require_keys_eq!(d.granted_by, agent.owner, PlatformError::Unauthorized);
+    require!(d.permissions & perm != 0, PlatformError::MissingPermission);
+    if d.expires_at != 0 {
+        require!(Clock::get()?.unix_timestamp <= d.expires_at, PlatformError::DelegateExpired);
+    }
+    Ok(())
+}
+
+fn validate_delegate_params(permissions: u8, expires_at: Option<i64>) -> Result<i64> {
+    require!(permissions != 0 && permissions & !PERM_ALL == 0, PlatformError::InvalidPermissions);
+    let expires_at = expires_at.unwrap_or(0);
+    if expires_at != 0 {
+        require!(expires_at > Clock::get()?.unix_timestamp, PlatformError::InvalidExpiry);
+    }
+    Ok(expires_at)
+}
+
+fn apply_memory_update(agent: &mut AgentRegistry, mode: u8, ptr: &[u8], hash_opt: Option<[u8; 32]>) -> Result<()> {
+    require!(agent.flags & FLAG_LOCKED == 0, PlatformError::MemoryLocked);
+    require!(ptr.len() <= MAX_CID_LEN, PlatformError::InvalidLength);
+
+    let mode = match mode {
+        0 => MemoryMode::None,
+        1 => MemoryMode::Cid,
+        2 => MemoryMode::Ipns,
+        3 => MemoryMode::Url,
+        4 => MemoryMode::Manifest,
+        _ => return err!(PlatformError::InvalidMemoryFields),
+    };
+
+    let zero = [0u8; 32];
+    match mode {
+        MemoryMode::None => {
+            require!(ptr.is_empty(), PlatformError::InvalidMemoryFields);
+            require!(hash_opt.unwrap_or(zero) == zero, PlatformError::InvalidMemoryFields);
+            agent.memory_hash = zero;
+            agent.memory_ptr = Vec::new();
+        }
+        MemoryMode::Cid => {
+            require!(!ptr.is_empty(), PlatformError::InvalidMemoryFields);
+            require!(hash_opt.unwrap_or(zero) == zero, PlatformError::InvalidMemoryFields);
+            let is_cid_like = {
+                if let Ok(s) = core::str::from_utf8(ptr) {
+                    let bytes_ok = !s.is_empty() && s.len() <= MAX_CID_LEN;
+                    if s.starts_with("bafy") {
+                        bytes_ok && s.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'))
+                    } else if s.starts_with("Qm") {
+                        bytes_ok && s.chars().all(|c| matches!(c, '1'..='9' | 'A'..='H' | 'J'..='N' | 'P'..='Z' | 'a'..='k' | 'm'..='z')) && s.len() == 46
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                }
+            };
+            require!(is_cid_like, PlatformError::InvalidMemoryFields);
+            agent.memory_hash = zero;
+        }
+        MemoryMode::Ipns | MemoryMode::Manifest | MemoryMode::Url => {
+            require!(!ptr.is_empty(), PlatformError::InvalidMemoryFields);
+            let h = hash_opt.ok_or(PlatformError::InvalidMemoryFields)?;
+            require!(h != zero, PlatformError::InvalidMemoryFields);
+            if let MemoryMode::Url = mode {
+                let s = core::str::from_utf8(ptr).map_err(|_| error!(PlatformError::InvalidMemoryFields))?;
+                require!(s.starts_with("https://"), PlatformError::InsecureUrl);
+            }
+            agent.memory_hash = h;
         }
     }
+
+    agent.memory_mode = mode as u8;
+    if !matches!(mode, MemoryMode::None) {
+        agent.memory_ptr = ptr.to_vec();
+    }
+
+    let preview = preview_str(ptr);
+    emit!(MemoryUpdated {
+        creator: agent.creator,
+        mode: mode as u8,
+        ptr_preview: preview,
+        hash: agent.memory_hash,
+    });
+    Ok(())
 }
 
-fn preview_str(bytes: &[u8]) -> String {
-    let take = core::cmp::min(32, bytes.len());
-    String::from_utf8_lossy(&bytes[..take]).to_string()
+pub fn signed_memory_message(agent: &Pubkey, nonce: u64, mode: u8, ptr: &[u8], hash: &[u8; 32]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(SIGNED_MEMORY_DOMAIN.len() + 32 + 8 + 1 + 32 + ptr.len());
+    msg.extend_from_slice(SIGNED_MEMORY_DOMAIN);
+    msg.extend_from_slice(agent.as_ref());
+    msg.extend_from_slice(&nonce.to_le_bytes());
+    msg.push(mode);
+    msg.extend_from_slice(hash);
+    msg.extend_from_slice(ptr);
+    msg
+}
+
+fn verify_ed25519_ix(ix_sysvar: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    let current = load_current_index_checked(ix_sysvar)?;
+    require!(current > 0, PlatformError::MissingEd25519Instruction);
+    let ix = load_instruction_at_checked((current - 1) as usize, ix_sysvar)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, PlatformError::MissingEd25519Instruction);
+
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, PlatformError::InvalidSignature);
+    let read = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    require!(
+        read(4) == u16::MAX && read(8) == u16::MAX && read(14) == u16::MAX,
+        PlatformError::InvalidSignature
+    );
+    let (pk_off, msg_off, msg_len) = (read(6) as usize, read(10) as usize, read(12) as usize);
+    let pk = data.get(pk_off..pk_off + 32).ok_or(PlatformError::InvalidSignature)?;
+    let msg = data.get(msg_off..msg_off + msg_len).ok_or(PlatformError::InvalidSignature)?;
+    require!(pk == signer.as_ref() && msg == message, PlatformError::InvalidSignature);
+    Ok(())
+}
+
+// Resize an account: `payer` covers growth, and rent freed by shrinking goes to `refund_to`.
+// Without a refund account the surplus stays in the account for a later resize or close.
+fn resize_account<'info>(
+    info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    refund_to: Option<&AccountInfo<'info>>,
+    system_program: &AccountInfo<'info>,
+    new_len: usize,
+) -> Result<()> {
+    let required = Rent::get()?.minimum_balance(new_len);
+    let current = info.lamports();
+    if current > required {
+        if let Some(refund_to) = refund_to {
+            **info.try_borrow_mut_lamports()? -= current - required;
+            **refund_to.try_borrow_mut_lamports()? += current - required;
+        }
+    } else if required > current {
+        let ix = system_instruction::transfer(payer.key, info.key, required - current);
+        anchor_lang::solana_program::program::invoke(&ix, &[payer.clone(), info.clone(), system_program.clone()])?;
+    }
+    info.resize(new_len)?;
+    Ok(())
+}
+
+// Where rent freed by shrinking an agent goes: the signer when it is the owner, otherwise the
+// owner account if the caller passed it (never a delegate or relayer)
+fn rent_refund_account<'info>(
+    agent: &AgentRegistry,
+    signer: &Signer<'info>,
+    agent_owner: &Option<UncheckedAccount<'info>>,
+) -> Option<AccountInfo<'info>> {
+    if signer.key() == agent.owner {
+        Some(signer.to_account_info())
+    } else {
+        agent_owner.as_ref().map(|owner| owner.to_account_info())
+    }
+}
+
+fn init_agent(agent: &mut AgentRegistry, creator: Pubkey, params: AgentParams) -> Result<()> {
+    let AgentParams { card_uri, card_hash, has_staking, memory_mode, memory_ptr, memory_hash } = params;
+    agent.version = AGENT_REGISTRY_VERSION;
+    agent.creator = creator;
+    agent.owner = creator;
+    
+    // Memory (optional)
+    if let Some(mode) = memory_mode {
+        if let Some(ptr) = &memory_ptr {
+            require!(!ptr.is_empty() && ptr.len() <= MAX_CID_LEN, PlatformError::InvalidLength);
+            require!(mode <= 3, PlatformError::InvalidMemoryFields);
+            
+            if mode == MemoryMode::Url as u8 {
+                let url_str = core::str::from_utf8(ptr).map_err(|_| PlatformError::InvalidMemoryFields)?;
+                require!(url_str.starts_with("https://"), PlatformError::InsecureUrl);
+            }
+            
+            agent.memory_mode = mode;
+            agent.memory_ptr = ptr.clone();
+            agent.memory_hash = memory_hash.unwrap_or([0u8; 32]);
+        } else {
+            return Err(PlatformError::InvalidMemoryFields.into());
+        }
+    } else {
+        agent.memory_mode = MemoryMode::None as u8;
+        agent.memory_ptr = Vec::new();
+        agent.memory_hash = [0u8; 32];
+    }
+    
+    // Card (mandatory)
+    let bytes = card_uri.as_bytes();
+    require!(!bytes.is_empty() && bytes.len() <= MAX_URI, PlatformError::InvalidLength);
+    let ok_scheme = card_uri.starts_with("https://") || card_uri.starts_with("ipfs://");
+    require!(ok_scheme, PlatformError::InsecureUrl);
+    agent.card_uri = bytes.to_vec();
+    agent.card_hash = card_hash;
+    
+    // Flags
+    agent.flags = FLAG_ACTIVE;
+    if has_staking.unwrap_or(true) {
+        agent.flags |= FLAG_HAS_STAKING;
+    }
+    
+    agent.agent_index = [0u8; 4];
+    Ok(())
+}
+
+// Fresh position of `staker` in `pool` (init_stake, or stake on a new account)
+fn init_stake_account(stake_acc: &mut StakeAccount, staker: Pubkey, pool: &StakingPool, now: i64, bump: u8) {
+    stake_acc.staker = staker;
+    stake_acc.agent_pda = pool.agent_pda;
+    stake_acc.shares = 0;
+    stake_acc.staked_at = now;
+    stake_acc.last_updated_at = now;
+    stake_acc.reward_per_token_paid = pool.reward_per_token_stored;
+    stake_acc.rewards_checkpoint_at = now;
+    stake_acc.rewards_owed = 0;
+    stake_acc.unbonding = [UnbondingRequest::default(); MAX_UNBONDING_REQUESTS];
+    stake_acc.lock_tier = 0;
+    stake_acc.lock_ends_at = 0;
+    stake_acc.lock_penalty_bps = 0;
+    stake_acc.weighted_amount = 0;
+    stake_acc.imported = false;
+    stake_acc.bump = bump;
+}
+
+// Vault: the ["token_vault", pool] PDA, created here, or the pool's associated token
+// account, created beforehand by the client
+fn setup_pool_vault<'info>(
+    payer: &AccountInfo<'info>,
+    token_vault: &AccountInfo<'info>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    pool: &Pubkey,
+    system_program: &AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    program_id: &Pubkey,
+) -> Result<()> {
+    let (vault_pda, vault_bump) = Pubkey::find_program_address(&[b"token_vault", pool.as_ref()], program_id);
+    if token_vault.key() == vault_pda {
+        init_pool_vault(
+            payer,
+            token_vault,
+            &token_mint.to_account_info(),
+            pool,
+            &[b"token_vault", pool.as_ref(), &[vault_bump]],
+            system_program,
+            &token_program.to_account_info(),
+        )
+    } else {
+        check_ata_vault(token_vault, pool, token_mint, token_program)
+    }
+}
+
+// Fresh pool state; receipt_mint and bump are set by the caller
+fn init_pool(
+    pool: &mut StakingPool,
+    agent_pda: Pubkey,
+    owner: Pubkey,
+    token_mint: Pubkey,
+    token_vault: Pubkey,
+    min_stake_amount: u64,
+    now: i64,
+) {
+    pool.agent_pda = agent_pda;
+    pool.owner = owner;
+    pool.token_mint = token_mint;
+    pool.token_vault = token_vault;
+    pool.min_stake_amount = min_stake_amount;
+    pool.total_shares = 0;
+    pool.staker_count = 0;
+    pool.created_at = now;
+    pool.flags = POOL_FLAG_ACTIVE;
+    pool.reward_mint = Pubkey::default();
+    pool.reward_vault = Pubkey::default();
+    pool.reward_per_token_stored = 0;
+    pool.reward_rate = 0;
+    pool.reward_reserve = 0;
+    pool.last_update_time = now;
+    pool.unbonding_period_seconds = 0;
+    pool.total_weighted_stake = 0;
+    pool.early_unlock_penalty_bps = 0;
+    pool.slash_authority = Pubkey::default();
+    pool.slash_max_bps_per_epoch = 0;
+    pool.slash_timelock_seconds = 0;
+    pool.slash_epoch = 0;
+    pool.slashed_bps_in_epoch = 0;
+    pool.receipt_mint = Pubkey::default();
+    pool.fee_immediate_lamports = 0;
+    pool.fee_regular_lamports = 0;
+    pool.fee_decay_duration_seconds = 0;
+    pool.fee_owner_share_bps = 0;
+    pool.fee_mode = FEE_MODE_DEFAULT;
+    pool.fee_vault = Pubkey::default();
+    pool.owner_token_fees = 0;
+    pool.rewards_owed_total = 0;
+    pool.total_unbonding = 0;
+}
+
+// Create a pool token account at the PDA of `vault_seeds` (["token_vault" or "reward_vault", pool, bump])
+// with the pool PDA as authority
+fn init_pool_vault<'info>(
+    payer: &AccountInfo<'info>,
+    token_vault: &AccountInfo<'info>,
+    token_mint: &AccountInfo<'info>,
+    pool: &Pubkey,
+    vault_seeds: &[&[u8]],
+    system_program: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+) -> Result<()> {
+    let signer = &[vault_seeds];
+    // Sized by the token program: Token-2022 mints may require account extensions
+    let vault_size = token_interface::get_account_data_size(
+        CpiContext::new(token_program.clone(), GetAccountDataSize { mint: token_mint.clone() }),
+        &[],
+    )?;
+    let vault_rent = Rent::get()?.minimum_balance(vault_size as usize);
+
+    solana_program::program::invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            token_vault.key,
+            vault_rent,
+            vault_size,
+            token_program.key,
+        ),
+        &[payer.clone(), token_vault.clone(), system_program.clone()],
+        signer,
+    )?;
+
+    // Accepts both SPL Token and Token-2022 program ids
+    let init_ix = token_interface::spl_token_2022::instruction::initialize_account3(
+        token_program.key,
+        token_vault.key,
+        token_mint.key,
+        pool,
+    )?;
+    solana_program::program::invoke(&init_ix, &[token_vault.clone(), token_mint.clone()])?;
+    Ok(())
+}
+
+// An associated token account of the pool can serve as vault: it must already exist and hold the pool mint
+// Send the stake left in a share-less pool's vault to `to`, returns the amount
+fn sweep_pool_vault<'info>(
+    pool: &Account<'info, StakingPool>,
+    token_program: &AccountInfo<'info>,
+    token_vault: &AccountInfo<'info>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    to: &AccountInfo<'info>,
+) -> Result<u64> {
+    let leftover = token::accessor::amount(token_vault)?;
+    if leftover > 0 {
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            token_program.clone(),
+            TransferChecked {
+                from: token_vault.clone(),
+                mint: token_mint.to_account_info(),
+                to: to.clone(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, leftover, token_mint.decimals)?;
+    }
+    Ok(leftover)
+}
+
+// Pay the fee vault out: the owner's share of token-mode fees to the pool owner, the rest
+// (treasury share and direct transfers) to the treasury. Returns (owner_amount, treasury_amount).
+fn pay_out_fee_vault<'info>(
+    pool: &Account<'info, StakingPool>,
+    token_program: &AccountInfo<'info>,
+    fee_vault: &InterfaceAccount<'info, TokenAccount>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    owner_token_account: &AccountInfo<'info>,
+    treasury_token_account: &AccountInfo<'info>,
+) -> Result<(u64, u64)> {
+    let balance = fee_vault.amount;
+    let owner_amount = pool.owner_token_fees.min(balance);
+    let treasury_amount = balance - owner_amount;
+
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    for (to, amount) in [(owner_token_account, owner_amount), (treasury_token_account, treasury_amount)] {
+        if amount == 0 {
+            continue;
+        }
+        let cpi_ctx = CpiContext::new_with_signer(
+            token_program.clone(),
+            TransferChecked {
+                from: fee_vault.to_account_info(),
+                mint: token_mint.to_account_info(),
+                to: to.clone(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, token_mint.decimals)?;
+    }
+    Ok((owner_amount, treasury_amount))
+}
+
+// Pay out and close the fee vault of a closing pool, if it has one. A non-empty vault needs
+// the treasury token account.
+fn close_fee_vault<'info>(
+    pool: &Account<'info, StakingPool>,
+    token_program: &AccountInfo<'info>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    fee_vault: Option<&InterfaceAccount<'info, TokenAccount>>,
+    owner_token_account: &AccountInfo<'info>,
+    treasury_token_account: Option<&InterfaceAccount<'info, TokenAccount>>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    if pool.fee_vault == Pubkey::default() {
+        return Ok(());
+    }
+    let fee_vault = fee_vault.ok_or(PlatformError::PoolVaultsRequired)?;
+    require_keys_eq!(fee_vault.key(), pool.fee_vault, PlatformError::InvalidVault);
+    if fee_vault.amount > 0 {
+        let treasury_token_account = treasury_token_account.ok_or(PlatformError::PoolVaultsRequired)?;
+        pay_out_fee_vault(pool, token_program, fee_vault, token_mint, owner_token_account, &treasury_token_account.to_account_info())?;
+    }
+    close_pool_token_account(token_program, &fee_vault.to_account_info(), destination, pool)
+}
+
+// Sweep and close the reward vault of a closing pool, if it has one. Rewards settled to
+// stakers must have been claimed; the rest (unemitted reserve, rounding dust, direct
+// transfers) goes to the owner's reward token account.
+fn close_reward_vault<'info>(
+    pool: &Account<'info, StakingPool>,
+    reward_token_program: &AccountInfo<'info>,
+    reward_vault: Option<&InterfaceAccount<'info, TokenAccount>>,
+    reward_mint: Option<&InterfaceAccount<'info, Mint>>,
+    owner_reward_account: Option<&InterfaceAccount<'info, TokenAccount>>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    if pool.reward_vault == Pubkey::default() {
+        return Ok(());
+    }
+    let reward_vault = reward_vault.ok_or(PlatformError::PoolVaultsRequired)?;
+    require_keys_eq!(reward_vault.key(), pool.reward_vault, PlatformError::InvalidRewardVault);
+    require!(pool.rewards_owed_total == 0, PlatformError::RewardsUnclaimed);
+    if reward_vault.amount > 0 {
+        let (Some(reward_mint), Some(owner_reward_account)) = (reward_mint, owner_reward_account) else {
+            return err!(PlatformError::PoolVaultsRequired);
+        };
+        require_keys_eq!(reward_mint.key(), pool.reward_mint, PlatformError::InvalidTokenMint);
+        require_keys_eq!(owner_reward_account.mint, pool.reward_mint, PlatformError::InvalidTokenAccountMint);
+        require_keys_eq!(owner_reward_account.owner, pool.owner, PlatformError::InvalidTokenAccountOwner);
+
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            reward_token_program.clone(),
+            TransferChecked {
+                from: reward_vault.to_account_info(),
+                mint: reward_mint.to_account_info(),
+                to: owner_reward_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, reward_vault.amount, reward_mint.decimals)?;
+    }
+    close_pool_token_account(reward_token_program, &reward_vault.to_account_info(), destination, pool)
+}
+
+fn close_pool_token_account<'info>(
+    token_program: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    pool: &Account<'info, StakingPool>,
+) -> Result<()> {
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.clone(),
+        CloseAccount {
+            account: account.clone(),
+            destination: destination.clone(),
+            authority: pool.to_account_info(),
+        },
+        signer,
+    );
+    token_interface::close_account(cpi_ctx)
+}
+
+fn check_ata_vault<'info>(
+    vault: &AccountInfo<'info>,
+    pool: &Pubkey,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    let ata = get_associated_token_address_with_program_id(pool, &mint.key(), token_program.key);
+    require_keys_eq!(vault.key(), ata, PlatformError::InvalidVault);
+    let vault = TokenAccount::try_deserialize(&mut &vault.try_borrow_data()?[..])?;
+    require_keys_eq!(vault.mint, mint.key(), PlatformError::InvalidTokenAccountMint);
+    require_keys_eq!(vault.owner, *pool, PlatformError::InvalidTokenAccountOwner);
+    Ok(())
+}
+
+// Accrue emissions since the last update into reward_per_token_stored. Emission is
+// capped by the funded reserve and paused while nothing is staked.
+fn update_rewards(pool: &mut StakingPool, now: i64) -> Result<()> {
+    let elapsed = now.saturating_sub(pool.last_update_time).max(0) as u64;
+    pool.last_update_time = now;
+    if pool.total_weighted_stake == 0 || pool.reward_rate == 0 || elapsed == 0 {
+        return Ok(());
+    }
+    let emitted = elapsed.saturating_mul(pool.reward_rate).min(pool.reward_reserve);
+    let per_token = (emitted as u128) * REWARD_PRECISION / pool.total_weighted_stake as u128;
+    pool.reward_per_token_stored = pool
+        .reward_per_token_stored
+        .checked_add(per_token)
+        .ok_or(PlatformError::MathOverflow)?;
+    pool.reward_reserve -= emitted;
+    Ok(())
+}
+
+// Move what the position earned since its last checkpoint into rewards_owed. The part an
+// ended lockup's boost would have taken after lock_ends_at goes back to the reward reserve.
+fn settle_rewards(pool: &mut StakingPool, stake_acc: &mut StakeAccount, now: i64) -> Result<()> {
+    let delta = pool.reward_per_token_stored.saturating_sub(stake_acc.reward_per_token_paid);
+    let boost_ends_at = (stake_acc.lock_tier != 0).then_some(stake_acc.lock_ends_at);
+    let (earned, forfeited) = accrued_rewards(
+        stake_acc.weighted_amount,
+        stake_acc.shares,
+        boost_ends_at,
+        stake_acc.rewards_checkpoint_at,
+        delta,
+        now,
+    )?;
+    stake_acc.rewards_owed = stake_acc.rewards_owed.checked_add(earned).ok_or(PlatformError::MathOverflow)?;
+    pool.rewards_owed_total = pool.rewards_owed_total.checked_add(earned).ok_or(PlatformError::MathOverflow)?;
+    pool.reward_reserve = pool.reward_reserve.checked_add(forfeited).ok_or(PlatformError::MathOverflow)?;
+    stake_acc.reward_per_token_paid = pool.reward_per_token_stored;
+    stake_acc.rewards_checkpoint_at = now;
+    Ok(())
+}
+
+// Rewards for an accumulator increase of `delta` between `checkpoint_at` and `now`, as
+// (earned, forfeited). Past `boost_ends_at` only the unlocked weight earns; the accumulator at
+// that time is not stored, so emissions are taken as even over the interval.
+fn accrued_rewards(
+    weighted_amount: u64,
+    shares: u64,
+    boost_ends_at: Option<i64>,
+    checkpoint_at: i64,
+    delta: u128,
+    now: i64,
+) -> Result<(u64, u64)> {
+    let weighted = weighted_amount as u128;
+    let full = weighted.checked_mul(delta).ok_or(PlatformError::MathOverflow)? / REWARD_PRECISION;
+    let earned = match boost_ends_at {
+        Some(ends_at) if now > ends_at => {
+            let unlocked = (shares as u128 * LOCKUP_TIERS[0].1 as u128 / BPS_DENOMINATOR as u128).min(weighted);
+            let elapsed = now.saturating_sub(checkpoint_at).max(1) as u128;
+            let boosted = (ends_at.saturating_sub(checkpoint_at).max(0) as u128).min(elapsed);
+            let boost_delta = delta * boosted / elapsed;
+            unlocked
+                .checked_mul(delta)
+                .and_then(|base| (weighted - unlocked).checked_mul(boost_delta).and_then(|boost| base.checked_add(boost)))
+                .ok_or(PlatformError::MathOverflow)?
+                / REWARD_PRECISION
+        }
+        _ => full,
+    };
+    let earned = u64::try_from(earned).map_err(|_| PlatformError::MathOverflow)?;
+    let forfeited = u64::try_from(full).map_err(|_| PlatformError::MathOverflow)? - earned;
+    Ok((earned, forfeited))
+}
+
+// Shares minted for `amount` against the vault balance plus VIRTUAL_SHARES (1:1 into an empty pool)
+fn amount_to_shares(pool: &StakingPool, amount: u64, vault_balance: u64) -> Result<u64> {
+    let outstanding = pool.total_shares as u128 + pool.total_unbonding as u128;
+    let shares = amount as u128 * (outstanding + VIRTUAL_SHARES as u128)
+        / (vault_balance as u128 + VIRTUAL_SHARES as u128);
+    u64::try_from(shares).map_err(|_| error!(PlatformError::MathOverflow))
+}
+
+// Tokens `shares` redeem for at the virtual price, capped at their pro-rata part of the vault, rounded down
+fn shares_to_amount(pool: &StakingPool, shares: u64, vault_balance: u64) -> Result<u64> {
+    let outstanding = pool.total_shares as u128 + pool.total_unbonding as u128;
+    if outstanding == 0 {
+        return Ok(0);
+    }
+    let virtual_amount = shares as u128 * (vault_balance as u128 + VIRTUAL_SHARES as u128)
+        / (outstanding + VIRTUAL_SHARES as u128);
+    let pro_rata = shares as u128 * vault_balance as u128 / outstanding;
+    u64::try_from(virtual_amount.min(pro_rata)).map_err(|_| error!(PlatformError::MathOverflow))
+}
+
+fn mul_div(value: u64, num: u64, denom: u64) -> Result<u64> {
+    let scaled = (value as u128 * num as u128)
+        .checked_div(denom as u128)
+        .ok_or(PlatformError::MathOverflow)?;
+    u64::try_from(scaled).map_err(|_| error!(PlatformError::MathOverflow))
+}
+
+fn reweight(pool: &mut StakingPool, stake_acc: &mut StakeAccount) -> Result<()> {
+    let (_, weight_bps) = LOCKUP_TIERS[stake_acc.lock_tier as usize];
+    let weighted = (stake_acc.shares as u128 * weight_bps as u128) / BPS_DENOMINATOR as u128;
+    let weighted = u64::try_from(weighted).map_err(|_| PlatformError::MathOverflow)?;
+    pool.total_weighted_stake = pool
+        .total_weighted_stake
+        .saturating_sub(stake_acc.weighted_amount)
+        .checked_add(weighted)
+        .ok_or(PlatformError::MathOverflow)?;
+    stake_acc.weighted_amount = weighted;
+    Ok(())
+}
+
+// Drop an ended lockup back to tier 0 (after settle_rewards, which stops the boost at lock_ends_at)
+fn end_lockup(pool: &mut StakingPool, stake_acc: &mut StakeAccount, now: i64) -> Result<()> {
+    if stake_acc.lock_tier == 0 || now < stake_acc.lock_ends_at {
+        return Ok(());
+    }
+    stake_acc.lock_tier = 0;
+    stake_acc.lock_ends_at = 0;
+    stake_acc.lock_penalty_bps = 0;
+    reweight(pool, stake_acc)
+}
+
+fn early_unlock_penalty(pool: &StakingPool, stake_acc: &StakeAccount, amount: u64, now: i64) -> Result<u64> {
+    // A deprecated pool releases every lock so stakers can leave for free
+    if now >= stake_acc.lock_ends_at || pool.flags & POOL_FLAG_DEPRECATED != 0 {
+        return Ok(0);
+    }
+    require!(stake_acc.lock_penalty_bps > 0, PlatformError::StakeLocked);
+    Ok((amount as u128 * stake_acc.lock_penalty_bps as u128 / BPS_DENOMINATOR as u128) as u64)
+}
+
+// The slash authority is never the pool owner, and its timelock leaves room to unstake
+fn check_slash_config(pool: &StakingPool, authority: Pubkey, timelock_seconds: u32) -> Result<()> {
+    if authority == Pubkey::default() {
+        return Ok(());
+    }
+    require_keys_neq!(authority, pool.owner, PlatformError::SlashAuthorityIsOwner);
+    require!(
+        timelock_seconds as u64 >= pool.unbonding_period_seconds as u64 + MIN_SLASH_EXIT_WINDOW_SECONDS as u64,
+        PlatformError::SlashTimelockTooShort
+    );
+    Ok(())
+}
+
+fn burn_from_vault<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    vault: &AccountInfo<'info>,
+    pool: &Account<'info, StakingPool>,
+    amount: u64,
+) -> Result<()> {
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        Burn {
+            mint: mint.to_account_info(),
+            from: vault.clone(),
+            authority: pool.to_account_info(),
+        },
+        signer,
+    );
+    token_interface::burn(cpi_ctx, amount)
+}
+
+// Receipt accounts of a stake/exit. Required when the pool has a receipt mint, ignored otherwise.
+fn receipt_accounts<'a, 'info>(
+    pool: &StakingPool,
+    mint: &'a Option<InterfaceAccount<'info, Mint>>,
+    account: &'a Option<InterfaceAccount<'info, TokenAccount>>,
+) -> Result<Option<(&'a InterfaceAccount<'info, Mint>, &'a InterfaceAccount<'info, TokenAccount>)>> {
+    if pool.receipt_mint == Pubkey::default() {
+        return Ok(None);
+    }
+    match (mint, account) {
+        (Some(mint), Some(account)) => Ok(Some((mint, account))),
+        _ => err!(PlatformError::ReceiptAccountsRequired),
+    }
+}
+
+// Mint receipts 1:1 with newly minted shares (pool PDA is the mint authority)
+fn mint_receipts<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    pool: &Account<'info, StakingPool>,
+    shares: u64,
+) -> Result<()> {
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        MintTo {
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: pool.to_account_info(),
+        },
+        signer,
+    );
+    token_interface::mint_to(cpi_ctx, shares)
+}
+
+fn burn_receipts<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    owner: &Signer<'info>,
+    shares: u64,
+) -> Result<()> {
+    let cpi_ctx = CpiContext::new(
+        token_program.to_account_info(),
+        Burn {
+            mint: mint.to_account_info(),
+            from: from.to_account_info(),
+            authority: owner.to_account_info(),
+        },
+    );
+    token_interface::burn(cpi_ctx, shares)
+}
+
+fn check_unstake_shares(pool: &StakingPool, stake_acc: &StakeAccount, shares: u64, vault_balance: u64) -> Result<u64> {
+    // Receipt pools: the exit redeems the receipts it burns, positions hold no shares
+    if pool.receipt_mint != Pubkey::default() {
+        require!(shares > 0, PlatformError::InvalidStakeAmount);
+        return Ok(0);
+    }
+    require!(stake_acc.shares > 0, PlatformError::NoStake);
+    require!(shares > 0, PlatformError::InvalidStakeAmount);
+    require!(shares <= stake_acc.shares, PlatformError::InsufficientStake);
+    let remaining = stake_acc.shares - shares;
+    // A partial exit must leave a position that could have been opened on its own
+    require!(
+        remaining == 0 || shares_to_amount(pool, remaining, vault_balance)? >= pool.min_stake_amount,
+        PlatformError::BelowMinimumStake
+    );
+    Ok(remaining)
+}
+
+// SOL unstake fee for `shares` on the pool curve, or the global one (call before the
+// position's shares are reduced)
+fn unstake_fee(state: &ProgramState, pool: &StakingPool, stake_acc: &StakeAccount, shares: u64, now: i64) -> Result<u64> {
+    if pool.flags & POOL_FLAG_DEPRECATED != 0 {
+        return Ok(0);
+    }
+    let elapsed = (now - stake_acc.staked_at).max(0) as u64;
+    let full_fee = if pool.fee_decay_duration_seconds > 0 {
+        // The curve was checked against the global bounds of the time it was set: keep it
+        // within the current ones, which update_fee_config may have moved since
+        calculate_unstake_fee(
+            elapsed,
+            pool.fee_immediate_lamports,
+            pool.fee_regular_lamports,
+            state.fee_max_lamports,
+            pool.fee_decay_duration_seconds,
+        )?
+        .max(state.fee_regular_lamports)
+        .min(state.fee_max_lamports)
+    } else {
+        calculate_unstake_fee(
+            elapsed,
+            state.fee_immediate_lamports,
+            state.fee_regular_lamports,
+            state.fee_max_lamports,
+            state.decay_duration_seconds,
+        )?
+    };
+    // Receipt pools keep no shares on the stake account to prorate against: each exit pays the
+    // full fee for the account's age, so splitting a redemption cannot lower it
+    if pool.receipt_mint != Pubkey::default() {
+        return Ok(full_fee);
+    }
+    prorate_fee(full_fee, shares, stake_acc.shares)
+}
+
+// Pay `fee` from the staker, split between the pool owner and the treasury. Returns the owner share.
+fn charge_unstake_fee<'info>(
+    state: &ProgramState,
+    pool: &StakingPool,
+    fee: u64,
+    staker: &AccountInfo<'info>,
+    treasury: &AccountInfo<'info>,
+    agent_owner: &Option<UncheckedAccount<'info>>,
+) -> Result<u64> {
+    if fee == 0 {
+        return Ok(0);
+    }
+
+    // Check sufficient SOL before transfer
+    let rent_exempt = Rent::get()?.minimum_balance(0);
+    require!(
+        staker.lamports() >= fee.saturating_add(rent_exempt),
+        PlatformError::InsufficientSolForFee
+    );
+    let owner_fee = mul_div(fee, pool.fee_owner_share_bps as u64, BPS_DENOMINATOR)?;
+    if owner_fee > 0 {
+        let owner = agent_owner.as_ref().ok_or(PlatformError::PoolOwnerAccountRequired)?;
+        let ix = system_instruction::transfer(staker.key, &pool.owner, owner_fee);
+        solana_program::program::invoke(&ix, &[staker.clone(), owner.to_account_info()])?;
+    }
+    let ix = system_instruction::transfer(staker.key, &state.treasury, fee - owner_fee);
+    solana_program::program::invoke(&ix, &[staker.clone(), treasury.clone()])?;
+    Ok(owner_fee)
+}
+
+// Fee for withdrawing `shares` out of `held`, rounded up
+fn prorate_fee(fee: u64, shares: u64, held: u64) -> Result<u64> {
+    require!(held > 0, PlatformError::NoStake);
+    let prorated = (fee as u128 * shares as u128).div_ceil(held as u128);
+    Ok(prorated as u64)
+}
+
+fn fee_mode(state: &ProgramState, pool: &StakingPool) -> u8 {
+    if pool.fee_mode == FEE_MODE_DEFAULT { state.fee_mode } else { pool.fee_mode }
+}
+
+fn token_fee_bps(state: &ProgramState, pool: &StakingPool, stake_acc: &StakeAccount, now: i64) -> Result<u64> {
+    if pool.flags & POOL_FLAG_DEPRECATED != 0 {
+        return Ok(0);
+    }
+    let elapsed = (now - stake_acc.staked_at).max(0) as u64;
+    calculate_unstake_fee(
+        elapsed,
+        state.fee_immediate_bps as u64,
+        state.fee_regular_bps as u64,
+        MAX_TOKEN_FEE_BPS as u64,
+        state.decay_duration_seconds,
+    )
+}
+
+// Pool vault -> fee vault, accruing the owner's share
+fn charge_token_fee<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    vault: &AccountInfo<'info>,
+    fee_vault: &Option<InterfaceAccount<'info, TokenAccount>>,
+    pool: &mut Account<'info, StakingPool>,
+    fee: u64,
+) -> Result<()> {
+    let fee_vault = fee_vault.as_ref().ok_or(PlatformError::FeeVaultRequired)?;
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        TransferChecked {
+            from: vault.clone(),
+            mint: mint.to_account_info(),
+            to: fee_vault.to_account_info(),
+            authority: pool.to_account_info(),
+        },
+        signer,
+    );
+    token_interface::transfer_checked(cpi_ctx, fee, mint.decimals)?;
+    let owner_fee = mul_div(fee, pool.fee_owner_share_bps as u64, BPS_DENOMINATOR)?;
+    pool.owner_token_fees = pool.owner_token_fees.saturating_add(owner_fee);
+    Ok(())
+}
+
+// Decode an account still in its v1 layout, recognised by its size
+fn read_v1<T: AnchorDeserialize>(info: &AccountInfo, discriminator: &[u8], v1_space: usize, space: usize) -> Result<T> {
+    let data = info.try_borrow_data()?;
+    require!(data.len() >= 8 && data[..8] == *discriminator, PlatformError::UnsupportedLayout);
+    match data.len() - 8 {
+        len if len == v1_space => Ok(T::deserialize(&mut &data[8..])?),
+        len if len == space => err!(PlatformError::AlreadyMigrated),
+        _ => err!(PlatformError::UnsupportedLayout),
+    }
 }
 
-fn calculate_unstake_fee(elapsed_secs: u64, state: &ProgramState) -> Result<u64> {
-    require!(state.decay_duration_seconds > 0, PlatformError::InvalidFeeConfig);
+fn calculate_unstake_fee(elapsed_secs: u64, immediate: u64, regular: u64, max: u64, decay_duration_seconds: u32) -> Result<u64> {
+    require!(decay_duration_seconds > 0, PlatformError::InvalidFeeConfig);
     
-    if elapsed_secs >= state.decay_duration_seconds as u64 {
-        return Ok(state.fee_regular_lamports.min(state.fee_max_lamports));
+    if elapsed_secs >= decay_duration_seconds as u64 {
+        return Ok(regular.min(max));
     }
     
-    let immediate = state.fee_immediate_lamports as u128;
-    let regular = state.fee_regular_lamports as u128;
-    let duration = state.decay_duration_seconds as u128;
+    let immediate = immediate as u128;
+    let regular = regular as u128;
+    let duration = decay_duration_seconds as u128;
     let elapsed = elapsed_secs as u128;
     let diff = immediate.saturating_sub(regular);
     
@@ -874,5 +5143,5 @@ fn calculate_unstake_fee(elapsed_secs: u64, state: &ProgramState) -> Result<u64>
         .ok_or(PlatformError::InvalidFeeConfig)?;
     
     let fee = immediate.saturating_sub(reduction);
-    Ok(fee.min(state.fee_max_lamports as u128) as u64)
+    Ok(fee.min(max as u128) as u64)
 }
@@ -1,4 +1,8 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as sysvar_instructions, load_current_index_checked, load_instruction_at_checked,
+};
 #[cfg(not(feature = "no-entrypoint"))]
 use solana_security_txt::security_txt;
 
@@ -22,14 +26,41 @@ security_txt! {
 pub const FLAG_ACTIVE: u32 = 1 << 0;
 pub const FLAG_LOCKED: u32 = 1 << 1;
 pub const FLAG_HAS_STAKING: u32 = 1 << 2;
+pub const FLAG_INDEXED: u32 = 1 << 3; // PDA seeds = ["agent", creator, agent_index]
 
-// Fixed account size (without the 8-byte discriminator)
-// Layout: version(1) + creator(32) + owner(32) + memory_mode(1) + memory_ptr_len(1) 
-//         + memory_ptr(96) + memory_hash(32) + card_uri_len(1) + card_uri(96) 
-//         + card_hash(32) + flags(4) + bump(1) + _padding(7) = 336 bytes
-const AGENT_REGISTRY_SPACE: usize = 336;
-const MAX_URI: usize = 96;
-const MAX_CID_LEN: usize = 96; // reuse same cap as ptr buffer
+// agent_staking program, owner of the ["staking_pool", agent] PDAs checked by disable_staking
+pub const AGENT_STAKING_ID: Pubkey = pubkey!("j3WMvorrddakwt69dqrQ5cve5APpyd4bxUCb9UF9Aqj");
+
+// Delegate permission bits (Delegate.permissions)
+pub const PERM_CARD: u8 = 1 << 0;
+pub const PERM_MEMORY: u8 = 1 << 1;
+pub const PERM_ACTIVE: u8 = 1 << 2;
+pub const PERM_ALL: u8 = PERM_CARD | PERM_MEMORY | PERM_ACTIVE;
+
+// AgentRegistry layout contract, versioned through AgentRegistry.version
+// (sizes without the 8-byte discriminator)
+// v1: fixed buffers
+//     version(1) + creator(32) + owner(32) + memory_mode(1) + memory_ptr_len(1)
+//     + memory_ptr(96) + memory_hash(32) + card_uri_len(1) + card_uri(96)
+//     + card_hash(32) + flags(4) + bump(1) + agent_index(4) + _padding(3) = 336 bytes
+// v2: fixed header + length-prefixed URIs, account is reallocated when they change
+//     version(1) + creator(32) + owner(32) + memory_mode(1) + memory_hash(32) + card_hash(32)
+//     + flags(4) + bump(1) + agent_index(4) + card_uri(4 + n) + memory_ptr(4 + m) = 147 + n + m bytes
+pub const AGENT_REGISTRY_VERSION: u8 = 2;
+pub const AGENT_REGISTRY_SPACE_V1: usize = 336;
+pub const AGENT_REGISTRY_BASE_SPACE_V2: usize = 147;
+// Layout: creator(32) + count(4) + bump(1) = 37 bytes
+const AGENT_COUNTER_SPACE: usize = 32 + 4 + 1;
+// Layout: agent(32) + proposer(32) + pending_owner(32) + expires_at(8) + bump(1) = 105 bytes
+const PENDING_OWNER_SPACE: usize = 32 + 32 + 32 + 8 + 1;
+// Layout: agent(32) + delegate(32) + granted_by(32) + permissions(1) + expires_at(8) + bump(1) = 106 bytes
+const DELEGATE_SPACE: usize = 32 + 32 + 32 + 1 + 8 + 1;
+// Layout: agent(32) + key(32) + bound_at(8) + nonce(8) + bump(1) = 81 bytes
+const AGENT_KEY_SPACE: usize = 32 + 32 + 8 + 8 + 1;
+const MAX_URI: usize = 512;
+const MAX_CID_LEN: usize = 512; // reuse same cap as ptr buffer
+// Domain prefix of the message signed for set_memory_signed
+const SIGNED_MEMORY_DOMAIN: &[u8] = b"agent_registry:set_memory:v1";
  
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
@@ -41,6 +72,17 @@ pub enum MemoryMode {
     Manifest = 4,
 }
 
+// Agent fields chosen at creation (create_agent, create_indexed_agent)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AgentParams {
+    pub card_uri: String,
+    pub card_hash: [u8; 32],
+    pub has_staking: Option<bool>,  // Default = true
+    pub memory_mode: Option<u8>,
+    pub memory_ptr: Option<Vec<u8>>,
+    pub memory_hash: Option<[u8; 32]>,
+}
+
 #[program]
 pub mod agent_registry {
     use super::*;
@@ -50,84 +92,57 @@ pub mod agent_registry {
     // Card (uri + hash) is REQUIRED - every agent must have identity
     // has_staking defaults to true
     // memory can be set at creation (optional)
-    pub fn create_agent(
-        ctx: Context<CreateAgent>,
-        creator: Pubkey,
-        card_uri: String,           // ✅ OBLIGATOIRE
-        card_hash: [u8; 32],        // ✅ OBLIGATOIRE
-        has_staking: Option<bool>,  // ✅ OPTIONNEL (default = true)
-        memory_mode: Option<u8>,    // ✅ OPTIONNEL: memory mode (None, CID, IPFS, URL)
-        memory_ptr: Option<Vec<u8>>,// ✅ OPTIONNEL: memory pointer (CID, URL, etc.)
-        memory_hash: Option<[u8; 32]>, // ✅ OPTIONNEL: memory content hash
-    ) -> Result<()> {
+    pub fn create_agent(ctx: Context<CreateAgent>, creator: Pubkey, params: AgentParams) -> Result<()> {
         let agent = &mut ctx.accounts.agent;
-        agent.version = 1;
-        agent.creator = creator;    // Immutable: used in PDA seeds
-        agent.owner = creator;      // Mutable: initially = creator, can be transferred
-        
-        // Memory: optionnel, peut être défini à la création
-        if let Some(mode) = memory_mode {
-            if let Some(ptr) = &memory_ptr {
-                // Validate memory parameters
-                require!(ptr.len() > 0 && ptr.len() <= MAX_CID_LEN, AgentError::InvalidLength);
-                
-                // Validate mode value (0=None, 1=CID, 2=IPFS, 3=URL)
-                require!(mode <= 3, AgentError::InvalidMemoryFields);
-                
-                // Validate URL scheme for URL mode (3)
-                if mode == MemoryMode::Url as u8 {
-                    let url_str = core::str::from_utf8(ptr).map_err(|_| AgentError::InvalidMemoryFields)?;
-                    require!(url_str.starts_with("https://"), AgentError::InsecureUrl);
-                }
-                
-                agent.memory_mode = mode;
-                agent.memory_ptr_len = ptr.len() as u8;
-                write_fixed(&mut agent.memory_ptr, ptr);
-                agent.memory_hash = memory_hash.unwrap_or([0u8; 32]);
-            } else {
-                // Mode provided but no pointer → invalid
-                return Err(AgentError::InvalidMemoryFields.into());
-            }
-        } else {
-            // No memory: default None
-            agent.memory_mode = MemoryMode::None as u8;
-            agent.memory_ptr_len = 0;
-            agent.memory_ptr = [0u8; 96];
-            agent.memory_hash = [0u8; 32];
-        }
-        
-        // Card: OBLIGATOIRE (identité de l'agent)
-        let bytes = card_uri.as_bytes();
-        require!(bytes.len() > 0 && bytes.len() <= MAX_URI, AgentError::InvalidLength);
-        // Allow only https:// or ipfs:// schemes for card_uri
-        let ok_scheme = card_uri.starts_with("https://") || card_uri.starts_with("ipfs://");
-        require!(ok_scheme, AgentError::InsecureUrl);
-        agent.card_uri_len = bytes.len() as u8;
-        write_fixed(&mut agent.card_uri, bytes);
-        agent.card_hash = card_hash;
-        
-        // Flags: Active + has_staking (default true)
-        agent.flags = FLAG_ACTIVE;
-        if has_staking.unwrap_or(true) {  // Default = true
-            agent.flags |= FLAG_HAS_STAKING;
-        }
-        
+        init_agent(agent, creator, params)?;
         agent.bump = ctx.bumps.agent;
-        agent._padding = [0u8; 7];
         emit!(AgentCreated { creator, owner: creator });
         Ok(())
     }
 
+    // Initialize the per-creator counter used by create_indexed_agent (once per creator)
+    pub fn init_agent_counter(ctx: Context<InitAgentCounter>) -> Result<()> {
+        let counter = &mut ctx.accounts.agent_counter;
+        counter.creator = ctx.accounts.creator.key();
+        counter.count = 0;
+        counter.bump = ctx.bumps.agent_counter;
+        Ok(())
+    }
+
+    // Create an additional agent PDA: seeds = ["agent", creator, index (u32 LE)]
+    // index = next value of the creator's counter, so one wallet can register many agents.
+    // The legacy ["agent", creator] PDA from create_agent keeps working unchanged.
+    pub fn create_indexed_agent(ctx: Context<CreateIndexedAgent>, creator: Pubkey, params: AgentParams) -> Result<()> {
+        let counter = &mut ctx.accounts.agent_counter;
+        let index = counter.count;
+        counter.count = index.checked_add(1).ok_or(AgentError::CounterOverflow)?;
+
+        let agent = &mut ctx.accounts.agent;
+        init_agent(agent, creator, params)?;
+        agent.flags |= FLAG_INDEXED;
+        agent.agent_index = index.to_le_bytes();
+        agent.bump = ctx.bumps.agent;
+        emit!(IndexedAgentCreated { agent: agent.key(), creator, owner: creator, index });
+        Ok(())
+    }
+
     // Update card (URI + hash).
-    pub fn set_card(ctx: Context<UpdateAgent>, card_uri: String, card_hash: [u8; 32]) -> Result<()> {
+    pub fn set_card(ctx: Context<SetCard>, card_uri: String, card_hash: [u8; 32]) -> Result<()> {
+        authorize(&ctx.accounts.agent, &ctx.accounts.owner.key(), ctx.accounts.delegate.as_deref(), PERM_CARD)?;
         let agent = &mut ctx.accounts.agent;
         let bytes = card_uri.as_bytes();
-        require!(bytes.len() > 0 && bytes.len() <= MAX_URI, AgentError::InvalidLength);
+        require!(!bytes.is_empty() && bytes.len() <= MAX_URI, AgentError::InvalidLength);
         // Allow only https:// or ipfs:// schemes for card_uri
         let ok_scheme = card_uri.starts_with("https://") || card_uri.starts_with("ipfs://");
         require!(ok_scheme, AgentError::InsecureUrl);
-        agent.card_uri_len = bytes.len() as u8;
-        write_fixed(&mut agent.card_uri, bytes);
+        resize_agent(
+            &agent.to_account_info(),
+            &ctx.accounts.owner.to_account_info(),
+            rent_refund_account(agent, &ctx.accounts.owner, &ctx.accounts.agent_owner).as_ref(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + AgentRegistry::space(bytes.len(), agent.memory_ptr.len()),
+        )?;
+        agent.card_uri = bytes.to_vec();
         agent.card_hash = card_hash;
         let preview = preview_str(bytes);
         emit!(CardSet {
@@ -140,86 +155,20 @@ pub mod agent_registry {
 
     // Update memory (mode + pointer + hash according to truth table).
     pub fn set_memory(
-        ctx: Context<UpdateAgent>,
+        ctx: Context<SetMemory>,
         mode: u8,
         ptr: Vec<u8>,
         hash_opt: Option<[u8; 32]>,
     ) -> Result<()> {
-        let agent = &mut ctx.accounts.agent;
-        require!(agent.flags & FLAG_LOCKED == 0, AgentError::MemoryLocked);
-        require!(ptr.len() <= 96, AgentError::InvalidLength);
-
-        let mode = match mode {
-            0 => MemoryMode::None,
-            1 => MemoryMode::Cid,
-            2 => MemoryMode::Ipns,
-            3 => MemoryMode::Url,
-            4 => MemoryMode::Manifest,
-            _ => return err!(AgentError::InvalidMemoryFields),
-        };
-
-        let zero = [0u8; 32];
-        match mode {
-            MemoryMode::None => {
-                require!(ptr.is_empty(), AgentError::InvalidMemoryFields);
-                require!(hash_opt.unwrap_or(zero) == zero, AgentError::InvalidMemoryFields);
-                agent.memory_hash = zero;
-                agent.memory_ptr_len = 0;
-                agent.memory_ptr = [0u8; 96];
-            }
-            MemoryMode::Cid => {
-                require!(!ptr.is_empty(), AgentError::InvalidMemoryFields);
-                require!(hash_opt.unwrap_or(zero) == zero, AgentError::InvalidMemoryFields);
-                // Basic CID shape validation (CIDv1 base32 lowercase or CIDv0 base58btc)
-                // - v1: starts with "bafy", base32 [a-z2-7]
-                // - v0: starts with "Qm", base58btc [1-9A-HJ-NP-Za-km-z]
-                let is_cid_like = {
-                    if let Ok(s) = core::str::from_utf8(&ptr) {
-                        let bytes_ok = s.len() > 0 && s.len() <= MAX_CID_LEN;
-                        if s.starts_with("bafy") {
-                            bytes_ok && s.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'))
-                        } else if s.starts_with("Qm") {
-                            bytes_ok && s.chars().all(|c| match c {
-                                '1'..='9' | 'A'..='H' | 'J'..='N' | 'P'..='Z' | 'a'..='k' | 'm'..='z' => true,
-                                _ => false,
-                            }) && s.len() == 46
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                };
-                require!(is_cid_like, AgentError::InvalidMemoryFields);
-                agent.memory_hash = zero;
-            }
-            MemoryMode::Ipns | MemoryMode::Manifest | MemoryMode::Url => {
-                require!(!ptr.is_empty(), AgentError::InvalidMemoryFields);
-                let h = hash_opt.ok_or(AgentError::InvalidMemoryFields)?;
-                require!(h != zero, AgentError::InvalidMemoryFields);
-                if let MemoryMode::Url = mode {
-                    // Validate UTF-8 and enforce https:// scheme strictly
-                    let s = core::str::from_utf8(&ptr).map_err(|_| error!(AgentError::InvalidMemoryFields))?;
-                    require!(s.starts_with("https://"), AgentError::InsecureUrl);
-                }
-                agent.memory_hash = h;
-            }
-        }
-
-        agent.memory_mode = mode as u8;
-        if !matches!(mode, MemoryMode::None) {
-            agent.memory_ptr_len = ptr.len() as u8;
-            write_fixed(&mut agent.memory_ptr, &ptr);
-        }
-
-        let preview = preview_str(&ptr);
-        emit!(MemoryUpdated {
-            creator: agent.creator,
-            mode: mode as u8,
-            ptr_preview: preview,
-            hash: agent.memory_hash,
-        });
-        Ok(())
+        authorize(&ctx.accounts.agent, &ctx.accounts.owner.key(), ctx.accounts.delegate.as_deref(), PERM_MEMORY)?;
+        resize_agent(
+            &ctx.accounts.agent.to_account_info(),
+            &ctx.accounts.owner.to_account_info(),
+            rent_refund_account(&ctx.accounts.agent, &ctx.accounts.owner, &ctx.accounts.agent_owner).as_ref(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + AgentRegistry::space(ctx.accounts.agent.card_uri.len(), ptr.len()),
+        )?;
+        apply_memory_update(&mut ctx.accounts.agent, mode, &ptr, hash_opt)
     }
 
     // Permanently lock memory.
@@ -231,7 +180,8 @@ pub mod agent_registry {
     }
 
     // Toggle the ACTIVE flag.
-    pub fn set_active(ctx: Context<UpdateAgent>, is_active: bool) -> Result<()> {
+    pub fn set_active(ctx: Context<DelegatedUpdate>, is_active: bool) -> Result<()> {
+        authorize(&ctx.accounts.agent, &ctx.accounts.owner.key(), ctx.accounts.delegate.as_deref(), PERM_ACTIVE)?;
         let agent = &mut ctx.accounts.agent;
         if is_active {
             agent.flags |= FLAG_ACTIVE;
@@ -251,25 +201,218 @@ pub mod agent_registry {
         Ok(())
     }
 
+    // Clear FLAG_HAS_STAKING so the agent can be closed; the staking pool must
+    // never have been created or already be closed (close_staking_pool).
+    pub fn disable_staking(ctx: Context<DisableStaking>) -> Result<()> {
+        require!(ctx.accounts.staking_pool.data_is_empty(), AgentError::StakingPoolExists);
+        let agent = &mut ctx.accounts.agent;
+        agent.flags &= !FLAG_HAS_STAKING;
+        emit!(StakingDisabled { creator: agent.creator });
+        Ok(())
+    }
+
     // Transfer ownership to a new owner (creator remains immutable)
-    pub fn transfer_owner(ctx: Context<UpdateAgent>, new_owner: Pubkey) -> Result<()> {
+    pub fn transfer_owner(ctx: Context<TransferOwner>, new_owner: Pubkey) -> Result<()> {
+        require!(new_owner != Pubkey::default(), AgentError::InvalidOwner);
+        // A proposal made before the transfer must not become acceptable again if ownership
+        // comes back to its proposer: close it, rent back to the proposer
+        let info = ctx.accounts.pending_owner.to_account_info();
+        if !info.data_is_empty() {
+            let pending = PendingOwner::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            let proposer = ctx.accounts.proposer.as_ref().ok_or(AgentError::ProposerRequired)?;
+            require_keys_eq!(proposer.key(), pending.proposer, AgentError::Unauthorized);
+            **proposer.try_borrow_mut_lamports()? += info.lamports();
+            **info.try_borrow_mut_lamports()? = 0;
+            info.assign(&System::id());
+            info.resize(0)?;
+            emit!(OwnerTransferCancelled { agent: pending.agent, pending_owner: pending.pending_owner });
+        }
+        let agent = &mut ctx.accounts.agent;
+        let old_owner = agent.owner;
+        agent.owner = new_owner;
+        emit!(OwnerTransferred { creator: agent.creator, old_owner, new_owner });
+        Ok(())
+    }
+
+    // Two-step transfer, step 1: record a pending owner (optionally expiring at a unix timestamp)
+    pub fn propose_owner(ctx: Context<ProposeOwner>, new_owner: Pubkey, expires_at: Option<i64>) -> Result<()> {
         require!(new_owner != Pubkey::default(), AgentError::InvalidOwner);
+        let expires_at = expires_at.unwrap_or(0);
+        if expires_at != 0 {
+            require!(expires_at > Clock::get()?.unix_timestamp, AgentError::InvalidExpiry);
+        }
+        let pending = &mut ctx.accounts.pending_owner;
+        pending.agent = ctx.accounts.agent.key();
+        pending.proposer = ctx.accounts.owner.key();
+        pending.pending_owner = new_owner;
+        pending.expires_at = expires_at;
+        pending.bump = ctx.bumps.pending_owner;
+        emit!(OwnerTransferProposed { agent: pending.agent, owner: pending.proposer, pending_owner: new_owner, expires_at });
+        Ok(())
+    }
+
+    // Two-step transfer, step 2: the pending owner signs to take ownership
+    pub fn accept_owner(ctx: Context<AcceptOwner>) -> Result<()> {
+        let pending = &ctx.accounts.pending_owner;
         let agent = &mut ctx.accounts.agent;
+        // Proposal made by a previous owner is void
+        require_keys_eq!(pending.proposer, agent.owner, AgentError::StaleOwnerTransfer);
+        if pending.expires_at != 0 {
+            require!(Clock::get()?.unix_timestamp <= pending.expires_at, AgentError::OwnerTransferExpired);
+        }
         let old_owner = agent.owner;
+        let new_owner = pending.pending_owner;
         agent.owner = new_owner;
+        emit!(OwnerTransferAccepted { agent: agent.key(), old_owner, new_owner });
         emit!(OwnerTransferred { creator: agent.creator, old_owner, new_owner });
         Ok(())
     }
+
+    // Drop a pending transfer (current owner only); rent goes back to the proposer
+    pub fn cancel_owner_transfer(ctx: Context<CancelOwnerTransfer>) -> Result<()> {
+        let pending = &ctx.accounts.pending_owner;
+        emit!(OwnerTransferCancelled { agent: pending.agent, pending_owner: pending.pending_owner });
+        Ok(())
+    }
+
+    // Grant an operator key scoped rights (PERM_* bitmask) on set_card / set_memory / set_active
+    pub fn add_delegate(
+        ctx: Context<AddDelegate>,
+        delegate_key: Pubkey,
+        permissions: u8,
+        expires_at: Option<i64>,
+    ) -> Result<()> {
+        let expires_at = validate_delegate_params(permissions, expires_at)?;
+        let d = &mut ctx.accounts.delegate;
+        d.agent = ctx.accounts.agent.key();
+        d.delegate = delegate_key;
+        d.granted_by = ctx.accounts.owner.key();
+        d.permissions = permissions;
+        d.expires_at = expires_at;
+        d.bump = ctx.bumps.delegate;
+        emit!(DelegateSet { agent: d.agent, delegate: delegate_key, permissions, expires_at });
+        Ok(())
+    }
+
+    // Change the permissions / expiry of an existing delegate
+    pub fn update_delegate(ctx: Context<UpdateDelegate>, permissions: u8, expires_at: Option<i64>) -> Result<()> {
+        let expires_at = validate_delegate_params(permissions, expires_at)?;
+        let d = &mut ctx.accounts.delegate;
+        d.granted_by = ctx.accounts.owner.key();
+        d.permissions = permissions;
+        d.expires_at = expires_at;
+        emit!(DelegateSet { agent: d.agent, delegate: d.delegate, permissions, expires_at });
+        Ok(())
+    }
+
+    // Revoke a delegate and reclaim its rent
+    pub fn remove_delegate(ctx: Context<RemoveDelegate>) -> Result<()> {
+        let d = &ctx.accounts.delegate;
+        emit!(DelegateRemoved { agent: d.agent, delegate: d.delegate });
+        Ok(())
+    }
+
+    // Memory update authorised by an Ed25519 signature from the key bound with bind_agent_key.
+    // Any relayer may submit it (and pay fees); the preceding instruction must be the Ed25519
+    // precompile over signed_memory_message(); the nonce on the AgentKey account prevents replay.
+    pub fn set_memory_signed(
+        ctx: Context<SetMemorySigned>,
+        mode: u8,
+        ptr: Vec<u8>,
+        hash_opt: Option<[u8; 32]>,
+        nonce: u64,
+    ) -> Result<()> {
+        let binding = &mut ctx.accounts.agent_key;
+        require!(nonce == binding.nonce, AgentError::InvalidNonce);
+
+        let agent_key = ctx.accounts.agent.key();
+        let signer = binding.key;
+        let message = signed_memory_message(&agent_key, nonce, mode, &ptr, &hash_opt.unwrap_or([0u8; 32]));
+        verify_ed25519_ix(&ctx.accounts.instructions, &signer, &message)?;
+        binding.nonce = nonce.checked_add(1).ok_or(AgentError::InvalidNonce)?;
+
+        resize_agent(
+            &ctx.accounts.agent.to_account_info(),
+            &ctx.accounts.relayer.to_account_info(),
+            rent_refund_account(&ctx.accounts.agent, &ctx.accounts.relayer, &ctx.accounts.agent_owner).as_ref(),
+            &ctx.accounts.system_program.to_account_info(),
+            8 + AgentRegistry::space(ctx.accounts.agent.card_uri.len(), ptr.len()),
+        )?;
+        apply_memory_update(&mut ctx.accounts.agent, mode, &ptr, hash_opt)?;
+        emit!(SignedMemoryUpdate { agent: agent_key, signer, nonce });
+        Ok(())
+    }
+
+    // Bind the keypair the agent runs with; both the owner and the agent key must sign
+    // (proof of possession). One key per agent: seeds = ["agent_key", agent]
+    pub fn bind_agent_key(ctx: Context<BindAgentKey>) -> Result<()> {
+        let binding = &mut ctx.accounts.agent_key;
+        binding.agent = ctx.accounts.agent.key();
+        binding.key = ctx.accounts.runtime_key.key();
+        binding.bound_at = Clock::get()?.unix_timestamp;
+        binding.nonce = 0;
+        binding.bump = ctx.bumps.agent_key;
+        emit!(AgentKeyBound { agent: binding.agent, key: binding.key });
+        Ok(())
+    }
+
+    // Replace the bound key; the new key must sign, the old one does not have to (may be lost)
+    pub fn rotate_agent_key(ctx: Context<RotateAgentKey>) -> Result<()> {
+        let binding = &mut ctx.accounts.agent_key;
+        let old_key = binding.key;
+        let new_key = ctx.accounts.new_key.key();
+        require_keys_neq!(old_key, new_key, AgentError::InvalidAgentKey);
+        binding.key = new_key;
+        binding.bound_at = Clock::get()?.unix_timestamp;
+        emit!(AgentKeyRotated { agent: binding.agent, old_key, new_key });
+        Ok(())
+    }
+
+    // Remove the binding and reclaim its rent
+    pub fn revoke_agent_key(ctx: Context<RevokeAgentKey>) -> Result<()> {
+        let binding = &ctx.accounts.agent_key;
+        emit!(AgentKeyRevoked { agent: binding.agent, key: binding.key });
+        Ok(())
+    }
+
+    // Upgrade an agent written with an older layout to AGENT_REGISTRY_VERSION in place.
+    // The account is taken unchecked since older layouts no longer deserialize as AgentRegistry.
+    pub fn migrate_agent(ctx: Context<MigrateAgent>) -> Result<()> {
+        let info = ctx.accounts.agent.to_account_info();
+        let owner = &ctx.accounts.owner;
+
+        let (from_version, migrated) = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() > 8 && data[..8] == *AgentRegistry::DISCRIMINATOR,
+                AgentError::UnsupportedVersion
+            );
+            match data[8] {
+                1 => (1u8, AgentRegistryV1::deserialize(&mut &data[8..])?.upgrade()),
+                AGENT_REGISTRY_VERSION => return err!(AgentError::AlreadyMigrated),
+                _ => return err!(AgentError::UnsupportedVersion),
+            }
+        };
+        require_keys_eq!(migrated.owner, owner.key(), AgentError::Unauthorized);
+
+        let new_len = 8 + AgentRegistry::space(migrated.card_uri.len(), migrated.memory_ptr.len());
+        let owner_info = owner.to_account_info();
+        resize_agent(&info, &owner_info, Some(&owner_info), &ctx.accounts.system_program.to_account_info(), new_len)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        emit!(AgentMigrated { agent: info.key(), from_version, to_version: AGENT_REGISTRY_VERSION });
+        Ok(())
+    }
 }
 
 // Accounts
 #[derive(Accounts)]
-#[instruction(creator: Pubkey)]
+#[instruction(creator: Pubkey, params: AgentParams)]
 pub struct CreateAgent<'info> {
     #[account(
         init,
         payer = creator_signer,
-        space = 8 + AGENT_REGISTRY_SPACE,
+        space = 8 + AgentRegistry::space(params.card_uri.len(), params.memory_ptr.as_ref().map_or(0, |p| p.len())),
         seeds = [b"agent", creator.as_ref()],
         bump
     )]
@@ -282,25 +425,151 @@ pub struct CreateAgent<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct InitAgentCounter<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + AGENT_COUNTER_SPACE,
+        seeds = [b"agent_counter", creator.key().as_ref()],
+        bump
+    )]
+    pub agent_counter: Account<'info, AgentCounter>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(creator: Pubkey, params: AgentParams)]
+pub struct CreateIndexedAgent<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent_counter", creator.as_ref()],
+        bump = agent_counter.bump
+    )]
+    pub agent_counter: Account<'info, AgentCounter>,
+    #[account(
+        init,
+        payer = creator_signer,
+        space = 8 + AgentRegistry::space(params.card_uri.len(), params.memory_ptr.as_ref().map_or(0, |p| p.len())),
+        seeds = [b"agent", creator.as_ref(), &agent_counter.count.to_le_bytes()],
+        bump
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        constraint = creator_signer.key() == creator @ AgentError::Unauthorized
+    )]
+    pub creator_signer: Signer<'info>,  // Must be the creator (pays and signs)
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct UpdateAgent<'info> {
     #[account(
         mut,
-        seeds = [b"agent", agent.creator.as_ref()],
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
         bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
         constraint = agent.owner == owner.key() @ AgentError::Unauthorized
     )]
     pub agent: Account<'info, AgentRegistry>,
     pub owner: Signer<'info>,  // Only the current owner can modify the agent
 }
 
+// transfer_owner: also closes a pending two-step proposal, if any
+#[derive(Accounts)]
+pub struct TransferOwner<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    /// CHECK: pending_owner PDA of the agent, closed if it holds a proposal
+    #[account(mut, seeds = [b"pending_owner", agent.key().as_ref()], bump)]
+    pub pending_owner: UncheckedAccount<'info>,
+    /// CHECK: proposer of the pending proposal, gets its rent back - checked in the handler
+    #[account(mut)]
+    pub proposer: Option<UncheckedAccount<'info>>,  // Required only when a proposal is pending
+    pub owner: Signer<'info>,
+}
+
+// set_active (and set_card / set_memory below): owner, or a delegate with the matching PERM_* bit
+#[derive(Accounts)]
+pub struct DelegatedUpdate<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"delegate", agent.key().as_ref(), owner.key().as_ref()],
+        bump = delegate.bump
+    )]
+    pub delegate: Option<Account<'info, Delegate>>,  // Required only when the signer is not the owner
+    pub owner: Signer<'info>,  // Current owner or delegate key (checked in authorize)
+}
+
+// set_card / set_memory resize the account to the new URI length: the signer pays for growth,
+// and the rent freed when it shrinks goes to the agent owner (agent_owner when a delegate signs).
+#[derive(Accounts)]
+pub struct SetCard<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"delegate", agent.key().as_ref(), owner.key().as_ref()],
+        bump = delegate.bump
+    )]
+    pub delegate: Option<Account<'info, Delegate>>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: agent owner, receives the rent freed when the account shrinks (optional when the owner signs)
+    #[account(mut, address = agent.owner @ AgentError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetMemory<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"delegate", agent.key().as_ref(), owner.key().as_ref()],
+        bump = delegate.bump
+    )]
+    pub delegate: Option<Account<'info, Delegate>>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: agent owner, receives the rent freed when the account shrinks (optional when the owner signs)
+    #[account(mut, address = agent.owner @ AgentError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct CloseAgent<'info> {
     #[account(
         mut,
         close = recipient,
-        seeds = [b"agent", agent.creator.as_ref()],
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
         bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
         constraint = agent.owner == owner.key() @ AgentError::Unauthorized
     )]
     pub agent: Account<'info, AgentRegistry>,
@@ -311,6 +580,248 @@ pub struct CloseAgent<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct DisableStaking<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    /// CHECK: agent_staking pool PDA of the agent, must hold no data
+    #[account(seeds = [b"staking_pool", agent.key().as_ref()], bump, seeds::program = AGENT_STAKING_ID)]
+    pub staking_pool: UncheckedAccount<'info>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeOwner<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + PENDING_OWNER_SPACE,
+        seeds = [b"pending_owner", agent.key().as_ref()],
+        bump
+    )]
+    pub pending_owner: Account<'info, PendingOwner>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOwner<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [b"pending_owner", agent.key().as_ref()],
+        bump = pending_owner.bump,
+        constraint = pending_owner.pending_owner == new_owner.key() @ AgentError::Unauthorized
+    )]
+    pub pending_owner: Account<'info, PendingOwner>,
+    pub new_owner: Signer<'info>,  // Must be the proposed owner
+    /// CHECK: proposer gets the proposal rent back
+    #[account(mut, address = pending_owner.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOwnerTransfer<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        close = proposer,
+        seeds = [b"pending_owner", agent.key().as_ref()],
+        bump = pending_owner.bump
+    )]
+    pub pending_owner: Account<'info, PendingOwner>,
+    pub owner: Signer<'info>,
+    /// CHECK: proposer gets the proposal rent back
+    #[account(mut, address = pending_owner.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(delegate_key: Pubkey)]
+pub struct AddDelegate<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + DELEGATE_SPACE,
+        seeds = [b"delegate", agent.key().as_ref(), delegate_key.as_ref()],
+        bump
+    )]
+    pub delegate: Account<'info, Delegate>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDelegate<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"delegate", agent.key().as_ref(), delegate.delegate.as_ref()],
+        bump = delegate.bump
+    )]
+    pub delegate: Account<'info, Delegate>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveDelegate<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"delegate", agent.key().as_ref(), delegate.delegate.as_ref()],
+        bump = delegate.bump
+    )]
+    pub delegate: Account<'info, Delegate>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMemorySigned<'info> {
+    #[account(
+        mut,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"agent_key", agent.key().as_ref()],
+        bump = agent_key.bump
+    )]
+    pub agent_key: Account<'info, AgentKey>,  // Its key must have signed the update
+    #[account(mut)]
+    pub relayer: Signer<'info>,  // Submits the tx and pays for any resize; not an authority
+    /// CHECK: instructions sysvar, read to find the Ed25519 verification
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    /// CHECK: agent owner, receives the rent freed when the account shrinks (optional when the owner signs)
+    #[account(mut, address = agent.owner @ AgentError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BindAgentKey<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + AGENT_KEY_SPACE,
+        seeds = [b"agent_key", agent.key().as_ref()],
+        bump
+    )]
+    pub agent_key: Account<'info, AgentKey>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub runtime_key: Signer<'info>,  // Agent operational key (proves possession)
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RotateAgentKey<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"agent_key", agent.key().as_ref()],
+        bump = agent_key.bump
+    )]
+    pub agent_key: Account<'info, AgentKey>,
+    pub owner: Signer<'info>,
+    pub new_key: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAgentKey<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ AgentError::UnsupportedVersion,
+        constraint = agent.owner == owner.key() @ AgentError::Unauthorized
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"agent_key", agent.key().as_ref()],
+        bump = agent_key.bump
+    )]
+    pub agent_key: Account<'info, AgentKey>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateAgent<'info> {
+    /// CHECK: raw agent account in a previous layout; discriminator, version and owner are checked in the handler
+    #[account(mut, owner = crate::ID)]
+    pub agent: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 // Account data
 // creator = immutable (used in PDA seeds)
 // owner = mutable (can be transferred)
@@ -320,6 +831,40 @@ pub struct AgentRegistry {
     pub creator: Pubkey,  // Immutable: original creator (used in PDA seeds)
     pub owner: Pubkey,    // Mutable: current owner (can be transferred)
     pub memory_mode: u8,
+    pub memory_hash: [u8; 32],
+    pub card_hash: [u8; 32],
+    pub flags: u32,
+    pub bump: u8,
+    pub agent_index: [u8; 4], // u32 LE, only meaningful with FLAG_INDEXED
+    // Variable-length tail (v2): kept last so every fixed field has a stable offset
+    pub card_uri: Vec<u8>,
+    pub memory_ptr: Vec<u8>,
+}
+
+impl AgentRegistry {
+    // v2 account size (without discriminator) for the given URI lengths
+    pub fn space(card_uri_len: usize, memory_ptr_len: usize) -> usize {
+        AGENT_REGISTRY_BASE_SPACE_V2 + card_uri_len + memory_ptr_len
+    }
+
+    // Extra PDA seed after ["agent", creator]: the index for indexed agents, empty otherwise.
+    // An empty seed does not change the derived address, so legacy agents keep their PDA.
+    pub fn index_seed(&self) -> &[u8] {
+        if self.flags & FLAG_INDEXED != 0 {
+            &self.agent_index
+        } else {
+            &[]
+        }
+    }
+}
+
+// Frozen v1 layout (AGENT_REGISTRY_SPACE_V1), only read by migrate_agent
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct AgentRegistryV1 {
+    pub version: u8,
+    pub creator: Pubkey,
+    pub owner: Pubkey,
+    pub memory_mode: u8,
     pub memory_ptr_len: u8,
     pub memory_ptr: [u8; 96],
     pub memory_hash: [u8; 32],
@@ -328,7 +873,66 @@ pub struct AgentRegistry {
     pub card_hash: [u8; 32],
     pub flags: u32,
     pub bump: u8,
-    pub _padding: [u8; 7],
+    pub agent_index: [u8; 4],
+    pub _padding: [u8; 3],
+}
+
+impl AgentRegistryV1 {
+    pub fn upgrade(self) -> AgentRegistry {
+        AgentRegistry {
+            version: AGENT_REGISTRY_VERSION,
+            creator: self.creator,
+            owner: self.owner,
+            memory_mode: self.memory_mode,
+            memory_hash: self.memory_hash,
+            card_hash: self.card_hash,
+            flags: self.flags,
+            bump: self.bump,
+            agent_index: self.agent_index,
+            card_uri: self.card_uri[..(self.card_uri_len as usize).min(96)].to_vec(),
+            memory_ptr: self.memory_ptr[..(self.memory_ptr_len as usize).min(96)].to_vec(),
+        }
+    }
+}
+
+// Per-creator counter for indexed agents: seeds = ["agent_counter", creator]
+#[account]
+pub struct AgentCounter {
+    pub creator: Pubkey,
+    pub count: u32,  // Next index handed out by create_indexed_agent
+    pub bump: u8,
+}
+
+// Pending two-step ownership transfer: seeds = ["pending_owner", agent]
+#[account]
+pub struct PendingOwner {
+    pub agent: Pubkey,
+    pub proposer: Pubkey,  // Owner at proposal time (refunded on close)
+    pub pending_owner: Pubkey,
+    pub expires_at: i64,  // 0 = no expiry
+    pub bump: u8,
+}
+
+// Operator key allowed to perform scoped updates: seeds = ["delegate", agent, delegate]
+#[account]
+pub struct Delegate {
+    pub agent: Pubkey,
+    pub delegate: Pubkey,
+    pub granted_by: Pubkey,  // Owner at grant time; delegation lapses when ownership changes
+    pub permissions: u8,  // PERM_* bitmask
+    pub expires_at: i64,  // 0 = no expiry
+    pub bump: u8,
+}
+
+// Runtime keypair bound to an agent: seeds = ["agent_key", agent]
+// Kept outside AgentRegistry so the fixed agent layout stays unchanged.
+#[account]
+pub struct AgentKey {
+    pub agent: Pubkey,
+    pub key: Pubkey,  // Operational pubkey the agent signs with
+    pub bound_at: i64,
+    pub nonce: u64,  // Next nonce expected by set_memory_signed
+    pub bump: u8,
 }
 
 // Events
@@ -338,6 +942,14 @@ pub struct AgentCreated {
     pub owner: Pubkey,    // Initial owner (= creator)
 }
 
+#[event]
+pub struct IndexedAgentCreated {
+    pub agent: Pubkey,    // PDA ["agent", creator, index]
+    pub creator: Pubkey,
+    pub owner: Pubkey,
+    pub index: u32,
+}
+
 #[event]
 pub struct CardSet {
     pub creator: Pubkey,
@@ -369,6 +981,11 @@ pub struct AgentClosed {
     pub creator: Pubkey,
 }
 
+#[event]
+pub struct StakingDisabled {
+    pub creator: Pubkey,
+}
+
 #[event]
 pub struct OwnerTransferred {
     pub creator: Pubkey,   // Immutable creator
@@ -376,6 +993,74 @@ pub struct OwnerTransferred {
     pub new_owner: Pubkey,
 }
 
+#[event]
+pub struct OwnerTransferProposed {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct OwnerTransferAccepted {
+    pub agent: Pubkey,
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+#[event]
+pub struct OwnerTransferCancelled {
+    pub agent: Pubkey,
+    pub pending_owner: Pubkey,
+}
+
+#[event]
+pub struct DelegateSet {
+    pub agent: Pubkey,
+    pub delegate: Pubkey,
+    pub permissions: u8,
+    pub expires_at: i64,
+}
+
+#[event]
+pub struct DelegateRemoved {
+    pub agent: Pubkey,
+    pub delegate: Pubkey,
+}
+
+#[event]
+pub struct SignedMemoryUpdate {
+    pub agent: Pubkey,
+    pub signer: Pubkey,
+    pub nonce: u64,
+}
+
+#[event]
+pub struct AgentKeyBound {
+    pub agent: Pubkey,
+    pub key: Pubkey,
+}
+
+#[event]
+pub struct AgentKeyRotated {
+    pub agent: Pubkey,
+    pub old_key: Pubkey,
+    pub new_key: Pubkey,
+}
+
+#[event]
+pub struct AgentKeyRevoked {
+    pub agent: Pubkey,
+    pub key: Pubkey,
+}
+
+#[event]
+pub struct AgentMigrated {
+    pub agent: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+}
+
 // Errors
 #[error_code]
 pub enum AgentError {
@@ -399,20 +1084,265 @@ pub enum AgentError {
     InsecureUrl,
     #[msg("Account already initialized")]
     AlreadyInitialized,
+    #[msg("Agent counter overflow")]
+    CounterOverflow,
+    #[msg("Invalid expiry timestamp")]
+    InvalidExpiry,
+    #[msg("Pending ownership transfer has expired")]
+    OwnerTransferExpired,
+    #[msg("Pending ownership transfer was proposed by a previous owner")]
+    StaleOwnerTransfer,
+    #[msg("Invalid delegate permissions")]
+    InvalidPermissions,
+    #[msg("Delegate lacks the required permission")]
+    MissingPermission,
+    #[msg("Delegate has expired")]
+    DelegateExpired,
+    #[msg("Missing Ed25519 signature verification instruction")]
+    MissingEd25519Instruction,
+    #[msg("Ed25519 signature does not match the expected signer or message")]
+    InvalidSignature,
+    #[msg("Invalid or already used nonce")]
+    InvalidNonce,
+    #[msg("Invalid agent key")]
+    InvalidAgentKey,
+    #[msg("Unsupported agent account version, run migrate_agent")]
+    UnsupportedVersion,
+    #[msg("Agent account already uses the current layout")]
+    AlreadyMigrated,
+    #[msg("Staking pool still exists, close it first")]
+    StakingPoolExists,
+    #[msg("Proposer of the pending owner transfer is required")]
+    ProposerRequired,
 }
 
 // Helpers
-fn write_fixed<const N: usize>(dst: &mut [u8; N], src: &[u8]) {
-    let len = core::cmp::min(N, src.len());
-    dst[..len].copy_from_slice(&src[..len]);
-    if len < N {
-        for b in &mut dst[len..] {
-            *b = 0;
+fn preview_str(bytes: &[u8]) -> String {
+    let take = core::cmp::min(32, bytes.len());
+    String::from_utf8_lossy(&bytes[..take]).to_string()
+}
+
+// Owner always passes; any other signer needs a live delegate PDA holding `perm`
+fn authorize(agent: &AgentRegistry, signer: &Pubkey, delegate: Option<&Delegate>, perm: u8) -> Result<()> {
+    if *signer == agent.owner {
+        return Ok(());
+    }
+    let d = delegate.ok_or(AgentError::Unauthorized)?;
+    require_keys_eq!(d.granted_by, agent.owner, AgentError::Unauthorized);
+    require!(d.permissions & perm != 0, AgentError::MissingPermission);
+    if d.expires_at != 0 {
+        require!(Clock::get()?.unix_timestamp <= d.expires_at, AgentError::DelegateExpired);
+    }
+    Ok(())
+}
+
+fn validate_delegate_params(permissions: u8, expires_at: Option<i64>) -> Result<i64> {
+    require!(permissions != 0 && permissions & !PERM_ALL == 0, AgentError::InvalidPermissions);
+    let expires_at = expires_at.unwrap_or(0);
+    if expires_at != 0 {
+        require!(expires_at > Clock::get()?.unix_timestamp, AgentError::InvalidExpiry);
+    }
+    Ok(expires_at)
+}
+
+// Memory truth table shared by set_memory and set_memory_signed
+fn apply_memory_update(agent: &mut AgentRegistry, mode: u8, ptr: &[u8], hash_opt: Option<[u8; 32]>) -> Result<()> {
+    require!(agent.flags & FLAG_LOCKED == 0, AgentError::MemoryLocked);
+    require!(ptr.len() <= MAX_CID_LEN, AgentError::InvalidLength);
+
+    let mode = match mode {
+        0 => MemoryMode::None,
+        1 => MemoryMode::Cid,
+        2 => MemoryMode::Ipns,
+        3 => MemoryMode::Url,
+        4 => MemoryMode::Manifest,
+        _ => return err!(AgentError::InvalidMemoryFields),
+    };
+
+    let zero = [0u8; 32];
+    match mode {
+        MemoryMode::None => {
+            require!(ptr.is_empty(), AgentError::InvalidMemoryFields);
+            require!(hash_opt.unwrap_or(zero) == zero, AgentError::InvalidMemoryFields);
+            agent.memory_hash = zero;
+            agent.memory_ptr = Vec::new();
+        }
+        MemoryMode::Cid => {
+            require!(!ptr.is_empty(), AgentError::InvalidMemoryFields);
+            require!(hash_opt.unwrap_or(zero) == zero, AgentError::InvalidMemoryFields);
+            // Basic CID shape validation (CIDv1 base32 lowercase or CIDv0 base58btc)
+            // - v1: starts with "bafy", base32 [a-z2-7]
+            // - v0: starts with "Qm", base58btc [1-9A-HJ-NP-Za-km-z]
+            let is_cid_like = {
+                if let Ok(s) = core::str::from_utf8(ptr) {
+                    let bytes_ok = !s.is_empty() && s.len() <= MAX_CID_LEN;
+                    if s.starts_with("bafy") {
+                        bytes_ok && s.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'))
+                    } else if s.starts_with("Qm") {
+                        bytes_ok && s.chars().all(|c| matches!(c, '1'..='9' | 'A'..='H' | 'J'..='N' | 'P'..='Z' | 'a'..='k' | 'm'..='z')) && s.len() == 46
+                    } else {
+                        false
+                    }
+                } else {
+                    false
+                }
+            };
+            require!(is_cid_like, AgentError::InvalidMemoryFields);
+            agent.memory_hash = zero;
         }
+        MemoryMode::Ipns | MemoryMode::Manifest | MemoryMode::Url => {
+            require!(!ptr.is_empty(), AgentError::InvalidMemoryFields);
+            let h = hash_opt.ok_or(AgentError::InvalidMemoryFields)?;
+            require!(h != zero, AgentError::InvalidMemoryFields);
+            if let MemoryMode::Url = mode {
+                // Validate UTF-8 and enforce https:// scheme strictly
+                let s = core::str::from_utf8(ptr).map_err(|_| error!(AgentError::InvalidMemoryFields))?;
+                require!(s.starts_with("https://"), AgentError::InsecureUrl);
+            }
+            agent.memory_hash = h;
+        }
+    }
+
+    agent.memory_mode = mode as u8;
+    if !matches!(mode, MemoryMode::None) {
+        agent.memory_ptr = ptr.to_vec();
     }
+
+    let preview = preview_str(ptr);
+    emit!(MemoryUpdated {
+        creator: agent.creator,
+        mode: mode as u8,
+        ptr_preview: preview,
+        hash: agent.memory_hash,
+    });
+    Ok(())
 }
 
-fn preview_str(bytes: &[u8]) -> String {
-    let take = core::cmp::min(32, bytes.len());
-    String::from_utf8_lossy(&bytes[..take]).to_string()
+// Bytes the registered delegate key signs for set_memory_signed
+pub fn signed_memory_message(agent: &Pubkey, nonce: u64, mode: u8, ptr: &[u8], hash: &[u8; 32]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(SIGNED_MEMORY_DOMAIN.len() + 32 + 8 + 1 + 32 + ptr.len());
+    msg.extend_from_slice(SIGNED_MEMORY_DOMAIN);
+    msg.extend_from_slice(agent.as_ref());
+    msg.extend_from_slice(&nonce.to_le_bytes());
+    msg.push(mode);
+    msg.extend_from_slice(hash);
+    msg.extend_from_slice(ptr);
+    msg
+}
+
+// The instruction right before this one must be an Ed25519 precompile call verifying
+// exactly one signature by `signer` over `message`, with all offsets pointing into itself.
+fn verify_ed25519_ix(ix_sysvar: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    let current = load_current_index_checked(ix_sysvar)?;
+    require!(current > 0, AgentError::MissingEd25519Instruction);
+    let ix = load_instruction_at_checked((current - 1) as usize, ix_sysvar)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, AgentError::MissingEd25519Instruction);
+
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, AgentError::InvalidSignature);
+    let read = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    // Offsets entry: sig(2) sig_ix(4) pubkey(6) pubkey_ix(8) msg(10) msg_len(12) msg_ix(14)
+    require!(
+        read(4) == u16::MAX && read(8) == u16::MAX && read(14) == u16::MAX,
+        AgentError::InvalidSignature
+    );
+    let (pk_off, msg_off, msg_len) = (read(6) as usize, read(10) as usize, read(12) as usize);
+    let pk = data.get(pk_off..pk_off + 32).ok_or(AgentError::InvalidSignature)?;
+    let msg = data.get(msg_off..msg_off + msg_len).ok_or(AgentError::InvalidSignature)?;
+    require!(pk == signer.as_ref() && msg == message, AgentError::InvalidSignature);
+    Ok(())
+}
+
+// Resize an agent account: `payer` covers growth, and rent freed by shrinking goes to `refund_to`.
+// Without a refund account the surplus stays in the account for a later resize or close.
+fn resize_agent<'info>(
+    info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    refund_to: Option<&AccountInfo<'info>>,
+    system_program: &AccountInfo<'info>,
+    new_len: usize,
+) -> Result<()> {
+    let required = Rent::get()?.minimum_balance(new_len);
+    let current = info.lamports();
+    if current > required {
+        if let Some(refund_to) = refund_to {
+            **info.try_borrow_mut_lamports()? -= current - required;
+            **refund_to.try_borrow_mut_lamports()? += current - required;
+        }
+    } else if required > current {
+        let ix = anchor_lang::solana_program::system_instruction::transfer(payer.key, info.key, required - current);
+        anchor_lang::solana_program::program::invoke(&ix, &[payer.clone(), info.clone(), system_program.clone()])?;
+    }
+    info.resize(new_len)?;
+    Ok(())
 }
+
+// Where rent freed by shrinking an agent goes: the signer when it is the owner, otherwise the
+// owner account if the caller passed it (never a delegate or relayer)
+fn rent_refund_account<'info>(
+    agent: &AgentRegistry,
+    signer: &Signer<'info>,
+    agent_owner: &Option<UncheckedAccount<'info>>,
+) -> Option<AccountInfo<'info>> {
+    if signer.key() == agent.owner {
+        Some(signer.to_account_info())
+    } else {
+        agent_owner.as_ref().map(|owner| owner.to_account_info())
+    }
+}
+
+// Shared initialization for create_agent / create_indexed_agent (bump is set by the caller)
+fn init_agent(agent: &mut AgentRegistry, creator: Pubkey, params: AgentParams) -> Result<()> {
+    let AgentParams { card_uri, card_hash, has_staking, memory_mode, memory_ptr, memory_hash } = params;
+    agent.version = AGENT_REGISTRY_VERSION;
+    agent.creator = creator;    // Immutable: used in PDA seeds
+    agent.owner = creator;      // Mutable: initially = creator, can be transferred
+    
+    // Memory: optionnel, peut être défini à la création
+    if let Some(mode) = memory_mode {
+        if let Some(ptr) = &memory_ptr {
+            // Validate memory parameters
+            require!(!ptr.is_empty() && ptr.len() <= MAX_CID_LEN, AgentError::InvalidLength);
+            
+            // Validate mode value (0=None, 1=CID, 2=IPFS, 3=URL)
+            require!(mode <= 3, AgentError::InvalidMemoryFields);
+            
+            // Validate URL scheme for URL mode (3)
+            if mode == MemoryMode::Url as u8 {
+                let url_str = core::str::from_utf8(ptr).map_err(|_| AgentError::InvalidMemoryFields)?;
+                require!(url_str.starts_with("https://"), AgentError::InsecureUrl);
+            }
+            
+            agent.memory_mode = mode;
+            agent.memory_ptr = ptr.clone();
+            agent.memory_hash = memory_hash.unwrap_or([0u8; 32]);
+        } else {
+            // Mode provided but no pointer → invalid
+            return Err(AgentError::InvalidMemoryFields.into());
+        }
+    } else {
+        // No memory: default None
+        agent.memory_mode = MemoryMode::None as u8;
+        agent.memory_ptr = Vec::new();
+        agent.memory_hash = [0u8; 32];
+    }
+    
+    // Card: OBLIGATOIRE (identité de l'agent)
+    let bytes = card_uri.as_bytes();
+    require!(!bytes.is_empty() && bytes.len() <= MAX_URI, AgentError::InvalidLength);
+    // Allow only https:// or ipfs:// schemes for card_uri
+    let ok_scheme = card_uri.starts_with("https://") || card_uri.starts_with("ipfs://");
+    require!(ok_scheme, AgentError::InsecureUrl);
+    agent.card_uri = bytes.to_vec();
+    agent.card_hash = card_hash;
+    
+    // Flags: Active + has_staking (default true)
+    agent.flags = FLAG_ACTIVE;
+    if has_staking.unwrap_or(true) {  // Default = true
+        agent.flags |= FLAG_HAS_STAKING;
+    }
+    
+    agent.agent_index = [0u8; 4];
+    Ok(())
+}
+
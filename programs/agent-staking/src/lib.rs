@@ -1,12 +1,76 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, Transfer};
-use agent_registry::{AgentRegistry, FLAG_HAS_STAKING};
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+use anchor_spl::token;
+use anchor_spl::token_interface::{
+    self, Burn, CloseAccount, GetAccountDataSize, InitializeAccount3, Mint, MintTo, TokenAccount, TokenInterface, TransferChecked,
+};
+use agent_registry::{AgentRegistry, AGENT_REGISTRY_VERSION, FLAG_ACTIVE, FLAG_HAS_STAKING};
 use anchor_lang::solana_program;
 use anchor_lang::solana_program::system_instruction;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
 
 declare_id!("j3WMvorrddakwt69dqrQ5cve5APpyd4bxUCb9UF9Aqj");
 
-// Simple treasury-only fee model, upgradeable via admin instruction.
+// Time-decayed unstake fees, in SOL or in the staked token (FEE_MODE_*), governed by the upgrade authority.
+
+// StakingPool.flags
+pub const POOL_FLAG_ACTIVE: u8 = 1 << 0;  // Cleared while paused: no new stake, exits still work
+pub const POOL_FLAG_MIGRATED: u8 = 1 << 1;  // Vault handed over to agent_platform, pool is frozen
+pub const POOL_FLAG_SLASH_PENDING: u8 = 1 << 2;  // A PendingSlash exists, config and export are blocked
+pub const POOL_FLAG_DEPRECATED: u8 = 1 << 3;  // Winding down for good: no new stake, fee- and lock-free exits
+
+// Merged program that receives pools through export_pool
+pub const AGENT_PLATFORM_ID: Pubkey = pubkey!("3TNdmF3EC9yrJjm5fxfFrrBxur5ntiuoByCqYSgtrEbw");
+
+// Scale of StakingPool.reward_per_token_stored (reward units per weighted share)
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+// Virtual shares and tokens added to both sides of the share price. At a 1:1 price they
+// change nothing, but a donation into a (nearly) empty pool mostly accrues to them, so
+// inflating the price to round the next deposit down costs the donor nearly all of it.
+pub const VIRTUAL_SHARES: u64 = 1_000;
+pub const MAX_SLASH_BPS_PER_EPOCH: u16 = 5_000;
+// A slash timelock exceeds the pool's unbonding cooldown by at least this, so stakers
+// who object to a proposed slash can complete an unstake before it executes
+pub const MIN_SLASH_EXIT_WINDOW_SECONDS: u32 = 86_400;
+// Delay between propose_slashing_config and configure_slashing: longer than any cooldown
+pub const SLASHING_CONFIG_TIMELOCK_SECONDS: i64 =
+    StakingPool::MAX_UNBONDING_PERIOD as i64 + MIN_SLASH_EXIT_WINDOW_SECONDS as i64;
+
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+// Lockup tiers: (lock duration in seconds, reward weight in bps). Tier 0 is an unlocked position.
+pub const LOCKUP_TIERS: [(u32, u16); 4] = [
+    (0, 10_000),
+    (30 * 86_400, 12_500),   // 30 days: 1.25x
+    (90 * 86_400, 15_000),   // 90 days: 1.5x
+    (365 * 86_400, 20_000),  // 365 days: 2x
+];
+pub const MAX_EARLY_UNLOCK_PENALTY_BPS: u16 = 5_000;
+// Largest part of an unstake fee a pool owner can route to themselves
+pub const MAX_OWNER_FEE_SHARE_BPS: u16 = 5_000;
+// Unstake fee modes: ProgramState.fee_mode is SOL or TOKEN, StakingPool.fee_mode may override it
+pub const FEE_MODE_DEFAULT: u8 = 0;  // Pool follows ProgramState.fee_mode
+pub const FEE_MODE_SOL: u8 = 1;      // Time-decayed lamports paid by the staker
+pub const FEE_MODE_TOKEN: u8 = 2;    // Time-decayed bps of the withdrawn tokens, into the pool fee vault
+pub const MAX_TOKEN_FEE_BPS: u16 = 1_000;
+// Pending unstake requests per position, each with its own cooldown
+pub const MAX_UNBONDING_REQUESTS: usize = 4;
+// Minimum time between the last change of a position and closing it (so re-opening it)
+pub const STAKE_REOPEN_DELAY_SECONDS: i64 = 86_400;
+// Delay between propose_fee_config and update_fee_config
+pub const FEE_CONFIG_TIMELOCK_SECONDS: i64 = 2 * 86_400;
+
+// Fee configuration queued by propose_fee_config and applied by update_fee_config
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FeeConfigParams {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub fee_mode: u8,            // FEE_MODE_SOL or FEE_MODE_TOKEN
+    pub fee_immediate_bps: u16,
+    pub fee_regular_bps: u16,
+}
 
 #[program]
 pub mod agent_staking {
@@ -18,55 +82,144 @@ pub mod agent_staking {
         state.fee_regular_lamports = ProgramState::DEFAULT_REGULAR_FEE;
         state.fee_max_lamports = ProgramState::DEFAULT_MAX_FEE;
         state.decay_duration_seconds = ProgramState::DEFAULT_DECAY_DURATION;
+        state.fee_mode = FEE_MODE_SOL;
+        state.fee_immediate_bps = ProgramState::DEFAULT_IMMEDIATE_FEE_BPS;
+        state.fee_regular_bps = ProgramState::DEFAULT_REGULAR_FEE_BPS;
         state.treasury = ctx.accounts.treasury.key();
         state.bump = ctx.bumps.program_state;
         Ok(())
     }
 
+    // Fee parameters are governed by the program upgrade authority: a new config is queued
+    // here and applied by update_fee_config once FEE_CONFIG_TIMELOCK_SECONDS have passed.
+    pub fn propose_fee_config(ctx: Context<ProposeFeeConfig>, params: FeeConfigParams) -> Result<()> {
+        let FeeConfigParams {
+            fee_immediate_lamports,
+            fee_regular_lamports,
+            fee_max_lamports,
+            decay_duration_seconds,
+            fee_mode,
+            fee_immediate_bps,
+            fee_regular_bps,
+        } = params;
+        require!(
+            fee_regular_lamports <= fee_immediate_lamports
+                && fee_immediate_lamports <= fee_max_lamports
+                && decay_duration_seconds > 0
+                && (fee_mode == FEE_MODE_SOL || fee_mode == FEE_MODE_TOKEN)
+                && fee_regular_bps <= fee_immediate_bps
+                && fee_immediate_bps <= MAX_TOKEN_FEE_BPS,
+            StakingError::InvalidFeeConfig
+        );
+        let now = Clock::get()?.unix_timestamp;
+        let execute_after = now.saturating_add(FEE_CONFIG_TIMELOCK_SECONDS);
+        let pending = &mut ctx.accounts.pending_fee_config;
+        pending.fee_immediate_lamports = fee_immediate_lamports;
+        pending.fee_regular_lamports = fee_regular_lamports;
+        pending.fee_max_lamports = fee_max_lamports;
+        pending.decay_duration_seconds = decay_duration_seconds;
+        pending.fee_mode = fee_mode;
+        pending.fee_immediate_bps = fee_immediate_bps;
+        pending.fee_regular_bps = fee_regular_bps;
+        pending.proposed_at = now;
+        pending.execute_after = execute_after;
+        pending.bump = ctx.bumps.pending_fee_config;
+        emit!(FeeConfigProposed {
+            fee_immediate_lamports,
+            fee_regular_lamports,
+            fee_max_lamports,
+            decay_duration_seconds,
+            fee_mode,
+            fee_immediate_bps,
+            fee_regular_bps,
+            execute_after,
+        });
+        Ok(())
+    }
+
+    pub fn update_fee_config(ctx: Context<UpdateFeeConfig>) -> Result<()> {
+        let pending = &ctx.accounts.pending_fee_config;
+        require!(Clock::get()?.unix_timestamp >= pending.execute_after, StakingError::FeeConfigTimelockActive);
+        let state = &mut ctx.accounts.program_state;
+        state.fee_immediate_lamports = pending.fee_immediate_lamports;
+        state.fee_regular_lamports = pending.fee_regular_lamports;
+        state.fee_max_lamports = pending.fee_max_lamports;
+        state.decay_duration_seconds = pending.decay_duration_seconds;
+        state.fee_mode = pending.fee_mode;
+        state.fee_immediate_bps = pending.fee_immediate_bps;
+        state.fee_regular_bps = pending.fee_regular_bps;
+        emit!(FeeConfigUpdated {
+            fee_immediate_lamports: state.fee_immediate_lamports,
+            fee_regular_lamports: state.fee_regular_lamports,
+            fee_max_lamports: state.fee_max_lamports,
+            decay_duration_seconds: state.decay_duration_seconds,
+            fee_mode: state.fee_mode,
+            fee_immediate_bps: state.fee_immediate_bps,
+            fee_regular_bps: state.fee_regular_bps,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_fee_config(ctx: Context<CancelFeeConfig>) -> Result<()> {
+        emit!(FeeConfigCancelled { execute_after: ctx.accounts.pending_fee_config.execute_after });
+        Ok(())
+    }
+
     pub fn create_staking_pool(ctx: Context<CreateStakingPool>, min_stake_amount: u64) -> Result<()> {
         require!(min_stake_amount > 0, StakingError::InvalidMinStakeAmount);
-        
-        // Initialize token vault via CPI
+        if let Some(receipt_mint) = &ctx.accounts.receipt_mint {
+            require!(receipt_mint.supply == 0, StakingError::ReceiptsOutstanding);
+        }
+
+        // Vault: the ["token_vault", pool] PDA, created here, or the pool's associated token
+        // account, created beforehand by the client
         let pool_key = ctx.accounts.staking_pool.key();
-        let seeds = &[b"token_vault", pool_key.as_ref(), &[ctx.bumps.token_vault]];
-        let signer = &[&seeds[..]];
-        
-        let rent = &ctx.accounts.rent;
-        let vault_rent = rent.minimum_balance(anchor_spl::token::TokenAccount::LEN);
-        
-        // Create account for token vault
-        solana_program::program::invoke_signed(
-            &system_instruction::create_account(
-                ctx.accounts.owner.key,
-                ctx.accounts.token_vault.key,
-                vault_rent,
-                165, // Size of TokenAccount
-                ctx.accounts.token_program.key,
-            ),
-            &[
-                ctx.accounts.owner.to_account_info(),
-                ctx.accounts.token_vault.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-            signer,
-        )?;
-        
-        // Initialize token account via token program
-        let init_ix = anchor_spl::token::spl_token::instruction::initialize_account3(
-            &anchor_spl::token::ID,
-            ctx.accounts.token_vault.key,
-            ctx.accounts.token_mint.key,
-            &ctx.accounts.staking_pool.key(), // authority = staking_pool PDA
-        )?;
-        
-        solana_program::program::invoke(
-            &init_ix,
-            &[
-                ctx.accounts.token_vault.to_account_info(),
-                ctx.accounts.token_mint.to_account_info(),
-            ],
-        )?;
-        
+        let (vault_pda, vault_bump) = Pubkey::find_program_address(&[b"token_vault", pool_key.as_ref()], ctx.program_id);
+        if ctx.accounts.token_vault.key() == vault_pda {
+            let seeds = &[b"token_vault", pool_key.as_ref(), &[vault_bump]];
+            let signer = &[&seeds[..]];
+
+            // Token-2022 mints may require account extensions: let the token program size the vault
+            let vault_size = token_interface::get_account_data_size(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    GetAccountDataSize { mint: ctx.accounts.token_mint.to_account_info() },
+                ),
+                &[],
+            )?;
+            let rent = &ctx.accounts.rent;
+            let vault_rent = rent.minimum_balance(vault_size as usize);
+
+            // Create account for token vault
+            solana_program::program::invoke_signed(
+                &system_instruction::create_account(
+                    ctx.accounts.owner.key,
+                    ctx.accounts.token_vault.key,
+                    vault_rent,
+                    vault_size,
+                    ctx.accounts.token_program.key,
+                ),
+                &[
+                    ctx.accounts.owner.to_account_info(),
+                    ctx.accounts.token_vault.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                ],
+                signer,
+            )?;
+
+            // Initialize token account via token program (SPL Token or Token-2022)
+            token_interface::initialize_account3(CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                InitializeAccount3 {
+                    account: ctx.accounts.token_vault.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    authority: ctx.accounts.staking_pool.to_account_info(), // authority = staking_pool PDA
+                },
+            ))?;
+        } else {
+            check_ata_vault(&ctx.accounts.token_vault, &pool_key, &ctx.accounts.token_mint, &ctx.accounts.token_program)?;
+        }
+
         let pool = &mut ctx.accounts.staking_pool;
         let clock = Clock::get()?;
         pool.agent_pda = ctx.accounts.agent.key();
@@ -74,12 +227,36 @@ pub mod agent_staking {
         pool.token_mint = ctx.accounts.token_mint.key();
         pool.token_vault = ctx.accounts.token_vault.key();
         pool.min_stake_amount = min_stake_amount;
-        pool.total_staked = 0;
+        pool.total_shares = 0;
         pool.staker_count = 0;
         pool.created_at = clock.unix_timestamp;
-        pool.flags = 1; // ACTIVE
+        pool.flags = POOL_FLAG_ACTIVE;
+        pool.reward_mint = Pubkey::default();
+        pool.reward_vault = Pubkey::default();
+        pool.reward_per_token_stored = 0;
+        pool.reward_rate = 0;
+        pool.reward_reserve = 0;
+        pool.last_update_time = clock.unix_timestamp;
+        pool.unbonding_period_seconds = 0;
+        pool.total_weighted_stake = 0;
+        pool.early_unlock_penalty_bps = 0;
+        pool.slash_authority = Pubkey::default();
+        pool.slash_max_bps_per_epoch = 0;
+        pool.slash_timelock_seconds = 0;
+        pool.slash_epoch = 0;
+        pool.slashed_bps_in_epoch = 0;
+        pool.receipt_mint = ctx.accounts.receipt_mint.as_ref().map_or(Pubkey::default(), |m| m.key());
+        pool.fee_immediate_lamports = 0;
+        pool.fee_regular_lamports = 0;
+        pool.fee_decay_duration_seconds = 0;
+        pool.fee_owner_share_bps = 0;
+        pool.fee_mode = FEE_MODE_DEFAULT;
+        pool.fee_vault = Pubkey::default();
+        pool.owner_token_fees = 0;
+        pool.rewards_owed_total = 0;
+        pool.total_unbonding = 0;
         pool.bump = ctx.bumps.staking_pool;
-        emit!(PoolCreated { agent_pda: pool.agent_pda, owner: pool.owner, min_stake_amount });
+        emit!(PoolCreated { agent_pda: pool.agent_pda, owner: pool.owner, min_stake_amount, receipt_mint: pool.receipt_mint });
         Ok(())
     }
 
@@ -93,6 +270,144 @@ pub mod agent_staking {
         Ok(())
     }
 
+    // Permissionless: point the pool at the agent's current owner after a transfer_owner
+    pub fn sync_pool_owner(ctx: Context<SyncPoolOwner>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let old_owner = pool.owner;
+        pool.owner = ctx.accounts.agent.owner;
+        emit!(PoolOwnerSynced { agent_pda: pool.agent_pda, old_owner, new_owner: pool.owner });
+        Ok(())
+    }
+
+    // Cooldown applied to new unstake requests; pending requests keep their unlock time.
+    // With a non-zero cooldown, withdraw_stake is disabled and exits go through the queue.
+    pub fn update_unbonding_period(ctx: Context<UpdateUnbondingPeriod>, new_period_seconds: u32) -> Result<()> {
+        require!(new_period_seconds <= StakingPool::MAX_UNBONDING_PERIOD, StakingError::InvalidUnbondingPeriod);
+        let pool = &mut ctx.accounts.staking_pool;
+        // Stakers must still be able to leave within the slash timelock
+        if pool.slash_authority != Pubkey::default() {
+            require!(
+                new_period_seconds as u64 + MIN_SLASH_EXIT_WINDOW_SECONDS as u64 <= pool.slash_timelock_seconds as u64,
+                StakingError::SlashTimelockTooShort
+            );
+        }
+        let old = pool.unbonding_period_seconds;
+        pool.unbonding_period_seconds = new_period_seconds;
+        emit!(UnbondingPeriodUpdated { agent_pda: pool.agent_pda, old_period_seconds: old, new_period_seconds });
+        Ok(())
+    }
+
+    // Penalty (in staked tokens, burned) for exiting a lockup early. 0 = early exits are
+    // rejected. Only applies to lockups chosen after the change.
+    pub fn update_early_unlock_penalty(ctx: Context<UpdateEarlyUnlockPenalty>, penalty_bps: u16) -> Result<()> {
+        require!(penalty_bps <= MAX_EARLY_UNLOCK_PENALTY_BPS, StakingError::InvalidPenalty);
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.early_unlock_penalty_bps = penalty_bps;
+        emit!(EarlyUnlockPenaltyUpdated { agent_pda: pool.agent_pda, penalty_bps });
+        Ok(())
+    }
+
+    // Paused pools take no new stake; withdrawals and unstake requests keep working
+    pub fn set_pool_paused(ctx: Context<UpdatePoolStatus>, paused: bool) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(pool.flags & POOL_FLAG_DEPRECATED == 0, StakingError::PoolDeprecated);
+        if paused {
+            pool.flags &= !POOL_FLAG_ACTIVE;
+        } else {
+            pool.flags |= POOL_FLAG_ACTIVE;
+        }
+        emit!(PoolPausedSet { agent_pda: pool.agent_pda, paused });
+        Ok(())
+    }
+
+    // Wind the pool down: stake is refused for good and exits no longer pay unstake fees
+    pub fn deprecate_pool(ctx: Context<UpdatePoolStatus>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(pool.flags & POOL_FLAG_DEPRECATED == 0, StakingError::PoolDeprecated);
+        pool.flags = (pool.flags & !POOL_FLAG_ACTIVE) | POOL_FLAG_DEPRECATED;
+        emit!(PoolDeprecated { agent_pda: pool.agent_pda });
+        Ok(())
+    }
+
+    // Pool-specific unstake fee curve, bounded by the global regular and max fees, with
+    // owner_share_bps of each fee paid to the pool owner. decay_duration_seconds = 0 goes
+    // back to the ProgramState curve.
+    pub fn update_pool_fees(
+        ctx: Context<UpdatePoolFees>,
+        fee_immediate_lamports: u64,
+        fee_regular_lamports: u64,
+        decay_duration_seconds: u32,
+        owner_share_bps: u16,
+    ) -> Result<()> {
+        let state = &ctx.accounts.program_state;
+        if decay_duration_seconds == 0 {
+            require!(
+                fee_immediate_lamports == 0 && fee_regular_lamports == 0 && owner_share_bps == 0,
+                StakingError::InvalidFeeConfig
+            );
+        } else {
+            require!(
+                state.fee_regular_lamports <= fee_regular_lamports
+                    && fee_regular_lamports <= fee_immediate_lamports
+                    && fee_immediate_lamports <= state.fee_max_lamports
+                    && decay_duration_seconds <= StakingPool::MAX_FEE_DECAY_DURATION
+                    && owner_share_bps <= MAX_OWNER_FEE_SHARE_BPS,
+                StakingError::InvalidFeeConfig
+            );
+        }
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.fee_immediate_lamports = fee_immediate_lamports;
+        pool.fee_regular_lamports = fee_regular_lamports;
+        pool.fee_decay_duration_seconds = decay_duration_seconds;
+        pool.fee_owner_share_bps = owner_share_bps;
+        emit!(PoolFeesUpdated {
+            agent_pda: pool.agent_pda,
+            fee_immediate_lamports,
+            fee_regular_lamports,
+            decay_duration_seconds,
+            owner_share_bps,
+        });
+        Ok(())
+    }
+
+    // Fee mode of the pool: FEE_MODE_DEFAULT follows ProgramState. Token mode needs the fee vault.
+    pub fn update_pool_fee_mode(ctx: Context<UpdatePoolFeeMode>, fee_mode: u8) -> Result<()> {
+        require!(fee_mode <= FEE_MODE_TOKEN, StakingError::InvalidFeeConfig);
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(
+            fee_mode != FEE_MODE_TOKEN || pool.fee_vault != Pubkey::default(),
+            StakingError::FeeVaultRequired
+        );
+        pool.fee_mode = fee_mode;
+        emit!(PoolFeeModeUpdated { agent_pda: pool.agent_pda, fee_mode });
+        Ok(())
+    }
+
+    // Create the ["fee_vault", pool] token account receiving token-mode fees. Permissionless,
+    // so a pool can always be brought in line with a global switch to token mode.
+    pub fn init_fee_vault(ctx: Context<InitFeeVault>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.fee_vault = ctx.accounts.fee_vault.key();
+        Ok(())
+    }
+
+    // Empty the fee vault: the owner's share of token-mode fees to the pool owner, the rest to
+    // the treasury. Permissionless.
+    pub fn collect_pool_fees(ctx: Context<CollectPoolFees>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let (owner_amount, treasury_amount) = pay_out_fee_vault(
+            pool,
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.fee_vault,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.owner_token_account.to_account_info(),
+            &ctx.accounts.treasury_token_account.to_account_info(),
+        )?;
+        pool.owner_token_fees = 0;
+        emit!(PoolFeesCollected { agent_pda: pool.agent_pda, owner_amount, treasury_amount });
+        Ok(())
+    }
+
     // Initialize a new stake account (required before first stake)
     pub fn init_stake(ctx: Context<InitStake>) -> Result<()> {
         let pool = &ctx.accounts.staking_pool;
@@ -101,29 +416,72 @@ pub mod agent_staking {
         
         stake_acc.staker = ctx.accounts.staker.key();
         stake_acc.agent_pda = pool.agent_pda;
-        stake_acc.staked_amount = 0;
+        stake_acc.shares = 0;
         stake_acc.staked_at = clock.unix_timestamp;
         stake_acc.last_updated_at = clock.unix_timestamp;
+        stake_acc.reward_per_token_paid = pool.reward_per_token_stored;
+        stake_acc.rewards_checkpoint_at = clock.unix_timestamp;
+        stake_acc.rewards_owed = 0;
+        stake_acc.unbonding = [UnbondingRequest::default(); MAX_UNBONDING_REQUESTS];
+        stake_acc.lock_tier = 0;
+        stake_acc.lock_ends_at = 0;
+        stake_acc.lock_penalty_bps = 0;
+        stake_acc.weighted_amount = 0;
         stake_acc.bump = ctx.bumps.stake_account;
 
         Ok(())
     }
 
+    // Lock the whole position for a tier's duration, starting now, in exchange for its reward
+    // weight. A lockup can be extended or moved to a higher tier but never shortened.
+    pub fn set_lockup(ctx: Context<SetLockup>, tier: u8) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        let (duration, _) = *LOCKUP_TIERS.get(tier as usize).ok_or(StakingError::InvalidLockupTier)?;
+        require!(stake_acc.shares > 0, StakingError::NoStake);
+        update_rewards(pool, now)?;
+        settle_rewards(pool, stake_acc, now)?;
+        end_lockup(pool, stake_acc, now)?;
+
+        let lock_ends_at = now.saturating_add(duration as i64);
+        require!(
+            tier >= stake_acc.lock_tier && lock_ends_at >= stake_acc.lock_ends_at,
+            StakingError::LockupCannotBeShortened
+        );
+        stake_acc.lock_tier = tier;
+        stake_acc.lock_ends_at = lock_ends_at;
+        stake_acc.lock_penalty_bps = pool.early_unlock_penalty_bps;
+        reweight(pool, stake_acc)?;
+
+        emit!(LockupSet { staker: stake_acc.staker, agent_pda: pool.agent_pda, tier, lock_ends_at, weighted_amount: stake_acc.weighted_amount });
+        Ok(())
+    }
+
+    // Permissionless: end a lockup that has run out, so total_weighted_stake stops counting its
+    // boost without waiting for the staker to touch the position
+    pub fn expire_lockup(ctx: Context<ExpireLockup>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        require!(stake_acc.lock_tier != 0 && now >= stake_acc.lock_ends_at, StakingError::LockupNotEnded);
+        update_rewards(pool, now)?;
+        settle_rewards(pool, stake_acc, now)?;
+        end_lockup(pool, stake_acc, now)?;
+
+        emit!(LockupExpired { staker: stake_acc.staker, agent_pda: pool.agent_pda, weighted_amount: stake_acc.weighted_amount });
+        Ok(())
+    }
+
     // Stake tokens (requires stake account to be initialized first)
     pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
         require!(amount > 0, StakingError::InvalidStakeAmount);
         let pool = &mut ctx.accounts.staking_pool;
         let stake_acc = &mut ctx.accounts.stake_account;
         let clock = Clock::get()?;
-
-        // First-time stake: enforce min_stake (FIX M-02)
-        if stake_acc.staked_amount == 0 {
-            require!(
-                amount >= pool.min_stake_amount,
-                StakingError::BelowMinimumStake
-            );
-            pool.staker_count = pool.staker_count.saturating_add(1);
-        }
+        update_rewards(pool, clock.unix_timestamp)?;
+        settle_rewards(pool, stake_acc, clock.unix_timestamp)?;
+        end_lockup(pool, stake_acc, clock.unix_timestamp)?;
 
         // Verify ownership
         require!(
@@ -131,65 +489,96 @@ pub mod agent_staking {
             StakingError::Unauthorized
         );
 
+        // Shares are priced against the vault before the deposit lands
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+
         // REAL SPL transfer: staker → vault
         let cpi_ctx = CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
-            Transfer {
+            TransferChecked {
                 from: ctx.accounts.staker_token_account.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
                 to: ctx.accounts.token_vault.to_account_info(),
                 authority: ctx.accounts.staker.to_account_info(),
             },
         );
-        token::transfer(cpi_ctx, amount)?;
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+        // Transfer-fee mints withhold part of the transfer: only what reached the vault counts
+        let received = token::accessor::amount(&ctx.accounts.token_vault)?
+            .checked_sub(vault_balance)
+            .ok_or(StakingError::MathOverflow)?;
 
-        stake_acc.staked_amount = stake_acc.staked_amount.saturating_add(amount);
+        let receipts = receipt_accounts(pool, &ctx.accounts.receipt_mint, &ctx.accounts.staker_receipt_account)?;
+        // First-time stake: enforce min_stake (FIX M-02). Receipt pools check every deposit.
+        if stake_acc.shares == 0 {
+            require!(
+                received >= pool.min_stake_amount,
+                StakingError::BelowMinimumStake
+            );
+            if receipts.is_none() {
+                pool.staker_count = pool.staker_count.saturating_add(1);
+            }
+        }
+        let shares = amount_to_shares(pool, received, vault_balance)?;
+        require!(shares > 0, StakingError::InvalidStakeAmount);
+        if let Some((receipt_mint, receipt_account)) = receipts {
+            // The receipts are the claim on the shares: whoever holds them can redeem them
+            mint_receipts(&ctx.accounts.token_program, receipt_mint, receipt_account, pool, shares)?;
+        } else {
+            // Added shares join the current lockup, if any
+            stake_acc.shares = stake_acc.shares.checked_add(shares).ok_or(StakingError::MathOverflow)?;
+        }
         stake_acc.last_updated_at = clock.unix_timestamp;
-        pool.total_staked = pool.total_staked.saturating_add(amount);
+        pool.total_shares = pool.total_shares.checked_add(shares).ok_or(StakingError::MathOverflow)?;
+        reweight(pool, stake_acc)?;
 
-        emit!(Staked { staker: stake_acc.staker, agent_pda: pool.agent_pda, amount, total: stake_acc.staked_amount });
+        emit!(Staked { staker: stake_acc.staker, agent_pda: pool.agent_pda, amount: received, shares, total: stake_acc.shares });
         Ok(())
     }
 
     // REMOVED: transfer_authority - no central authority in zero-admin architecture
-    // Program parameters are hardcoded and can only be changed via program upgrades
+    // Fee parameters follow propose_fee_config / update_fee_config (upgrade authority + timelock)
 
-    pub fn withdraw_stake(ctx: Context<WithdrawStake>) -> Result<()> {
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>, shares: u64) -> Result<()> {
         let pool = &mut ctx.accounts.staking_pool;
         let state = &ctx.accounts.program_state;
         let stake_acc = &mut ctx.accounts.stake_account;
         let clock = Clock::get()?;
 
-        require!(stake_acc.staked_amount > 0, StakingError::NoStake);
-
-        let elapsed = (clock.unix_timestamp - stake_acc.staked_at).max(0) as u64;
-        let fee = calculate_unstake_fee(elapsed, state)?;  // FIX H-01: propagate error
+        // Instant exits are only allowed on pools without a cooldown
+        require!(pool.unbonding_period_seconds == 0, StakingError::UnbondingRequired);
+        update_rewards(pool, clock.unix_timestamp)?;
+        settle_rewards(pool, stake_acc, clock.unix_timestamp)?;
+        end_lockup(pool, stake_acc, clock.unix_timestamp)?;
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let remaining = check_unstake_shares(pool, stake_acc, shares, vault_balance)?;
+        let amount = shares_to_amount(pool, shares, vault_balance)?;
+        // In receipt pools the exit redeems the receipts burned here
+        if let Some((receipt_mint, receipt_account)) =
+            receipt_accounts(pool, &ctx.accounts.receipt_mint, &ctx.accounts.staker_receipt_account)?
+        {
+            burn_receipts(&ctx.accounts.token_program, receipt_mint, receipt_account, &ctx.accounts.staker, shares)?;
+        }
+        let penalty = early_unlock_penalty(pool, stake_acc, amount, clock.unix_timestamp)?;
 
-        // FIX H-02: Check suffisance SOL AVANT transfer
-        if fee > 0 {
-            let staker_lamports = ctx.accounts.staker.lamports();
-            let rent_exempt = Rent::get()?.minimum_balance(0);
-            
-            require!(
-                staker_lamports >= fee.saturating_add(rent_exempt),
-                StakingError::InsufficientSolForFee
-            );
-            
-            let ix = anchor_lang::solana_program::system_instruction::transfer(
-                &ctx.accounts.staker.key(),
-                &state.treasury,
+        // Early-exit fee: SOL from the staker, or a share of the withdrawn tokens in token mode
+        let (fee, owner_fee, token_fee) = if fee_mode(state, pool) == FEE_MODE_TOKEN {
+            let token_fee = mul_div(amount, token_fee_bps(state, pool, stake_acc, clock.unix_timestamp)?, BPS_DENOMINATOR)?;
+            (0, 0, token_fee)
+        } else {
+            let fee = unstake_fee(state, pool, stake_acc, shares, clock.unix_timestamp)?;
+            let owner_fee = charge_unstake_fee(
+                state,
+                pool,
                 fee,
-            );
-            anchor_lang::solana_program::program::invoke(
-                &ix,
-                &[
-                    ctx.accounts.staker.to_account_info(),
-                    ctx.accounts.treasury.to_account_info(),
-                ],
+                &ctx.accounts.staker.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.agent_owner,
             )?;
-        }
+            (fee, owner_fee, 0)
+        };
 
         // REAL SPL transfer: vault → staker (PDA must sign)
-        let amount = stake_acc.staked_amount;
         let agent_pda = pool.agent_pda;
         let seeds = &[
             b"staking_pool",
@@ -200,189 +589,1678 @@ pub mod agent_staking {
 
         let cpi_ctx = CpiContext::new_with_signer(
             ctx.accounts.token_program.to_account_info(),
-            Transfer {
+            TransferChecked {
                 from: ctx.accounts.token_vault.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
                 to: ctx.accounts.staker_token_account.to_account_info(),
                 authority: pool.to_account_info(),
             },
             signer,
         );
-        token::transfer(cpi_ctx, amount)?;
+        token_interface::transfer_checked(cpi_ctx, amount - penalty - token_fee, ctx.accounts.token_mint.decimals)?;
+        if penalty > 0 {
+            burn_from_vault(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_vault,
+                pool,
+                penalty,
+            )?;
+        }
+        if token_fee > 0 {
+            charge_token_fee(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_vault,
+                &ctx.accounts.fee_vault,
+                pool,
+                token_fee,
+            )?;
+        }
 
-        // FIX C-02: Ne PAS fermer le compte, juste réinitialiser montant
-        // Ceci conserve staked_at pour éviter manipulation fees
-        stake_acc.staked_amount = 0;
+        // FIX C-02: Ne PAS fermer le compte, juste décrémenter les parts
+        // Ceci conserve staked_at; close_stake_account ferme après STAKE_REOPEN_DELAY_SECONDS
+        stake_acc.shares = remaining;
         stake_acc.last_updated_at = clock.unix_timestamp;
         
-        pool.total_staked = pool.total_staked.saturating_sub(amount);
-        // Note: staker_count reste inchangé (compte existe toujours)
+        pool.total_shares = pool.total_shares.saturating_sub(shares);
+        reweight(pool, stake_acc)?;
+        // stake() re-increments staker_count if the position is reopened (receipt pools never count)
+        if remaining == 0 && pool.receipt_mint == Pubkey::default() {
+            pool.staker_count = pool.staker_count.saturating_sub(1);
+        }
 
-        emit!(Withdrawn { staker: stake_acc.staker, agent_pda: pool.agent_pda, amount, fee });
+        emit!(Withdrawn { staker: stake_acc.staker, agent_pda: pool.agent_pda, amount, shares, fee, owner_fee, token_fee, penalty });
         Ok(())
     }
-}
-
-#[derive(Accounts)]
-pub struct InitProgramState<'info> {
-    #[account(
-        init,
-        payer = initializer,
-        space = 8 + ProgramState::SPACE,
-        seeds = [b"program_state"],
-        bump
-    )]
-    pub program_state: Account<'info, ProgramState>,
-    #[account(mut)]
-    pub initializer: Signer<'info>,  // Just pays for initialization, no ongoing control
-    /// CHECK: treasury system account
-    pub treasury: UncheckedAccount<'info>,
-    pub system_program: Program<'info, System>,
-}
 
-// REMOVED: TransferAuthority context - no central authority needed
+    // Start unbonding `shares`: they stop earning rewards right away and are redeemed by
+    // complete_unstake once the pool cooldown is over, at the vault price of that moment.
+    // Each request takes its own slot and cooldown, earlier ones keep their unlock time.
+    pub fn unstake_request(ctx: Context<UnstakeRequest>, shares: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let state = &ctx.accounts.program_state;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let clock = Clock::get()?;
 
-#[derive(Accounts)]
-pub struct CreateStakingPool<'info> {
-    /// FIX C-01 COMPLETE: Validation via lecture directe du compte agent-registry
-    /// Vérifie que l'agent existe ET a le flag HAS_STAKING activé
-    #[account(
-        constraint = agent.flags & FLAG_HAS_STAKING != 0 @ StakingError::StakingNotEnabled,
-        seeds = [b"agent", agent.creator.as_ref()],
-        bump = agent.bump,
-        seeds::program = agent_registry::ID
-    )]
-    pub agent: Account<'info, AgentRegistry>,
-    #[account(
-        init,
-        payer = owner,
-        space = 8 + StakingPool::SPACE,
-        seeds = [b"staking_pool", agent.key().as_ref()],
-        bump
-    )]
-    pub staking_pool: Account<'info, StakingPool>,
-    /// CHECK: Token vault PDA - will be initialized via CPI in instruction
-    #[account(
-        mut,
-        seeds = [b"token_vault", staking_pool.key().as_ref()],
-        bump
-    )]
-    pub token_vault: AccountInfo<'info>,
-    /// CHECK: Token mint - validated by token program during vault init
-    pub token_mint: AccountInfo<'info>,
-    #[account(
-        mut,
-        constraint = owner.key() == agent.owner @ StakingError::Unauthorized
-    )]
-    pub owner: Signer<'info>,  // Must be the current agent owner
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
-}
+        update_rewards(pool, clock.unix_timestamp)?;
+        settle_rewards(pool, stake_acc, clock.unix_timestamp)?;
+        end_lockup(pool, stake_acc, clock.unix_timestamp)?;
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let remaining = check_unstake_shares(pool, stake_acc, shares, vault_balance)?;
+        let slot = stake_acc
+            .unbonding
+            .iter()
+            .position(|request| request.shares == 0)
+            .ok_or(StakingError::TooManyUnbondingRequests)?;
+        if let Some((receipt_mint, receipt_account)) =
+            receipt_accounts(pool, &ctx.accounts.receipt_mint, &ctx.accounts.staker_receipt_account)?
+        {
+            burn_receipts(&ctx.accounts.token_program, receipt_mint, receipt_account, &ctx.accounts.staker, shares)?;
+        }
+        // Charged now in shares, burned by complete_unstake at the then-current price
+        let penalty_shares = early_unlock_penalty(pool, stake_acc, shares, clock.unix_timestamp)?;
 
-#[derive(Accounts)]
-pub struct UpdateMinStake<'info> {
-    #[account(
-        mut,
-        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
-        bump = staking_pool.bump
-    )]
-    pub staking_pool: Account<'info, StakingPool>,
-    pub owner: Signer<'info>,
-}
+        // In token mode the fee shares are redeemed into the fee vault now, the rest is queued
+        let (fee, owner_fee, fee_shares, token_fee) = if fee_mode(state, pool) == FEE_MODE_TOKEN {
+            let fee_shares = mul_div(shares, token_fee_bps(state, pool, stake_acc, clock.unix_timestamp)?, BPS_DENOMINATOR)?;
+            let token_fee = shares_to_amount(pool, fee_shares, vault_balance)?;
+            (0, 0, fee_shares, token_fee)
+        } else {
+            let fee = unstake_fee(state, pool, stake_acc, shares, clock.unix_timestamp)?;
+            let owner_fee = charge_unstake_fee(
+                state,
+                pool,
+                fee,
+                &ctx.accounts.staker.to_account_info(),
+                &ctx.accounts.treasury.to_account_info(),
+                &ctx.accounts.agent_owner,
+            )?;
+            (fee, owner_fee, 0, 0)
+        };
+        if token_fee > 0 {
+            charge_token_fee(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_vault,
+                &ctx.accounts.fee_vault,
+                pool,
+                token_fee,
+            )?;
+        }
+        // Pending shares leave total_shares for total_unbonding: still priced against the vault
+        // (so exposed to slashes and donations), but no longer active stake
+        pool.total_shares = pool.total_shares.saturating_sub(shares);
+        pool.total_unbonding = pool.total_unbonding.checked_add(shares - fee_shares).ok_or(StakingError::MathOverflow)?;
+        let unlock_at = clock.unix_timestamp.saturating_add(pool.unbonding_period_seconds as i64);
+        stake_acc.unbonding[slot] = UnbondingRequest { shares: shares - fee_shares, penalty_shares, ends_at: unlock_at };
+        stake_acc.shares = remaining;
+        stake_acc.last_updated_at = clock.unix_timestamp;
 
-// Context for initializing a new stake account
-#[derive(Accounts)]
-pub struct InitStake<'info> {
-    #[account(
-        seeds = [b"staking_pool", agent_pda.key().as_ref()],
-        bump = staking_pool.bump
-    )]
-    pub staking_pool: Account<'info, StakingPool>,
-    /// CHECK: agent pda in agent-registry program
-    pub agent_pda: UncheckedAccount<'info>,
-    #[account(
-        init,
-        payer = staker,
-        space = 8 + StakeAccount::SPACE,
-        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
-        bump
-    )]
-    pub stake_account: Account<'info, StakeAccount>,
-    #[account(mut)]
-    pub staker: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
+        reweight(pool, stake_acc)?;
+        if remaining == 0 && pool.receipt_mint == Pubkey::default() {
+            pool.staker_count = pool.staker_count.saturating_sub(1);
+        }
 
-// Context for staking tokens (requires initialized stake account)
-#[derive(Accounts)]
-pub struct Stake<'info> {
-    #[account(
-        mut,
-        seeds = [b"staking_pool", agent_pda.key().as_ref()],
-        bump = staking_pool.bump
-    )]
-    pub staking_pool: Account<'info, StakingPool>,
-    /// CHECK: agent pda in agent-registry program
-    pub agent_pda: UncheckedAccount<'info>,
-    #[account(
-        mut,
-        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
-        bump = stake_account.bump,
-        constraint = stake_account.staker == staker.key() @ StakingError::Unauthorized
-    )]
-    pub stake_account: Account<'info, StakeAccount>,
-    /// CHECK: Token vault to receive staked tokens - validated manually
-    #[account(
-        mut,
-        constraint = token_vault.key() == staking_pool.token_vault @ StakingError::InvalidVault
-    )]
-    pub token_vault: AccountInfo<'info>,
-    /// CHECK: Staker's token account (source of tokens) - validated by SPL transfer
-    #[account(mut)]
-    pub staker_token_account: AccountInfo<'info>,
-    #[account(mut)]
-    pub staker: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-}
+        emit!(UnstakeRequested { staker: stake_acc.staker, agent_pda: pool.agent_pda, shares, fee, owner_fee, token_fee, penalty_shares, unlock_at });
+        Ok(())
+    }
 
-#[derive(Accounts)]
+    // Release every pending request whose cooldown has ended, the others keep waiting
+    pub fn complete_unstake(ctx: Context<CompleteUnstake>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        require!(stake_acc.unbonding.iter().any(|request| request.shares > 0), StakingError::NoPendingUnstake);
+        let mut shares: u64 = 0;
+        let mut penalty_shares: u64 = 0;
+        for request in stake_acc.unbonding.iter_mut().filter(|request| request.shares > 0 && now >= request.ends_at) {
+            shares = shares.checked_add(request.shares).ok_or(StakingError::MathOverflow)?;
+            penalty_shares = penalty_shares.saturating_add(request.penalty_shares);
+            *request = UnbondingRequest::default();
+        }
+        require!(shares > 0, StakingError::UnbondingNotFinished);
+
+        // Pending shares were exposed to slashes and donations until now
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let amount = shares_to_amount(pool, shares, vault_balance)?;
+        // Penalties queued before the pool was deprecated are waived as well
+        let penalty = if pool.flags & POOL_FLAG_DEPRECATED != 0 {
+            0
+        } else {
+            shares_to_amount(pool, penalty_shares, vault_balance)?
+        };
+
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.token_vault.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+                to: ctx.accounts.staker_token_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount - penalty, ctx.accounts.token_mint.decimals)?;
+        if penalty > 0 {
+            burn_from_vault(
+                &ctx.accounts.token_program,
+                &ctx.accounts.token_mint,
+                &ctx.accounts.token_vault,
+                pool,
+                penalty,
+            )?;
+        }
+
+        pool.total_unbonding = pool.total_unbonding.saturating_sub(shares);
+        emit!(UnstakeCompleted { staker: stake_acc.staker, agent_pda, shares, amount: amount - penalty, penalty });
+        Ok(())
+    }
+
+    // Reclaim the rent of an empty position. Re-opening goes through init_stake, which restarts
+    // staked_at (full immediate fee), and is only possible STAKE_REOPEN_DELAY_SECONDS after
+    // the last change of the position.
+    pub fn close_stake_account(ctx: Context<CloseStakeAccount>) -> Result<()> {
+        let stake_acc = &ctx.accounts.stake_account;
+        require!(
+            stake_acc.shares == 0
+                && stake_acc.unbonding.iter().all(|request| request.shares == 0)
+                && stake_acc.rewards_owed == 0,
+            StakingError::StakeAccountNotEmpty
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= stake_acc.last_updated_at.saturating_add(STAKE_REOPEN_DELAY_SECONDS),
+            StakingError::ReopenDelayActive
+        );
+        emit!(StakeAccountClosed { staker: stake_acc.staker, agent_pda: stake_acc.agent_pda });
+        Ok(())
+    }
+
+    // Close a pool without shares, with its vault (PDA-signed close), rent to the owner. Tokens
+    // left in the vault back no share and go to the owner. The fee vault is paid out like
+    // collect_pool_fees and the reward vault (unemitted reserve, dust, direct transfers) goes
+    // to owner_reward_account; rewards settled to stakers must have been claimed.
+    pub fn close_staking_pool(ctx: Context<CloseStakingPool>) -> Result<()> {
+        let pool = &ctx.accounts.staking_pool;
+        let owner = ctx.accounts.owner.to_account_info();
+        let token_program = ctx.accounts.token_program.to_account_info();
+        let leftover = token::accessor::amount(&ctx.accounts.token_vault)?;
+        if leftover > 0 {
+            let agent_pda = pool.agent_pda;
+            let seeds = &[
+                b"staking_pool",
+                agent_pda.as_ref(),
+                &[pool.bump],
+            ];
+            let signer = &[&seeds[..]];
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.token_vault.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: ctx.accounts.owner_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer,
+            );
+            token_interface::transfer_checked(cpi_ctx, leftover, ctx.accounts.token_mint.decimals)?;
+        }
+        close_pool_token_account(&token_program, &ctx.accounts.token_vault, &owner, pool)?;
+        close_fee_vault(
+            pool,
+            &token_program,
+            &ctx.accounts.token_mint,
+            ctx.accounts.fee_vault.as_ref(),
+            &ctx.accounts.owner_token_account.to_account_info(),
+            ctx.accounts.treasury_token_account.as_ref(),
+            &owner,
+        )?;
+        // Rewards may use another token program than the staked mint
+        let reward_token_program = ctx.accounts.reward_token_program.as_ref().map_or(token_program, |p| p.to_account_info());
+        close_reward_vault(
+            pool,
+            &reward_token_program,
+            ctx.accounts.reward_vault.as_ref(),
+            ctx.accounts.reward_mint.as_ref(),
+            ctx.accounts.owner_reward_account.as_ref(),
+            &owner,
+        )?;
+
+        emit!(StakingPoolClosed { agent_pda: pool.agent_pda, owner: owner.key(), leftover });
+        Ok(())
+    }
+
+    // Move the whole vault, and the reward vault if any, to the agent_platform vaults of the
+    // same agent (called by agent_platform::import_staking_pool). The pool is frozen afterwards:
+    // stakers carry their positions and unclaimed rewards over with agent_platform::import_stake.
+    // Token-mode fees and receipts do not move: collect and redeem them first.
+    pub fn export_pool(ctx: Context<ExportPool>) -> Result<()> {
+        let pool = &ctx.accounts.staking_pool;
+        if pool.fee_vault != Pubkey::default() {
+            let fee_vault = ctx.accounts.fee_vault.as_ref().ok_or(StakingError::PoolVaultsRequired)?;
+            require!(fee_vault.amount == 0, StakingError::FeesNotCollected);
+        }
+        if pool.receipt_mint != Pubkey::default() {
+            let receipt_mint = ctx.accounts.receipt_mint.as_ref().ok_or(StakingError::ReceiptAccountsRequired)?;
+            require!(receipt_mint.supply == 0, StakingError::ReceiptsOutstanding);
+        }
+
+        let agent = &ctx.accounts.agent;
+        let (platform_agent, _) = Pubkey::find_program_address(
+            &[b"agent", agent.creator.as_ref(), agent.index_seed()],
+            &AGENT_PLATFORM_ID,
+        );
+        let (platform_pool, _) = Pubkey::find_program_address(&[b"staking_pool", platform_agent.as_ref()], &AGENT_PLATFORM_ID);
+        let (platform_vault, _) = Pubkey::find_program_address(&[b"token_vault", platform_pool.as_ref()], &AGENT_PLATFORM_ID);
+        require_keys_eq!(ctx.accounts.destination.key(), platform_vault, StakingError::InvalidMigrationTarget);
+
+        let amount = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let pool = &mut ctx.accounts.staking_pool;
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        if amount > 0 {
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.token_vault.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: ctx.accounts.destination.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer,
+            );
+            token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+        }
+
+        // Stop emissions: what has accrued is settled into the positions by import_stake
+        update_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.reward_rate = 0;
+        let mut reward_amount = 0;
+        if pool.reward_vault != Pubkey::default() {
+            let (Some(reward_vault), Some(reward_mint), Some(reward_destination)) =
+                (&ctx.accounts.reward_vault, &ctx.accounts.reward_mint, &ctx.accounts.reward_destination)
+            else {
+                return err!(StakingError::PoolVaultsRequired);
+            };
+            let (platform_reward_vault, _) =
+                Pubkey::find_program_address(&[b"reward_vault", platform_pool.as_ref()], &AGENT_PLATFORM_ID);
+            require_keys_eq!(reward_destination.key(), platform_reward_vault, StakingError::InvalidMigrationTarget);
+            reward_amount = reward_vault.amount;
+            if reward_amount > 0 {
+                // Rewards may use another token program than the staked mint
+                let reward_token_program = ctx
+                    .accounts
+                    .reward_token_program
+                    .as_ref()
+                    .map_or(ctx.accounts.token_program.to_account_info(), |p| p.to_account_info());
+                let cpi_ctx = CpiContext::new_with_signer(
+                    reward_token_program,
+                    TransferChecked {
+                        from: reward_vault.to_account_info(),
+                        mint: reward_mint.to_account_info(),
+                        to: reward_destination.to_account_info(),
+                        authority: pool.to_account_info(),
+                    },
+                    signer,
+                );
+                token_interface::transfer_checked(cpi_ctx, reward_amount, reward_mint.decimals)?;
+            }
+        }
+        pool.flags |= POOL_FLAG_MIGRATED;
+        emit!(PoolExported { agent_pda, destination: platform_vault, amount, reward_amount });
+        Ok(())
+    }
+
+    // Close a position of an exported pool once agent_platform::import_stake has carried it
+    // over (signed by the platform pool of the same agent), so it cannot be imported twice.
+    // The rent goes back to the staker.
+    pub fn export_stake(ctx: Context<ExportStake>) -> Result<()> {
+        let agent = &ctx.accounts.agent;
+        let (platform_agent, _) = Pubkey::find_program_address(
+            &[b"agent", agent.creator.as_ref(), agent.index_seed()],
+            &AGENT_PLATFORM_ID,
+        );
+        let (platform_pool, _) = Pubkey::find_program_address(&[b"staking_pool", platform_agent.as_ref()], &AGENT_PLATFORM_ID);
+        require_keys_eq!(ctx.accounts.platform_pool.key(), platform_pool, StakingError::Unauthorized);
+
+        let stake_acc = &ctx.accounts.stake_account;
+        emit!(StakeExported { staker: stake_acc.staker, agent_pda: agent.key(), shares: stake_acc.shares });
+        Ok(())
+    }
+
+    // Create the pool's reward vault for `reward_mint` and start emitting `reward_rate`
+    // tokens per second, pro-rata to stake, as long as the vault is funded.
+    pub fn init_rewards(ctx: Context<InitRewards>, reward_rate: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(pool.reward_vault == Pubkey::default(), StakingError::RewardsAlreadyInitialized);
+        // Rewards accrue to stake account positions, which receipt pools do not have
+        require!(pool.receipt_mint == Pubkey::default(), StakingError::ReceiptPoolWithoutPositions);
+        pool.reward_mint = ctx.accounts.reward_mint.key();
+        pool.reward_vault = ctx.accounts.reward_vault.key();
+        pool.reward_rate = reward_rate;
+        pool.last_update_time = Clock::get()?.unix_timestamp;
+        emit!(RewardRateUpdated { agent_pda: pool.agent_pda, reward_rate });
+        Ok(())
+    }
+
+    // Take back reward reserve that has not been emitted yet. Rewards already emitted stay
+    // claimable by stakers.
+    pub fn withdraw_reward_reserve(ctx: Context<WithdrawRewardReserve>, amount: u64) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidRewardAmount);
+        let pool = &mut ctx.accounts.staking_pool;
+        update_rewards(pool, Clock::get()?.unix_timestamp)?;
+        require!(amount <= pool.reward_reserve, StakingError::InsufficientRewardReserve);
+
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                mint: ctx.accounts.reward_mint.to_account_info(),
+                to: ctx.accounts.owner_reward_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+
+        pool.reward_reserve -= amount;
+        emit!(RewardReserveWithdrawn { agent_pda, amount, reward_reserve: pool.reward_reserve });
+        Ok(())
+    }
+
+    pub fn set_reward_rate(ctx: Context<SetRewardRate>, reward_rate: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        update_rewards(pool, Clock::get()?.unix_timestamp)?;
+        pool.reward_rate = reward_rate;
+        emit!(RewardRateUpdated { agent_pda: pool.agent_pda, reward_rate });
+        Ok(())
+    }
+
+    // Anyone can top up the reward reserve
+    pub fn fund_rewards(ctx: Context<FundRewards>, amount: u64) -> Result<()> {
+        require!(amount > 0, StakingError::InvalidRewardAmount);
+        let pool = &mut ctx.accounts.staking_pool;
+        update_rewards(pool, Clock::get()?.unix_timestamp)?;
+
+        let reserve_before = token::accessor::amount(&ctx.accounts.reward_vault)?;
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.funder_token_account.to_account_info(),
+                mint: ctx.accounts.reward_mint.to_account_info(),
+                to: ctx.accounts.reward_vault.to_account_info(),
+                authority: ctx.accounts.funder.to_account_info(),
+            },
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+        let amount = token::accessor::amount(&ctx.accounts.reward_vault)?
+            .checked_sub(reserve_before)
+            .ok_or(StakingError::MathOverflow)?;
+
+        pool.reward_reserve = pool.reward_reserve.checked_add(amount).ok_or(StakingError::MathOverflow)?;
+        emit!(RewardsFunded { agent_pda: pool.agent_pda, funder: ctx.accounts.funder.key(), amount });
+        Ok(())
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        let stake_acc = &mut ctx.accounts.stake_account;
+        let now = Clock::get()?.unix_timestamp;
+        update_rewards(pool, now)?;
+        settle_rewards(pool, stake_acc, now)?;
+        end_lockup(pool, stake_acc, now)?;
+
+        let amount = stake_acc.rewards_owed;
+        require!(amount > 0, StakingError::NoRewards);
+
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.reward_vault.to_account_info(),
+                mint: ctx.accounts.reward_mint.to_account_info(),
+                to: ctx.accounts.staker_reward_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.reward_mint.decimals)?;
+
+        stake_acc.rewards_owed = 0;
+        pool.rewards_owed_total = pool.rewards_owed_total.saturating_sub(amount);
+        emit!(RewardsClaimed { staker: stake_acc.staker, agent_pda, amount });
+        Ok(())
+    }
+
+    // Slashing is governed by the program upgrade authority, never by the pool owner:
+    // `authority` (an arbiter key or a governance PDA) may take up to `max_bps_per_epoch` of
+    // the pool per epoch, each slash waiting `timelock_seconds` between propose_slash and
+    // execute_slash. The config is queued here and applied by configure_slashing once
+    // SLASHING_CONFIG_TIMELOCK_SECONDS have passed. Pass Pubkey::default() to disable.
+    pub fn propose_slashing_config(
+        ctx: Context<ProposeSlashingConfig>,
+        authority: Pubkey,
+        max_bps_per_epoch: u16,
+        timelock_seconds: u32,
+    ) -> Result<()> {
+        require!(
+            max_bps_per_epoch > 0 && max_bps_per_epoch <= MAX_SLASH_BPS_PER_EPOCH,
+            StakingError::InvalidSlashConfig
+        );
+        let pool = &ctx.accounts.staking_pool;
+        check_slash_config(pool, authority, timelock_seconds)?;
+        let now = Clock::get()?.unix_timestamp;
+        let execute_after = now.saturating_add(SLASHING_CONFIG_TIMELOCK_SECONDS);
+        let pending = &mut ctx.accounts.pending_slashing_config;
+        pending.pool = pool.key();
+        pending.authority = authority;
+        pending.max_bps_per_epoch = max_bps_per_epoch;
+        pending.timelock_seconds = timelock_seconds;
+        pending.proposed_at = now;
+        pending.execute_after = execute_after;
+        pending.bump = ctx.bumps.pending_slashing_config;
+        emit!(SlashingConfigProposed {
+            agent_pda: pool.agent_pda,
+            authority,
+            max_bps_per_epoch,
+            timelock_seconds,
+            execute_after,
+        });
+        Ok(())
+    }
+
+    pub fn configure_slashing(ctx: Context<ConfigureSlashing>) -> Result<()> {
+        let pending = &ctx.accounts.pending_slashing_config;
+        require!(Clock::get()?.unix_timestamp >= pending.execute_after, StakingError::SlashingConfigTimelockActive);
+        let pool = &mut ctx.accounts.staking_pool;
+        // The owner or the cooldown may have changed while the config was queued
+        check_slash_config(pool, pending.authority, pending.timelock_seconds)?;
+        pool.slash_authority = pending.authority;
+        pool.slash_max_bps_per_epoch = pending.max_bps_per_epoch;
+        pool.slash_timelock_seconds = pending.timelock_seconds;
+        emit!(SlashingConfigured {
+            agent_pda: pool.agent_pda,
+            authority: pool.slash_authority,
+            max_bps_per_epoch: pool.slash_max_bps_per_epoch,
+            timelock_seconds: pool.slash_timelock_seconds,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_slashing_config(ctx: Context<CancelSlashingConfig>) -> Result<()> {
+        emit!(SlashingConfigCancelled {
+            agent_pda: ctx.accounts.staking_pool.agent_pda,
+            execute_after: ctx.accounts.pending_slashing_config.execute_after,
+        });
+        Ok(())
+    }
+
+    // Queue a slash of `bps` of everything staked or unbonding in the pool, paid to
+    // `destination`. `reason_hash` points at off-chain evidence.
+    pub fn propose_slash(ctx: Context<ProposeSlash>, bps: u16, reason_hash: [u8; 32]) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        require!(bps > 0 && bps <= pool.slash_max_bps_per_epoch, StakingError::InvalidSlashAmount);
+        // Pools configured before the exit window existed are held to it here
+        check_slash_config(pool, pool.slash_authority, pool.slash_timelock_seconds)?;
+        let now = Clock::get()?.unix_timestamp;
+        let execute_after = now.saturating_add(pool.slash_timelock_seconds as i64);
+
+        let pending = &mut ctx.accounts.pending_slash;
+        pending.pool = pool.key();
+        pending.authority = ctx.accounts.authority.key();
+        pending.destination = ctx.accounts.destination.key();
+        pending.bps = bps;
+        pending.reason_hash = reason_hash;
+        pending.proposed_at = now;
+        pending.execute_after = execute_after;
+        pending.bump = ctx.bumps.pending_slash;
+        pool.flags |= POOL_FLAG_SLASH_PENDING;
+
+        emit!(SlashProposed {
+            agent_pda: pool.agent_pda,
+            authority: pending.authority,
+            destination: pending.destination,
+            bps,
+            reason_hash,
+            execute_after,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_slash(ctx: Context<CancelSlash>) -> Result<()> {
+        let pool = &mut ctx.accounts.staking_pool;
+        pool.flags &= !POOL_FLAG_SLASH_PENDING;
+        emit!(SlashCancelled { agent_pda: pool.agent_pda, bps: ctx.accounts.pending_slash.bps });
+        Ok(())
+    }
+
+    // Move the slashed tokens out of the vault. Shares are untouched, so every position,
+    // pending unstakes included, loses the same fraction of its value.
+    pub fn execute_slash(ctx: Context<ExecuteSlash>) -> Result<()> {
+        let pending = &ctx.accounts.pending_slash;
+        let pool = &mut ctx.accounts.staking_pool;
+        let clock = Clock::get()?;
+        require!(clock.unix_timestamp >= pending.execute_after, StakingError::SlashTimelockActive);
+
+        if clock.epoch != pool.slash_epoch {
+            pool.slash_epoch = clock.epoch;
+            pool.slashed_bps_in_epoch = 0;
+        }
+        let slashed_bps = pool.slashed_bps_in_epoch.saturating_add(pending.bps);
+        require!(slashed_bps <= pool.slash_max_bps_per_epoch, StakingError::SlashCapExceeded);
+
+        let vault_balance = token::accessor::amount(&ctx.accounts.token_vault)?;
+        let amount = (vault_balance as u128 * pending.bps as u128 / BPS_DENOMINATOR as u128) as u64;
+
+        if amount > 0 {
+            let agent_pda = pool.agent_pda;
+            let seeds = &[
+                b"staking_pool",
+                agent_pda.as_ref(),
+                &[pool.bump],
+            ];
+            let signer = &[&seeds[..]];
+            let cpi_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.token_vault.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    to: ctx.accounts.destination.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer,
+            );
+            token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.token_mint.decimals)?;
+        }
+
+        pool.slashed_bps_in_epoch = slashed_bps;
+        pool.flags &= !POOL_FLAG_SLASH_PENDING;
+
+        emit!(Slashed {
+            agent_pda: pool.agent_pda,
+            destination: pending.destination,
+            bps: pending.bps,
+            amount,
+            reason_hash: pending.reason_hash,
+        });
+        Ok(())
+    }
+
+    // Upgrade accounts written before shares, rewards, lockups and fee curves existed. The v1
+    // layouts had no version field, so they are recognised by their size and taken unchecked.
+    // Permissionless: the payer only covers the extra rent.
+    pub fn migrate_program_state(ctx: Context<MigrateProgramState>) -> Result<()> {
+        let info = ctx.accounts.program_state.to_account_info();
+        let migrated = read_v1::<ProgramStateV1>(&info, ProgramState::DISCRIMINATOR, ProgramStateV1::SPACE, ProgramState::SPACE)?.upgrade();
+        resize_account(&info, &ctx.accounts.payer.to_account_info(), &ctx.accounts.system_program.to_account_info(), 8 + ProgramState::SPACE)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        emit!(AccountMigrated { account: info.key() });
+        Ok(())
+    }
+
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+        let info = ctx.accounts.staking_pool.to_account_info();
+        let now = Clock::get()?.unix_timestamp;
+        let migrated = read_v1::<StakingPoolV1>(&info, StakingPool::DISCRIMINATOR, StakingPoolV1::SPACE, StakingPool::SPACE)?.upgrade(now);
+        resize_account(&info, &ctx.accounts.payer.to_account_info(), &ctx.accounts.system_program.to_account_info(), 8 + StakingPool::SPACE)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        emit!(AccountMigrated { account: info.key() });
+        Ok(())
+    }
+
+    pub fn migrate_stake_account(ctx: Context<MigrateStakeAccount>) -> Result<()> {
+        let info = ctx.accounts.stake_account.to_account_info();
+        let migrated = read_v1::<StakeAccountV1>(&info, StakeAccount::DISCRIMINATOR, StakeAccountV1::SPACE, StakeAccount::SPACE)?.upgrade();
+        resize_account(&info, &ctx.accounts.payer.to_account_info(), &ctx.accounts.system_program.to_account_info(), 8 + StakeAccount::SPACE)?;
+        migrated.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+        emit!(AccountMigrated { account: info.key() });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitProgramState<'info> {
+    #[account(
+        init,
+        payer = initializer,
+        space = 8 + ProgramState::SPACE,
+        seeds = [b"program_state"],
+        bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(initializer.key()) @ StakingError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub initializer: Signer<'info>,  // Must be the program upgrade authority
+    /// CHECK: treasury system account
+    pub treasury: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeFeeConfig<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingFeeConfig::SPACE,
+        seeds = [b"pending_fee_config"],
+        bump
+    )]
+    pub pending_fee_config: Account<'info, PendingFeeConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ StakingError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFeeConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_fee_config"],
+        bump = pending_fee_config.bump
+    )]
+    pub pending_fee_config: Account<'info, PendingFeeConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ StakingError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelFeeConfig<'info> {
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_fee_config"],
+        bump = pending_fee_config.bump
+    )]
+    pub pending_fee_config: Account<'info, PendingFeeConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ StakingError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+// REMOVED: TransferAuthority context - no central authority needed
+
+#[derive(Accounts)]
+pub struct CreateStakingPool<'info> {
+    /// FIX C-01 COMPLETE: Validation via lecture directe du compte agent-registry
+    /// Vérifie que l'agent existe ET a le flag HAS_STAKING activé
+    #[account(
+        constraint = agent.flags & FLAG_HAS_STAKING != 0 @ StakingError::StakingNotEnabled,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ StakingError::UnsupportedAgentVersion,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        seeds::program = agent_registry::ID
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + StakingPool::SPACE,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Token vault PDA (initialized via CPI in instruction) or existing ATA of the pool - validated in instruction
+    #[account(mut)]
+    pub token_vault: AccountInfo<'info>,
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Optional liquid receipt mint (same decimals as the staked token, pool PDA as authority).
+    /// Mints cannot be closed, so a pool recreated at the same address reuses its empty mint
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [b"receipt_mint", staking_pool.key().as_ref()],
+        bump,
+        mint::decimals = token_mint.decimals,
+        mint::authority = staking_pool
+    )]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = owner.key() == agent.owner @ StakingError::Unauthorized
+    )]
+    pub owner: Signer<'info>,  // Must be the current agent owner
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMinStake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SyncPoolOwner<'info> {
+    #[account(
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ StakingError::UnsupportedAgentVersion,
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        seeds::program = agent_registry::ID
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateEarlyUnlockPenalty<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolStatus<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolFees<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolFeeMode<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitFeeVault<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"fee_vault", staking_pool.key().as_ref()],
+        bump,
+        token::mint = token_mint,
+        token::authority = staking_pool,
+        token::token_program = token_program
+    )]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CollectPoolFees<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(mut, address = staking_pool.fee_vault @ StakingError::InvalidVault)]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == staking_pool.token_mint @ StakingError::InvalidTokenAccountMint,
+        constraint = owner_token_account.owner == staking_pool.owner @ StakingError::InvalidTokenAccountOwner
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = treasury_token_account.mint == staking_pool.token_mint @ StakingError::InvalidTokenAccountMint,
+        constraint = treasury_token_account.owner == program_state.treasury @ StakingError::InvalidTokenAccountOwner
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateUnbondingPeriod<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+// Context for initializing a new stake account
+#[derive(Accounts)]
+pub struct InitStake<'info> {
+    #[account(
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda in agent-registry program
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = staker,
+        space = 8 + StakeAccount::SPACE,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireLockup<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda in agent-registry program
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", stake_account.staker.as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetLockup<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda in agent-registry program
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ StakingError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    pub staker: Signer<'info>,
+}
+
+// Context for staking tokens (requires initialized stake account)
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated,
+        constraint = staking_pool.flags & POOL_FLAG_ACTIVE != 0 @ StakingError::PoolPaused
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(constraint = agent_pda.flags & FLAG_ACTIVE != 0 @ StakingError::AgentInactive)]
+    pub agent_pda: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ StakingError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Token vault to receive staked tokens - validated manually
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ StakingError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Staker's token account (source of tokens)
+    #[account(
+        mut,
+        constraint = staker_token_account.mint == staking_pool.token_mint @ StakingError::InvalidTokenAccountMint
+    )]
+    pub staker_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Receipt mint, required when the pool has one
+    #[account(mut, address = staking_pool.receipt_mint @ StakingError::InvalidReceiptMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, constraint = staker_receipt_account.mint == staking_pool.receipt_mint @ StakingError::InvalidReceiptMint)]
+    pub staker_receipt_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
 pub struct WithdrawStake<'info> {
     #[account(
-        seeds = [b"program_state"],
-        bump = program_state.bump
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda in agent-registry program
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        // FIX C-02: Ne plus fermer le compte pour conserver staked_at
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ StakingError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Token vault (source of returned tokens) - validated manually
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ StakingError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    /// Staked token mint (early unlock penalties are burned)
+    #[account(mut, address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Staker's token account (destination for returned tokens)
+    #[account(
+        mut,
+        constraint = staker_token_account.mint == staking_pool.token_mint @ StakingError::InvalidTokenAccountMint,
+        constraint = staker_token_account.owner == staker.key() @ StakingError::InvalidTokenAccountOwner
+    )]
+    pub staker_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Pool fee vault, required when a token-mode fee is charged
+    #[account(mut, address = staking_pool.fee_vault @ StakingError::InvalidVault)]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Receipt mint, required when the pool has one
+    #[account(mut, address = staking_pool.receipt_mint @ StakingError::InvalidReceiptMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, constraint = staker_receipt_account.mint == staking_pool.receipt_mint @ StakingError::InvalidReceiptMint)]
+    pub staker_receipt_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    /// CHECK: treasury account (receives lamports)
+    #[account(mut, address = program_state.treasury)]
+    pub treasury: UncheckedAccount<'info>,
+    /// CHECK: pool owner, receives its share of the fee. Required when the pool sets one
+    #[account(mut, address = staking_pool.owner @ StakingError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeRequest<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda in agent-registry program
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ StakingError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Token vault (prices the shares, pays token-mode fees) - validated manually
+    #[account(mut, constraint = token_vault.key() == staking_pool.token_vault @ StakingError::InvalidVault)]
+    pub token_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Pool fee vault, required when a token-mode fee is charged
+    #[account(mut, address = staking_pool.fee_vault @ StakingError::InvalidVault)]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Receipt mint, required when the pool has one
+    #[account(mut, address = staking_pool.receipt_mint @ StakingError::InvalidReceiptMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut, constraint = staker_receipt_account.mint == staking_pool.receipt_mint @ StakingError::InvalidReceiptMint)]
+    pub staker_receipt_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+    /// CHECK: treasury account (receives lamports)
+    #[account(mut, address = program_state.treasury)]
+    pub treasury: UncheckedAccount<'info>,
+    /// CHECK: pool owner, receives its share of the fee. Required when the pool sets one
+    #[account(mut, address = staking_pool.owner @ StakingError::Unauthorized)]
+    pub agent_owner: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteUnstake<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent_pda.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: agent pda in agent-registry program
+    pub agent_pda: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ StakingError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: Token vault (source of released tokens) - validated manually
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ StakingError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    /// Staked token mint (early unlock penalties are burned)
+    #[account(mut, address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Staker's token account (destination)
+    #[account(
+        mut,
+        constraint = staker_token_account.mint == staking_pool.token_mint @ StakingError::InvalidTokenAccountMint,
+        constraint = staker_token_account.owner == staker.key() @ StakingError::InvalidTokenAccountOwner
+    )]
+    pub staker_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub staker: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseStakeAccount<'info> {
+    #[account(
+        mut,
+        close = staker,
+        seeds = [b"stake_account", staker.key().as_ref(), stake_account.agent_pda.as_ref()],
+        bump = stake_account.bump,
+        constraint = stake_account.staker == staker.key() @ StakingError::Unauthorized
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    #[account(mut)]
+    pub staker: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseStakingPool<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized,
+        constraint = staking_pool.total_shares == 0 && staking_pool.total_unbonding == 0 @ StakingError::PoolNotEmpty,
+        constraint = staking_pool.flags & POOL_FLAG_SLASH_PENDING == 0 @ StakingError::SlashPending
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Token vault (closed) - validated manually
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ StakingError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// Receives the tokens left in the vault
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == staking_pool.token_mint @ StakingError::InvalidTokenAccountMint,
+        constraint = owner_token_account.owner == owner.key() @ StakingError::InvalidTokenAccountOwner
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Required when the pool has a fee vault
+    #[account(mut, address = staking_pool.fee_vault @ StakingError::InvalidVault)]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Receives the treasury's part of a non-empty fee vault
+    #[account(
+        mut,
+        constraint = treasury_token_account.mint == staking_pool.token_mint @ StakingError::InvalidTokenAccountMint,
+        constraint = treasury_token_account.owner == program_state.treasury @ StakingError::InvalidTokenAccountOwner
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Required when the pool has rewards
+    #[account(mut, address = staking_pool.reward_vault @ StakingError::InvalidRewardVault)]
+    pub reward_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Required with a non-empty reward vault
+    pub reward_mint: Option<InterfaceAccount<'info, Mint>>,
+    /// Receives what is left in the reward vault
+    #[account(mut)]
+    pub owner_reward_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Token program of the reward vault, when it differs from token_program
+    pub reward_token_program: Option<Interface<'info, TokenInterface>>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ExportPool<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        seeds::program = agent_registry::ID,
+        constraint = agent.version == AGENT_REGISTRY_VERSION @ StakingError::UnsupportedAgentVersion
+    )]
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        mut,
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated,
+        constraint = staking_pool.flags & POOL_FLAG_SLASH_PENDING == 0 @ StakingError::SlashPending
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Token vault (source) - validated manually
+    #[account(
+        mut,
+        constraint = token_vault.key() == staking_pool.token_vault @ StakingError::InvalidVault
+    )]
+    pub token_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: agent_platform vault for the same agent - address checked in the handler
+    #[account(mut)]
+    pub destination: AccountInfo<'info>,
+    #[account(constraint = owner.key() == staking_pool.owner @ StakingError::Unauthorized)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// Required when the pool has a fee vault, which must be empty
+    #[account(address = staking_pool.fee_vault @ StakingError::InvalidVault)]
+    pub fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// Required in receipt mode, with no receipts left
+    #[account(address = staking_pool.receipt_mint @ StakingError::InvalidReceiptMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    /// Required when the pool has rewards
+    #[account(mut, address = staking_pool.reward_vault @ StakingError::InvalidRewardVault)]
+    pub reward_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(address = staking_pool.reward_mint @ StakingError::InvalidTokenMint)]
+    pub reward_mint: Option<InterfaceAccount<'info, Mint>>,
+    /// CHECK: agent_platform reward vault for the same agent - address checked in the handler
+    #[account(mut)]
+    pub reward_destination: Option<AccountInfo<'info>>,
+    /// Token program of the reward vault, when it differs from token_program
+    pub reward_token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct ExportStake<'info> {
+    #[account(
+        seeds = [b"agent", agent.creator.as_ref(), agent.index_seed()],
+        bump = agent.bump,
+        seeds::program = agent_registry::ID
     )]
-    pub program_state: Account<'info, ProgramState>,
+    pub agent: Account<'info, AgentRegistry>,
+    #[account(
+        seeds = [b"staking_pool", agent.key().as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED != 0 @ StakingError::PoolNotExported
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = staker,
+        seeds = [b"stake_account", staker.key().as_ref(), agent.key().as_ref()],
+        bump = stake_account.bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    /// CHECK: owner of the position, receives the rent
+    #[account(mut)]
+    pub staker: UncheckedAccount<'info>,
+    /// agent_platform pool of the same agent - address checked in the handler
+    pub platform_pool: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = owner,
+        seeds = [b"reward_vault", staking_pool.key().as_ref()],
+        bump,
+        token::mint = reward_mint,
+        token::authority = staking_pool
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawRewardReserve<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized,
+        constraint = staking_pool.reward_vault != Pubkey::default() @ StakingError::RewardsNotInitialized,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Reward vault - validated manually
+    #[account(
+        mut,
+        constraint = reward_vault.key() == staking_pool.reward_vault @ StakingError::InvalidRewardVault
+    )]
+    pub reward_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.reward_mint @ StakingError::InvalidTokenMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = owner_reward_account.mint == staking_pool.reward_mint @ StakingError::InvalidTokenAccountMint,
+        constraint = owner_reward_account.owner == owner.key() @ StakingError::InvalidTokenAccountOwner
+    )]
+    pub owner_reward_account: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardRate<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.owner == owner.key() @ StakingError::Unauthorized,
+        constraint = staking_pool.reward_vault != Pubkey::default() @ StakingError::RewardsNotInitialized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewards<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.reward_vault != Pubkey::default() @ StakingError::RewardsNotInitialized,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    /// CHECK: Reward vault - validated manually
+    #[account(
+        mut,
+        constraint = reward_vault.key() == staking_pool.reward_vault @ StakingError::InvalidRewardVault
+    )]
+    pub reward_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.reward_mint @ StakingError::InvalidTokenMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    /// Funder's token account (source)
+    #[account(
+        mut,
+        constraint = funder_token_account.mint == staking_pool.reward_mint @ StakingError::InvalidTokenAccountMint
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub funder: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
     #[account(
         mut,
         seeds = [b"staking_pool", agent_pda.key().as_ref()],
-        bump = staking_pool.bump
+        bump = staking_pool.bump,
+        constraint = staking_pool.reward_vault != Pubkey::default() @ StakingError::RewardsNotInitialized,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
     )]
     pub staking_pool: Account<'info, StakingPool>,
     /// CHECK: agent pda in agent-registry program
     pub agent_pda: UncheckedAccount<'info>,
     #[account(
         mut,
-        // FIX C-02: Ne plus fermer le compte pour conserver staked_at
         seeds = [b"stake_account", staker.key().as_ref(), agent_pda.key().as_ref()],
         bump = stake_account.bump,
         constraint = stake_account.staker == staker.key() @ StakingError::Unauthorized
     )]
     pub stake_account: Account<'info, StakeAccount>,
-    /// CHECK: Token vault (source of returned tokens) - validated manually
+    /// CHECK: Reward vault - validated manually
+    #[account(
+        mut,
+        constraint = reward_vault.key() == staking_pool.reward_vault @ StakingError::InvalidRewardVault
+    )]
+    pub reward_vault: AccountInfo<'info>,
+    #[account(address = staking_pool.reward_mint @ StakingError::InvalidTokenMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    /// Staker's reward token account (destination)
+    #[account(
+        mut,
+        constraint = staker_reward_account.mint == staking_pool.reward_mint @ StakingError::InvalidTokenAccountMint,
+        constraint = staker_reward_account.owner == staker.key() @ StakingError::InvalidTokenAccountOwner
+    )]
+    pub staker_reward_account: InterfaceAccount<'info, TokenAccount>,
+    pub staker: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeSlashingConfig<'info> {
+    #[account(
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingSlashingConfig::SPACE,
+        seeds = [b"pending_slashing_config", staking_pool.key().as_ref()],
+        bump
+    )]
+    pub pending_slashing_config: Account<'info, PendingSlashingConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ StakingError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureSlashing<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_SLASH_PENDING == 0 @ StakingError::SlashPending
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_slashing_config", staking_pool.key().as_ref()],
+        bump = pending_slashing_config.bump
+    )]
+    pub pending_slashing_config: Account<'info, PendingSlashingConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ StakingError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelSlashingConfig<'info> {
+    #[account(
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_slashing_config", staking_pool.key().as_ref()],
+        bump = pending_slashing_config.bump
+    )]
+    pub pending_slashing_config: Account<'info, PendingSlashingConfig>,
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ StakingError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeSlash<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated,
+        constraint = staking_pool.slash_authority != Pubkey::default() @ StakingError::SlashingDisabled,
+        constraint = staking_pool.slash_authority == authority.key() @ StakingError::Unauthorized
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PendingSlash::SPACE,
+        seeds = [b"pending_slash", staking_pool.key().as_ref()],
+        bump
+    )]
+    pub pending_slash: Account<'info, PendingSlash>,
+    #[account(constraint = destination.mint == staking_pool.token_mint @ StakingError::InvalidSlashDestination)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelSlash<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_slash", staking_pool.key().as_ref()],
+        bump = pending_slash.bump,
+        constraint = pending_slash.authority == authority.key() @ StakingError::Unauthorized
+    )]
+    pub pending_slash: Account<'info, PendingSlash>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteSlash<'info> {
+    #[account(
+        mut,
+        seeds = [b"staking_pool", staking_pool.agent_pda.as_ref()],
+        bump = staking_pool.bump,
+        constraint = staking_pool.flags & POOL_FLAG_MIGRATED == 0 @ StakingError::PoolMigrated
+    )]
+    pub staking_pool: Account<'info, StakingPool>,
+    #[account(
+        mut,
+        close = authority,
+        seeds = [b"pending_slash", staking_pool.key().as_ref()],
+        bump = pending_slash.bump,
+        constraint = pending_slash.authority == authority.key() @ StakingError::Unauthorized
+    )]
+    pub pending_slash: Account<'info, PendingSlash>,
+    /// CHECK: Token vault (source) - validated manually
     #[account(
         mut,
         constraint = token_vault.key() == staking_pool.token_vault @ StakingError::InvalidVault
     )]
     pub token_vault: AccountInfo<'info>,
-    /// CHECK: Staker's token account (destination for returned tokens) - validated by SPL transfer
+    #[account(address = staking_pool.token_mint @ StakingError::InvalidTokenMint)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: Destination chosen at propose_slash
+    #[account(mut, address = pending_slash.destination @ StakingError::InvalidSlashDestination)]
+    pub destination: AccountInfo<'info>,
     #[account(mut)]
-    pub staker_token_account: AccountInfo<'info>,
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateProgramState<'info> {
+    /// CHECK: raw program state in the v1 layout; discriminator and size are checked in the handler
+    #[account(mut, owner = crate::ID, seeds = [b"program_state"], bump)]
+    pub program_state: UncheckedAccount<'info>,
     #[account(mut)]
-    pub staker: Signer<'info>,
-    /// CHECK: treasury account (receives lamports)
-    #[account(mut, address = program_state.treasury)]
-    pub treasury: UncheckedAccount<'info>,
-    pub token_program: Program<'info, Token>,
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    /// CHECK: raw staking pool in the v1 layout; discriminator and size are checked in the handler
+    #[account(mut, owner = crate::ID)]
+    pub staking_pool: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateStakeAccount<'info> {
+    /// CHECK: raw stake account in the v1 layout; discriminator and size are checked in the handler
+    #[account(mut, owner = crate::ID)]
+    pub stake_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
@@ -394,18 +2272,188 @@ pub struct ProgramState {
     pub decay_duration_seconds: u32,
     pub treasury: Pubkey,
     pub bump: u8,
+    pub fee_mode: u8,            // FEE_MODE_SOL or FEE_MODE_TOKEN
+    pub fee_immediate_bps: u16,  // Token mode curve, decays over decay_duration_seconds
+    pub fee_regular_bps: u16,
 }
 
 impl ProgramState {
-    pub const SPACE: usize = 8 + 8 + 8 + 4 + 32 + 1; // Removed authority field (32 bytes)
+    pub const SPACE: usize = 8 + 8 + 8 + 4 + 32 + 1 + 1 + 2 + 2; // Removed authority field (32 bytes)
     pub const DEFAULT_IMMEDIATE_FEE: u64 = 100_000_000; // 0.1 SOL
     pub const DEFAULT_REGULAR_FEE: u64 = 1_000_000;     // 0.001 SOL
     pub const DEFAULT_MAX_FEE: u64 = 100_000_000;       // 0.1 SOL (cap)
     pub const DEFAULT_DECAY_DURATION: u32 = 86_400;     // 24h
+    pub const DEFAULT_IMMEDIATE_FEE_BPS: u16 = 500;     // 5%
+    pub const DEFAULT_REGULAR_FEE_BPS: u16 = 10;        // 0.1%
+}
+
+#[account]
+pub struct PendingFeeConfig {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub proposed_at: i64,
+    pub execute_after: i64,
+    pub bump: u8,
+    pub fee_mode: u8,
+    pub fee_immediate_bps: u16,
+    pub fee_regular_bps: u16,
+}
+
+impl PendingFeeConfig {
+    pub const SPACE: usize = 8 + 8 + 8 + 4 + 8 + 8 + 1 + 1 + 2 + 2;
+}
+
+#[account]
+pub struct StakingPool {
+    pub agent_pda: Pubkey,
+    pub owner: Pubkey,
+    pub token_mint: Pubkey,
+    pub token_vault: Pubkey,
+    pub min_stake_amount: u64,
+    pub total_shares: u64,              // Active shares, redeemable pro-rata against the vault with total_unbonding
+    pub staker_count: u32,
+    pub created_at: i64,
+    pub flags: u8,
+    pub bump: u8,
+    // Rewards (Pubkey::default() until init_rewards)
+    pub reward_mint: Pubkey,
+    pub reward_vault: Pubkey,
+    pub reward_per_token_stored: u128,  // Scaled by REWARD_PRECISION
+    pub reward_rate: u64,               // Reward tokens emitted per second
+    pub reward_reserve: u64,            // Funded but not yet emitted
+    pub last_update_time: i64,
+    pub unbonding_period_seconds: u32,  // Cooldown between unstake_request and complete_unstake
+    pub total_weighted_stake: u64,      // Sum of StakeAccount.weighted_amount, drives reward share
+    pub early_unlock_penalty_bps: u16,  // 0 = exits before lock_ends_at are rejected
+    // Slashing (authority = Pubkey::default() until configure_slashing)
+    pub slash_authority: Pubkey,
+    pub slash_max_bps_per_epoch: u16,
+    pub slash_timelock_seconds: u32,
+    pub slash_epoch: u64,               // Epoch slashed_bps_in_epoch refers to
+    pub slashed_bps_in_epoch: u16,
+    pub receipt_mint: Pubkey,           // Liquid receipt mint, Pubkey::default() = no receipts
+    // Owner fee curve (fee_decay_duration_seconds = 0 until update_pool_fees: ProgramState curve)
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_decay_duration_seconds: u32,
+    pub fee_owner_share_bps: u16,       // Part of each unstake fee paid to the pool owner
+    pub fee_mode: u8,                   // FEE_MODE_DEFAULT = ProgramState.fee_mode
+    pub fee_vault: Pubkey,              // Token-mode fees, Pubkey::default() until init_fee_vault
+    pub owner_token_fees: u64,          // Owner's share of the fee vault, paid by collect_pool_fees
+    pub rewards_owed_total: u64,        // Sum of StakeAccount.rewards_owed: settled, not claimed yet
+    pub total_unbonding: u64,           // Shares queued by unstake_request, out of total_shares
+}
+
+impl StakingPool {
+    pub const SPACE: usize = 32 + 32 + 32 + 32 + 8 + 8 + 4 + 8 + 1 + 1 + 32 + 32 + 16 + 8 + 8 + 8 + 4 + 8 + 2
+        + 32 + 2 + 4 + 8 + 2 + 32 + 8 + 8 + 4 + 2 + 1 + 32 + 8 + 8 + 8;
+    pub const MAX_UNBONDING_PERIOD: u32 = 30 * 86_400;  // 30 days
+    pub const MAX_FEE_DECAY_DURATION: u32 = 30 * 86_400;
+}
+
+#[account]
+pub struct StakeAccount {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    // OPTIMIZATION: pool supprimé, peut être dérivé de agent_pda
+    // Économie: 32 bytes = 0.00024 SOL par staker
+    pub shares: u64,  // Claim on the vault: shares * vault balance / (total_shares + total_unbonding)
+    pub staked_at: i64,
+    pub last_updated_at: i64,
+    pub bump: u8,
+    pub reward_per_token_paid: u128,  // Pool accumulator at the last settlement
+    pub rewards_owed: u64,
+    // Pending unstakes: still in the vault (in total_unbonding), but no longer earning rewards
+    pub unbonding: [UnbondingRequest; MAX_UNBONDING_REQUESTS],
+    // Lockup (tier 0 = unlocked)
+    pub lock_tier: u8,
+    pub lock_ends_at: i64,
+    pub lock_penalty_bps: u16,        // Pool penalty when the lockup was chosen
+    pub weighted_amount: u64,         // shares scaled by the tier weight
+    pub rewards_checkpoint_at: i64,   // When reward_per_token_paid was taken
+}
+
+impl StakeAccount {
+    pub const SPACE: usize = 32 + 32 + 8 + 8 + 8 + 1 + 16 + 8
+        + UnbondingRequest::SPACE * MAX_UNBONDING_REQUESTS + 1 + 8 + 2 + 8 + 8;  // 236 bytes
+}
+
+// One unstake_request, free while shares == 0
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct UnbondingRequest {
+    pub shares: u64,
+    pub penalty_shares: u64,  // Part of shares burned on completion
+    pub ends_at: i64,
+}
+
+impl UnbondingRequest {
+    pub const SPACE: usize = 8 + 8 + 8;
+}
+
+#[account]
+pub struct PendingSlash {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub destination: Pubkey,  // Token account of the pool mint
+    pub bps: u16,
+    pub reason_hash: [u8; 32],
+    pub proposed_at: i64,
+    pub execute_after: i64,
+    pub bump: u8,
+}
+
+impl PendingSlash {
+    pub const SPACE: usize = 32 + 32 + 32 + 2 + 32 + 8 + 8 + 1;
+}
+
+#[account]
+pub struct PendingSlashingConfig {
+    pub pool: Pubkey,
+    pub authority: Pubkey,
+    pub max_bps_per_epoch: u16,
+    pub timelock_seconds: u32,
+    pub proposed_at: i64,
+    pub execute_after: i64,
+    pub bump: u8,
+}
+
+impl PendingSlashingConfig {
+    pub const SPACE: usize = 32 + 32 + 2 + 4 + 8 + 8 + 1;
+}
+
+// Frozen v1 layouts (no version field), only read by the migrate_* instructions
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct ProgramStateV1 {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub treasury: Pubkey,
+    pub bump: u8,
+}
+
+impl ProgramStateV1 {
+    pub const SPACE: usize = 8 + 8 + 8 + 4 + 32 + 1;
+
+    pub fn upgrade(self) -> ProgramState {
+        ProgramState {
+            fee_immediate_lamports: self.fee_immediate_lamports,
+            fee_regular_lamports: self.fee_regular_lamports,
+            fee_max_lamports: self.fee_max_lamports,
+            decay_duration_seconds: self.decay_duration_seconds,
+            treasury: self.treasury,
+            bump: self.bump,
+            fee_mode: FEE_MODE_SOL,
+            fee_immediate_bps: ProgramState::DEFAULT_IMMEDIATE_FEE_BPS,
+            fee_regular_bps: ProgramState::DEFAULT_REGULAR_FEE_BPS,
+        }
+    }
 }
 
-#[account]
-pub struct StakingPool {
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct StakingPoolV1 {
     pub agent_pda: Pubkey,
     pub owner: Pubkey,
     pub token_mint: Pubkey,
@@ -418,37 +2466,660 @@ pub struct StakingPool {
     pub bump: u8,
 }
 
-impl StakingPool {
+impl StakingPoolV1 {
     pub const SPACE: usize = 32 + 32 + 32 + 32 + 8 + 8 + 4 + 8 + 1 + 1;
+
+    // v1 stakes were plain token amounts: one share per token, unlocked at 1x weight
+    pub fn upgrade(self, now: i64) -> StakingPool {
+        StakingPool {
+            agent_pda: self.agent_pda,
+            owner: self.owner,
+            token_mint: self.token_mint,
+            token_vault: self.token_vault,
+            min_stake_amount: self.min_stake_amount,
+            total_shares: self.total_staked,
+            staker_count: self.staker_count,
+            created_at: self.created_at,
+            flags: self.flags,
+            bump: self.bump,
+            reward_mint: Pubkey::default(),
+            reward_vault: Pubkey::default(),
+            reward_per_token_stored: 0,
+            reward_rate: 0,
+            reward_reserve: 0,
+            last_update_time: now,
+            unbonding_period_seconds: 0,
+            total_weighted_stake: self.total_staked,
+            early_unlock_penalty_bps: 0,
+            slash_authority: Pubkey::default(),
+            slash_max_bps_per_epoch: 0,
+            slash_timelock_seconds: 0,
+            slash_epoch: 0,
+            slashed_bps_in_epoch: 0,
+            receipt_mint: Pubkey::default(),
+            fee_immediate_lamports: 0,
+            fee_regular_lamports: 0,
+            fee_decay_duration_seconds: 0,
+            fee_owner_share_bps: 0,
+            fee_mode: FEE_MODE_DEFAULT,
+            fee_vault: Pubkey::default(),
+            owner_token_fees: 0,
+            rewards_owed_total: 0,
+            total_unbonding: 0,
+        }
+    }
 }
 
-#[account]
-pub struct StakeAccount {
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct StakeAccountV1 {
     pub staker: Pubkey,
     pub agent_pda: Pubkey,
-    // OPTIMIZATION: pool supprimé, peut être dérivé de agent_pda
-    // Économie: 32 bytes = 0.00024 SOL par staker
     pub staked_amount: u64,
     pub staked_at: i64,
     pub last_updated_at: i64,
     pub bump: u8,
 }
 
-impl StakeAccount {
-    pub const SPACE: usize = 32 + 32 + 8 + 8 + 8 + 1;  // 89 bytes (vs 121)
+impl StakeAccountV1 {
+    pub const SPACE: usize = 32 + 32 + 8 + 8 + 8 + 1;
+
+    pub fn upgrade(self) -> StakeAccount {
+        StakeAccount {
+            staker: self.staker,
+            agent_pda: self.agent_pda,
+            shares: self.staked_amount,
+            staked_at: self.staked_at,
+            last_updated_at: self.last_updated_at,
+            bump: self.bump,
+            reward_per_token_paid: 0,  // Counted in total_weighted_stake since the pool migrated
+            rewards_owed: 0,
+            unbonding: [UnbondingRequest::default(); MAX_UNBONDING_REQUESTS],
+            lock_tier: 0,
+            lock_ends_at: 0,
+            lock_penalty_bps: 0,
+            weighted_amount: self.staked_amount,
+            rewards_checkpoint_at: 0,
+        }
+    }
+}
+
+// Accrue emissions since the last update into reward_per_token_stored. Emission is
+// capped by the funded reserve and paused while nothing is staked.
+fn update_rewards(pool: &mut StakingPool, now: i64) -> Result<()> {
+    let elapsed = now.saturating_sub(pool.last_update_time).max(0) as u64;
+    pool.last_update_time = now;
+    if pool.total_weighted_stake == 0 || pool.reward_rate == 0 || elapsed == 0 {
+        return Ok(());
+    }
+    let emitted = elapsed.saturating_mul(pool.reward_rate).min(pool.reward_reserve);
+    let per_token = (emitted as u128) * REWARD_PRECISION / pool.total_weighted_stake as u128;
+    pool.reward_per_token_stored = pool
+        .reward_per_token_stored
+        .checked_add(per_token)
+        .ok_or(StakingError::MathOverflow)?;
+    pool.reward_reserve -= emitted;
+    Ok(())
+}
+
+// Move what the position earned since its last checkpoint into rewards_owed. The part an
+// ended lockup's boost would have taken after lock_ends_at goes back to the reward reserve.
+fn settle_rewards(pool: &mut StakingPool, stake_acc: &mut StakeAccount, now: i64) -> Result<()> {
+    let delta = pool.reward_per_token_stored.saturating_sub(stake_acc.reward_per_token_paid);
+    let boost_ends_at = (stake_acc.lock_tier != 0).then_some(stake_acc.lock_ends_at);
+    let (earned, forfeited) = accrued_rewards(
+        stake_acc.weighted_amount,
+        stake_acc.shares,
+        boost_ends_at,
+        stake_acc.rewards_checkpoint_at,
+        delta,
+        now,
+    )?;
+    stake_acc.rewards_owed = stake_acc.rewards_owed.checked_add(earned).ok_or(StakingError::MathOverflow)?;
+    pool.rewards_owed_total = pool.rewards_owed_total.checked_add(earned).ok_or(StakingError::MathOverflow)?;
+    pool.reward_reserve = pool.reward_reserve.checked_add(forfeited).ok_or(StakingError::MathOverflow)?;
+    stake_acc.reward_per_token_paid = pool.reward_per_token_stored;
+    stake_acc.rewards_checkpoint_at = now;
+    Ok(())
+}
+
+// Rewards for an accumulator increase of `delta` between `checkpoint_at` and `now`, as
+// (earned, forfeited). Past `boost_ends_at` only the unlocked weight earns; the accumulator at
+// that time is not stored, so emissions are taken as even over the interval.
+fn accrued_rewards(
+    weighted_amount: u64,
+    shares: u64,
+    boost_ends_at: Option<i64>,
+    checkpoint_at: i64,
+    delta: u128,
+    now: i64,
+) -> Result<(u64, u64)> {
+    let weighted = weighted_amount as u128;
+    let full = weighted.checked_mul(delta).ok_or(StakingError::MathOverflow)? / REWARD_PRECISION;
+    let earned = match boost_ends_at {
+        Some(ends_at) if now > ends_at => {
+            let unlocked = (shares as u128 * LOCKUP_TIERS[0].1 as u128 / BPS_DENOMINATOR as u128).min(weighted);
+            let elapsed = now.saturating_sub(checkpoint_at).max(1) as u128;
+            let boosted = (ends_at.saturating_sub(checkpoint_at).max(0) as u128).min(elapsed);
+            let boost_delta = delta * boosted / elapsed;
+            unlocked
+                .checked_mul(delta)
+                .and_then(|base| (weighted - unlocked).checked_mul(boost_delta).and_then(|boost| base.checked_add(boost)))
+                .ok_or(StakingError::MathOverflow)?
+                / REWARD_PRECISION
+        }
+        _ => full,
+    };
+    let earned = u64::try_from(earned).map_err(|_| StakingError::MathOverflow)?;
+    let forfeited = u64::try_from(full).map_err(|_| StakingError::MathOverflow)? - earned;
+    Ok((earned, forfeited))
+}
+
+// Shares minted for depositing `amount` into a vault holding `vault_balance`, priced with
+// VIRTUAL_SHARES on both sides: 1:1 into an empty pool.
+fn amount_to_shares(pool: &StakingPool, amount: u64, vault_balance: u64) -> Result<u64> {
+    let outstanding = pool.total_shares as u128 + pool.total_unbonding as u128;
+    let shares = amount as u128 * (outstanding + VIRTUAL_SHARES as u128)
+        / (vault_balance as u128 + VIRTUAL_SHARES as u128);
+    u64::try_from(shares).map_err(|_| error!(StakingError::MathOverflow))
+}
+
+// Tokens `shares` redeem for at the same virtual price, never more than their pro-rata part
+// of the vault so a slashed vault (fewer tokens than shares) stays solvent. Rounds down,
+// so rounding dust stays with the pool.
+fn shares_to_amount(pool: &StakingPool, shares: u64, vault_balance: u64) -> Result<u64> {
+    let outstanding = pool.total_shares as u128 + pool.total_unbonding as u128;
+    if outstanding == 0 {
+        return Ok(0);
+    }
+    let virtual_amount = shares as u128 * (vault_balance as u128 + VIRTUAL_SHARES as u128)
+        / (outstanding + VIRTUAL_SHARES as u128);
+    let pro_rata = shares as u128 * vault_balance as u128 / outstanding;
+    u64::try_from(virtual_amount.min(pro_rata)).map_err(|_| error!(StakingError::MathOverflow))
+}
+
+fn mul_div(value: u64, num: u64, denom: u64) -> Result<u64> {
+    let scaled = (value as u128 * num as u128)
+        .checked_div(denom as u128)
+        .ok_or(StakingError::MathOverflow)?;
+    u64::try_from(scaled).map_err(|_| error!(StakingError::MathOverflow))
+}
+
+// Recompute the position's weight after a change of amount or tier (call after settle_rewards)
+fn reweight(pool: &mut StakingPool, stake_acc: &mut StakeAccount) -> Result<()> {
+    let (_, weight_bps) = LOCKUP_TIERS[stake_acc.lock_tier as usize];
+    let weighted = (stake_acc.shares as u128 * weight_bps as u128) / BPS_DENOMINATOR as u128;
+    let weighted = u64::try_from(weighted).map_err(|_| StakingError::MathOverflow)?;
+    pool.total_weighted_stake = pool
+        .total_weighted_stake
+        .saturating_sub(stake_acc.weighted_amount)
+        .checked_add(weighted)
+        .ok_or(StakingError::MathOverflow)?;
+    stake_acc.weighted_amount = weighted;
+    Ok(())
+}
+
+// Drop an ended lockup back to tier 0 (after settle_rewards, which stops crediting the boost
+// at lock_ends_at). expire_lockup lets anyone do this for a position its staker leaves alone.
+fn end_lockup(pool: &mut StakingPool, stake_acc: &mut StakeAccount, now: i64) -> Result<()> {
+    if stake_acc.lock_tier == 0 || now < stake_acc.lock_ends_at {
+        return Ok(());
+    }
+    stake_acc.lock_tier = 0;
+    stake_acc.lock_ends_at = 0;
+    stake_acc.lock_penalty_bps = 0;
+    reweight(pool, stake_acc)
+}
+
+// Part of `amount` (tokens or shares) burned when it leaves a position that is still locked
+fn early_unlock_penalty(pool: &StakingPool, stake_acc: &StakeAccount, amount: u64, now: i64) -> Result<u64> {
+    // A deprecated pool releases every lock so stakers can leave for free
+    if now >= stake_acc.lock_ends_at || pool.flags & POOL_FLAG_DEPRECATED != 0 {
+        return Ok(0);
+    }
+    require!(stake_acc.lock_penalty_bps > 0, StakingError::StakeLocked);
+    Ok((amount as u128 * stake_acc.lock_penalty_bps as u128 / BPS_DENOMINATOR as u128) as u64)
+}
+
+// The slash authority is never the pool owner, and its timelock leaves room to unstake
+fn check_slash_config(pool: &StakingPool, authority: Pubkey, timelock_seconds: u32) -> Result<()> {
+    if authority == Pubkey::default() {
+        return Ok(());
+    }
+    require_keys_neq!(authority, pool.owner, StakingError::SlashAuthorityIsOwner);
+    require!(
+        timelock_seconds as u64 >= pool.unbonding_period_seconds as u64 + MIN_SLASH_EXIT_WINDOW_SECONDS as u64,
+        StakingError::SlashTimelockTooShort
+    );
+    Ok(())
+}
+
+fn burn_from_vault<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    vault: &AccountInfo<'info>,
+    pool: &Account<'info, StakingPool>,
+    amount: u64,
+) -> Result<()> {
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        Burn {
+            mint: mint.to_account_info(),
+            from: vault.clone(),
+            authority: pool.to_account_info(),
+        },
+        signer,
+    );
+    token_interface::burn(cpi_ctx, amount)
+}
+
+// Pay the fee vault out: the owner's share of token-mode fees to the pool owner, the rest
+// (treasury share and direct transfers) to the treasury. Returns (owner_amount, treasury_amount).
+fn pay_out_fee_vault<'info>(
+    pool: &Account<'info, StakingPool>,
+    token_program: &AccountInfo<'info>,
+    fee_vault: &InterfaceAccount<'info, TokenAccount>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    owner_token_account: &AccountInfo<'info>,
+    treasury_token_account: &AccountInfo<'info>,
+) -> Result<(u64, u64)> {
+    let balance = fee_vault.amount;
+    let owner_amount = pool.owner_token_fees.min(balance);
+    let treasury_amount = balance - owner_amount;
+
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    for (to, amount) in [(owner_token_account, owner_amount), (treasury_token_account, treasury_amount)] {
+        if amount == 0 {
+            continue;
+        }
+        let cpi_ctx = CpiContext::new_with_signer(
+            token_program.clone(),
+            TransferChecked {
+                from: fee_vault.to_account_info(),
+                mint: token_mint.to_account_info(),
+                to: to.clone(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, amount, token_mint.decimals)?;
+    }
+    Ok((owner_amount, treasury_amount))
+}
+
+// Pay out and close the fee vault of a closing pool, if it has one. A non-empty vault needs
+// the treasury token account.
+fn close_fee_vault<'info>(
+    pool: &Account<'info, StakingPool>,
+    token_program: &AccountInfo<'info>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    fee_vault: Option<&InterfaceAccount<'info, TokenAccount>>,
+    owner_token_account: &AccountInfo<'info>,
+    treasury_token_account: Option<&InterfaceAccount<'info, TokenAccount>>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    if pool.fee_vault == Pubkey::default() {
+        return Ok(());
+    }
+    let fee_vault = fee_vault.ok_or(StakingError::PoolVaultsRequired)?;
+    require_keys_eq!(fee_vault.key(), pool.fee_vault, StakingError::InvalidVault);
+    if fee_vault.amount > 0 {
+        let treasury_token_account = treasury_token_account.ok_or(StakingError::PoolVaultsRequired)?;
+        pay_out_fee_vault(pool, token_program, fee_vault, token_mint, owner_token_account, &treasury_token_account.to_account_info())?;
+    }
+    close_pool_token_account(token_program, &fee_vault.to_account_info(), destination, pool)
+}
+
+// Sweep and close the reward vault of a closing pool, if it has one. Rewards settled to
+// stakers must have been claimed; the rest (unemitted reserve, rounding dust, direct
+// transfers) goes to the owner's reward token account.
+fn close_reward_vault<'info>(
+    pool: &Account<'info, StakingPool>,
+    reward_token_program: &AccountInfo<'info>,
+    reward_vault: Option<&InterfaceAccount<'info, TokenAccount>>,
+    reward_mint: Option<&InterfaceAccount<'info, Mint>>,
+    owner_reward_account: Option<&InterfaceAccount<'info, TokenAccount>>,
+    destination: &AccountInfo<'info>,
+) -> Result<()> {
+    if pool.reward_vault == Pubkey::default() {
+        return Ok(());
+    }
+    let reward_vault = reward_vault.ok_or(StakingError::PoolVaultsRequired)?;
+    require_keys_eq!(reward_vault.key(), pool.reward_vault, StakingError::InvalidRewardVault);
+    require!(pool.rewards_owed_total == 0, StakingError::RewardsUnclaimed);
+    if reward_vault.amount > 0 {
+        let (Some(reward_mint), Some(owner_reward_account)) = (reward_mint, owner_reward_account) else {
+            return err!(StakingError::PoolVaultsRequired);
+        };
+        require_keys_eq!(reward_mint.key(), pool.reward_mint, StakingError::InvalidTokenMint);
+        require_keys_eq!(owner_reward_account.mint, pool.reward_mint, StakingError::InvalidTokenAccountMint);
+        require_keys_eq!(owner_reward_account.owner, pool.owner, StakingError::InvalidTokenAccountOwner);
+
+        let agent_pda = pool.agent_pda;
+        let seeds = &[
+            b"staking_pool",
+            agent_pda.as_ref(),
+            &[pool.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            reward_token_program.clone(),
+            TransferChecked {
+                from: reward_vault.to_account_info(),
+                mint: reward_mint.to_account_info(),
+                to: owner_reward_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            signer,
+        );
+        token_interface::transfer_checked(cpi_ctx, reward_vault.amount, reward_mint.decimals)?;
+    }
+    close_pool_token_account(reward_token_program, &reward_vault.to_account_info(), destination, pool)
+}
+
+// Close a token account of the pool (pool PDA signs), rent to `destination`
+fn close_pool_token_account<'info>(
+    token_program: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    pool: &Account<'info, StakingPool>,
+) -> Result<()> {
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.clone(),
+        CloseAccount {
+            account: account.clone(),
+            destination: destination.clone(),
+            authority: pool.to_account_info(),
+        },
+        signer,
+    );
+    token_interface::close_account(cpi_ctx)
+}
+
+// An associated token account of the pool can serve as vault: it must already exist and hold the pool mint
+fn check_ata_vault<'info>(
+    vault: &AccountInfo<'info>,
+    pool: &Pubkey,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    let ata = get_associated_token_address_with_program_id(pool, &mint.key(), token_program.key);
+    require_keys_eq!(vault.key(), ata, StakingError::InvalidVault);
+    let vault = TokenAccount::try_deserialize(&mut &vault.try_borrow_data()?[..])?;
+    require_keys_eq!(vault.mint, mint.key(), StakingError::InvalidTokenAccountMint);
+    require_keys_eq!(vault.owner, *pool, StakingError::InvalidTokenAccountOwner);
+    Ok(())
+}
+
+// Receipt accounts of a stake/exit. Required when the pool has a receipt mint, ignored otherwise.
+fn receipt_accounts<'a, 'info>(
+    pool: &StakingPool,
+    mint: &'a Option<InterfaceAccount<'info, Mint>>,
+    account: &'a Option<InterfaceAccount<'info, TokenAccount>>,
+) -> Result<Option<(&'a InterfaceAccount<'info, Mint>, &'a InterfaceAccount<'info, TokenAccount>)>> {
+    if pool.receipt_mint == Pubkey::default() {
+        return Ok(None);
+    }
+    match (mint, account) {
+        (Some(mint), Some(account)) => Ok(Some((mint, account))),
+        _ => err!(StakingError::ReceiptAccountsRequired),
+    }
+}
+
+// Mint receipts 1:1 with newly minted shares (pool PDA is the mint authority)
+fn mint_receipts<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    pool: &Account<'info, StakingPool>,
+    shares: u64,
+) -> Result<()> {
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        MintTo {
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: pool.to_account_info(),
+        },
+        signer,
+    );
+    token_interface::mint_to(cpi_ctx, shares)
+}
+
+fn burn_receipts<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    owner: &Signer<'info>,
+    shares: u64,
+) -> Result<()> {
+    let cpi_ctx = CpiContext::new(
+        token_program.to_account_info(),
+        Burn {
+            mint: mint.to_account_info(),
+            from: from.to_account_info(),
+            authority: owner.to_account_info(),
+        },
+    );
+    token_interface::burn(cpi_ctx, shares)
+}
+
+// Validate an exit of `shares` and return the shares left in the position
+fn check_unstake_shares(pool: &StakingPool, stake_acc: &StakeAccount, shares: u64, vault_balance: u64) -> Result<u64> {
+    // Receipt pools: the exit redeems the receipts it burns, positions hold no shares
+    if pool.receipt_mint != Pubkey::default() {
+        require!(shares > 0, StakingError::InvalidStakeAmount);
+        return Ok(0);
+    }
+    require!(stake_acc.shares > 0, StakingError::NoStake);
+    require!(shares > 0, StakingError::InvalidStakeAmount);
+    require!(shares <= stake_acc.shares, StakingError::InsufficientStake);
+    let remaining = stake_acc.shares - shares;
+    // A partial exit must leave a position that could have been opened on its own
+    require!(
+        remaining == 0 || shares_to_amount(pool, remaining, vault_balance)? >= pool.min_stake_amount,
+        StakingError::BelowMinimumStake
+    );
+    Ok(remaining)
+}
+
+// Time-decayed SOL fee for taking `shares` out of the position (before its shares are
+// reduced), on the pool curve if the owner set one. Never above the global max.
+fn unstake_fee(state: &ProgramState, pool: &StakingPool, stake_acc: &StakeAccount, shares: u64, now: i64) -> Result<u64> {
+    if pool.flags & POOL_FLAG_DEPRECATED != 0 {
+        return Ok(0);
+    }
+    let elapsed = (now - stake_acc.staked_at).max(0) as u64;
+    // FIX H-01: propagate error
+    let full_fee = if pool.fee_decay_duration_seconds > 0 {
+        // The curve was checked against the global bounds of the time it was set: keep it
+        // within the current ones, which update_fee_config may have moved since
+        calculate_unstake_fee(
+            elapsed,
+            pool.fee_immediate_lamports,
+            pool.fee_regular_lamports,
+            state.fee_max_lamports,
+            pool.fee_decay_duration_seconds,
+        )?
+        .max(state.fee_regular_lamports)
+        .min(state.fee_max_lamports)
+    } else {
+        calculate_unstake_fee(
+            elapsed,
+            state.fee_immediate_lamports,
+            state.fee_regular_lamports,
+            state.fee_max_lamports,
+            state.decay_duration_seconds,
+        )?
+    };
+    // Receipt pools keep no shares on the stake account to prorate against: each exit pays the
+    // full fee for the account's age, so splitting a redemption cannot lower it
+    if pool.receipt_mint != Pubkey::default() {
+        return Ok(full_fee);
+    }
+    prorate_fee(full_fee, shares, stake_acc.shares)
+}
+
+// Collect `fee` from the staker: the pool owner's share goes to the owner, the rest to the
+// treasury. The staker must keep a rent-exempt balance afterwards. Returns the owner's share.
+fn charge_unstake_fee<'info>(
+    state: &ProgramState,
+    pool: &StakingPool,
+    fee: u64,
+    staker: &AccountInfo<'info>,
+    treasury: &AccountInfo<'info>,
+    agent_owner: &Option<UncheckedAccount<'info>>,
+) -> Result<u64> {
+    if fee == 0 {
+        return Ok(0);
+    }
+
+    // FIX H-02: Check suffisance SOL AVANT transfer
+    let rent_exempt = Rent::get()?.minimum_balance(0);
+    require!(
+        staker.lamports() >= fee.saturating_add(rent_exempt),
+        StakingError::InsufficientSolForFee
+    );
+    let owner_fee = mul_div(fee, pool.fee_owner_share_bps as u64, BPS_DENOMINATOR)?;
+    if owner_fee > 0 {
+        let owner = agent_owner.as_ref().ok_or(StakingError::PoolOwnerAccountRequired)?;
+        let ix = system_instruction::transfer(staker.key, &pool.owner, owner_fee);
+        solana_program::program::invoke(&ix, &[staker.clone(), owner.to_account_info()])?;
+    }
+    let ix = system_instruction::transfer(staker.key, &state.treasury, fee - owner_fee);
+    solana_program::program::invoke(&ix, &[staker.clone(), treasury.clone()])?;
+    Ok(owner_fee)
+}
+
+// Share of the position-level fee charged for withdrawing `shares` out of `held`,
+// rounded up so splitting an exit into small withdrawals cannot dodge the fee
+fn prorate_fee(fee: u64, shares: u64, held: u64) -> Result<u64> {
+    require!(held > 0, StakingError::NoStake);
+    let prorated = (fee as u128 * shares as u128).div_ceil(held as u128);
+    Ok(prorated as u64)
+}
+
+fn fee_mode(state: &ProgramState, pool: &StakingPool) -> u8 {
+    if pool.fee_mode == FEE_MODE_DEFAULT { state.fee_mode } else { pool.fee_mode }
+}
+
+// Token-mode fee rate of the position, decaying like the SOL fee
+fn token_fee_bps(state: &ProgramState, pool: &StakingPool, stake_acc: &StakeAccount, now: i64) -> Result<u64> {
+    if pool.flags & POOL_FLAG_DEPRECATED != 0 {
+        return Ok(0);
+    }
+    let elapsed = (now - stake_acc.staked_at).max(0) as u64;
+    calculate_unstake_fee(
+        elapsed,
+        state.fee_immediate_bps as u64,
+        state.fee_regular_bps as u64,
+        MAX_TOKEN_FEE_BPS as u64,
+        state.decay_duration_seconds,
+    )
+}
+
+// Move a token-mode fee from the pool vault to the fee vault, setting the owner's share aside
+fn charge_token_fee<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    mint: &InterfaceAccount<'info, Mint>,
+    vault: &AccountInfo<'info>,
+    fee_vault: &Option<InterfaceAccount<'info, TokenAccount>>,
+    pool: &mut Account<'info, StakingPool>,
+    fee: u64,
+) -> Result<()> {
+    let fee_vault = fee_vault.as_ref().ok_or(StakingError::FeeVaultRequired)?;
+    let agent_pda = pool.agent_pda;
+    let seeds = &[
+        b"staking_pool",
+        agent_pda.as_ref(),
+        &[pool.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        TransferChecked {
+            from: vault.clone(),
+            mint: mint.to_account_info(),
+            to: fee_vault.to_account_info(),
+            authority: pool.to_account_info(),
+        },
+        signer,
+    );
+    token_interface::transfer_checked(cpi_ctx, fee, mint.decimals)?;
+    let owner_fee = mul_div(fee, pool.fee_owner_share_bps as u64, BPS_DENOMINATOR)?;
+    pool.owner_token_fees = pool.owner_token_fees.saturating_add(owner_fee);
+    Ok(())
 }
 
 // FIX H-01: Retourner Result pour gérer division par zéro
-fn calculate_unstake_fee(elapsed_secs: u64, state: &ProgramState) -> Result<u64> {
-    require!(state.decay_duration_seconds > 0, StakingError::InvalidFeeConfig);
+// Decode an account still in its v1 layout, recognised by its size
+fn read_v1<T: AnchorDeserialize>(info: &AccountInfo, discriminator: &[u8], v1_space: usize, space: usize) -> Result<T> {
+    let data = info.try_borrow_data()?;
+    require!(data.len() >= 8 && data[..8] == *discriminator, StakingError::UnsupportedLayout);
+    match data.len() - 8 {
+        len if len == v1_space => Ok(T::deserialize(&mut &data[8..])?),
+        len if len == space => err!(StakingError::AlreadyMigrated),
+        _ => err!(StakingError::UnsupportedLayout),
+    }
+}
+
+// Resize an account, settling rent with `payer`: the surplus is refunded when it shrinks,
+// the difference is paid when it grows
+fn resize_account<'info>(
+    info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    new_len: usize,
+) -> Result<()> {
+    let required = Rent::get()?.minimum_balance(new_len);
+    let current = info.lamports();
+    if current > required {
+        **info.try_borrow_mut_lamports()? -= current - required;
+        **payer.try_borrow_mut_lamports()? += current - required;
+    } else if required > current {
+        let ix = system_instruction::transfer(payer.key, info.key, required - current);
+        solana_program::program::invoke(&ix, &[payer.clone(), info.clone(), system_program.clone()])?;
+    }
+    info.resize(new_len)?;
+    Ok(())
+}
+
+fn calculate_unstake_fee(elapsed_secs: u64, immediate: u64, regular: u64, max: u64, decay_duration_seconds: u32) -> Result<u64> {
+    require!(decay_duration_seconds > 0, StakingError::InvalidFeeConfig);
     
-    if elapsed_secs >= state.decay_duration_seconds as u64 {
-        return Ok(state.fee_regular_lamports.min(state.fee_max_lamports));
+    if elapsed_secs >= decay_duration_seconds as u64 {
+        return Ok(regular.min(max));
     }
     
-    let immediate = state.fee_immediate_lamports as u128;
-    let regular = state.fee_regular_lamports as u128;
-    let duration = state.decay_duration_seconds as u128;
+    let immediate = immediate as u128;
+    let regular = regular as u128;
+    let duration = decay_duration_seconds as u128;
     let elapsed = elapsed_secs as u128;
     let diff = immediate.saturating_sub(regular);
     
@@ -459,7 +3130,7 @@ fn calculate_unstake_fee(elapsed_secs: u64, state: &ProgramState) -> Result<u64>
         .ok_or(StakingError::InvalidFeeConfig)?;
     
     let fee = immediate.saturating_sub(reduction);
-    Ok(fee.min(state.fee_max_lamports as u128) as u64)
+    Ok(fee.min(max as u128) as u64)
 }
 
 #[error_code]
@@ -468,6 +3139,8 @@ pub enum StakingError {
     InvalidMinStakeAmount,
     #[msg("Invalid fee configuration")]
     InvalidFeeConfig,
+    #[msg("Fee config timelock has not elapsed")]
+    FeeConfigTimelockActive,
     #[msg("Invalid stake amount")]
     InvalidStakeAmount,
     #[msg("No stake to withdraw")]
@@ -480,11 +3153,143 @@ pub enum StakingError {
     BelowMinimumStake,
     #[msg("Insufficient SOL to pay unstaking fee")]
     InsufficientSolForFee,
+    #[msg("Pool owner account is required for the fee split")]
+    PoolOwnerAccountRequired,
+    #[msg("Fee vault is required in token fee mode")]
+    FeeVaultRequired,
     #[msg("Invalid agent PDA")]
     InvalidAgent,
     // REMOVED: InvalidAuthority - no central authority in zero-admin architecture
     #[msg("Agent does not have staking enabled")]
     StakingNotEnabled,
+    #[msg("Agent account uses an outdated layout, run migrate_agent")]
+    UnsupportedAgentVersion,
+    #[msg("Staking pool was migrated to agent_platform")]
+    PoolMigrated,
+    #[msg("Destination is not the agent_platform vault for this agent")]
+    InvalidMigrationTarget,
+    #[msg("Rewards are not initialized for this pool")]
+    RewardsNotInitialized,
+    #[msg("Rewards are already initialized for this pool")]
+    RewardsAlreadyInitialized,
+    #[msg("Invalid reward vault")]
+    InvalidRewardVault,
+    #[msg("Invalid reward amount")]
+    InvalidRewardAmount,
+    #[msg("No rewards to claim")]
+    NoRewards,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Withdraw amount exceeds staked amount")]
+    InsufficientStake,
+    #[msg("Pool has an unbonding period, use unstake_request")]
+    UnbondingRequired,
+    #[msg("Unbonding period exceeds the maximum")]
+    InvalidUnbondingPeriod,
+    #[msg("No pending unstake")]
+    NoPendingUnstake,
+    #[msg("Unbonding period has not ended")]
+    UnbondingNotFinished,
+    #[msg("Invalid lockup tier")]
+    InvalidLockupTier,
+    #[msg("Lockup cannot be shortened")]
+    LockupCannotBeShortened,
+    #[msg("Stake is locked")]
+    StakeLocked,
+    #[msg("Invalid early unlock penalty")]
+    InvalidPenalty,
+    #[msg("Slashing is not configured for this pool")]
+    SlashingDisabled,
+    #[msg("Invalid slashing configuration")]
+    InvalidSlashConfig,
+    #[msg("Invalid slash amount")]
+    InvalidSlashAmount,
+    #[msg("Slash destination must hold the pool token")]
+    InvalidSlashDestination,
+    #[msg("Slash timelock has not elapsed")]
+    SlashTimelockActive,
+    #[msg("Slash exceeds the per-epoch cap")]
+    SlashCapExceeded,
+    #[msg("A slash is pending for this pool")]
+    SlashPending,
+    #[msg("Slash authority cannot be the pool owner")]
+    SlashAuthorityIsOwner,
+    #[msg("Slash timelock must exceed the unbonding period by the exit window")]
+    SlashTimelockTooShort,
+    #[msg("Slashing config timelock has not elapsed")]
+    SlashingConfigTimelockActive,
+    #[msg("Receipt mint and receipt token account are required for this pool")]
+    ReceiptAccountsRequired,
+    #[msg("Invalid receipt mint")]
+    InvalidReceiptMint,
+    #[msg("Token mint does not match the pool")]
+    InvalidTokenMint,
+    #[msg("Token account does not hold the pool token")]
+    InvalidTokenAccountMint,
+    #[msg("Token account is not owned by the staker")]
+    InvalidTokenAccountOwner,
+    #[msg("Stake account still holds shares, a pending unstake or rewards")]
+    StakeAccountNotEmpty,
+    #[msg("Stake account changed too recently to be closed")]
+    ReopenDelayActive,
+    #[msg("Pool still has shares")]
+    PoolNotEmpty,
+    #[msg("Fee and reward vaults of the pool are required")]
+    PoolVaultsRequired,
+    #[msg("Stakers still have unclaimed rewards")]
+    RewardsUnclaimed,
+    #[msg("Pool is paused, no new stake accepted")]
+    PoolPaused,
+    #[msg("Pool is deprecated")]
+    PoolDeprecated,
+    #[msg("Agent is not active")]
+    AgentInactive,
+    #[msg("Amount exceeds the unemitted reward reserve")]
+    InsufficientRewardReserve,
+    #[msg("Too many pending unstake requests, complete one first")]
+    TooManyUnbondingRequests,
+    #[msg("Receipt pools have no stake account positions")]
+    ReceiptPoolWithoutPositions,
+    #[msg("Collect the pool's token-mode fees first")]
+    FeesNotCollected,
+    #[msg("Receipts of the pool are still outstanding")]
+    ReceiptsOutstanding,
+    #[msg("Account layout is not supported, expected a v1 account")]
+    UnsupportedLayout,
+    #[msg("Account already uses the current layout")]
+    AlreadyMigrated,
+    #[msg("Lockup has not ended")]
+    LockupNotEnded,
+    #[msg("Staking pool has not been exported to agent_platform")]
+    PoolNotExported,
+}
+
+#[event]
+pub struct FeeConfigProposed {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub fee_mode: u8,
+    pub fee_immediate_bps: u16,
+    pub fee_regular_bps: u16,
+    pub execute_after: i64,
+}
+
+#[event]
+pub struct FeeConfigUpdated {
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub fee_max_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub fee_mode: u8,
+    pub fee_immediate_bps: u16,
+    pub fee_regular_bps: u16,
+}
+
+#[event]
+pub struct FeeConfigCancelled {
+    pub execute_after: i64,
 }
 
 #[event]
@@ -492,6 +3297,14 @@ pub struct PoolCreated {
     pub agent_pda: Pubkey,
     pub owner: Pubkey,
     pub min_stake_amount: u64,
+    pub receipt_mint: Pubkey,
+}
+
+#[event]
+pub struct PoolOwnerSynced {
+    pub agent_pda: Pubkey,
+    pub old_owner: Pubkey,
+    pub new_owner: Pubkey,
 }
 
 #[event]
@@ -506,7 +3319,8 @@ pub struct Staked {
     pub staker: Pubkey,
     pub agent_pda: Pubkey,
     pub amount: u64,
-    pub total: u64,
+    pub shares: u64,
+    pub total: u64,  // Shares held by the position
 }
 
 #[event]
@@ -514,7 +3328,202 @@ pub struct Withdrawn {
     pub staker: Pubkey,
     pub agent_pda: Pubkey,
     pub amount: u64,
+    pub shares: u64,
+    pub fee: u64,
+    pub owner_fee: u64,
+    pub token_fee: u64,
+    pub penalty: u64,
+}
+
+#[event]
+pub struct UnbondingPeriodUpdated {
+    pub agent_pda: Pubkey,
+    pub old_period_seconds: u32,
+    pub new_period_seconds: u32,
+}
+
+#[event]
+pub struct UnstakeRequested {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub shares: u64,
     pub fee: u64,
+    pub owner_fee: u64,
+    pub token_fee: u64,
+    pub penalty_shares: u64,
+    pub unlock_at: i64,
+}
+
+#[event]
+pub struct UnstakeCompleted {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub shares: u64,
+    pub amount: u64,
+    pub penalty: u64,
+}
+
+#[event]
+pub struct EarlyUnlockPenaltyUpdated {
+    pub agent_pda: Pubkey,
+    pub penalty_bps: u16,
+}
+
+#[event]
+pub struct PoolPausedSet {
+    pub agent_pda: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct PoolDeprecated {
+    pub agent_pda: Pubkey,
+}
+
+#[event]
+pub struct PoolFeesUpdated {
+    pub agent_pda: Pubkey,
+    pub fee_immediate_lamports: u64,
+    pub fee_regular_lamports: u64,
+    pub decay_duration_seconds: u32,
+    pub owner_share_bps: u16,
+}
+
+#[event]
+pub struct PoolFeeModeUpdated {
+    pub agent_pda: Pubkey,
+    pub fee_mode: u8,
+}
+
+#[event]
+pub struct PoolFeesCollected {
+    pub agent_pda: Pubkey,
+    pub owner_amount: u64,
+    pub treasury_amount: u64,
+}
+
+#[event]
+pub struct LockupSet {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub tier: u8,
+    pub lock_ends_at: i64,
+    pub weighted_amount: u64,
+}
+
+#[event]
+pub struct LockupExpired {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub weighted_amount: u64,
+}
+
+#[event]
+pub struct RewardRateUpdated {
+    pub agent_pda: Pubkey,
+    pub reward_rate: u64,
+}
+
+#[event]
+pub struct RewardReserveWithdrawn {
+    pub agent_pda: Pubkey,
+    pub amount: u64,
+    pub reward_reserve: u64,  // Left after the withdrawal
+}
+
+#[event]
+pub struct RewardsFunded {
+    pub agent_pda: Pubkey,
+    pub funder: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct PoolExported {
+    pub agent_pda: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub reward_amount: u64,
+}
+
+#[event]
+pub struct StakeExported {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+    pub shares: u64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub account: Pubkey,
+}
+
+#[event]
+pub struct SlashingConfigProposed {
+    pub agent_pda: Pubkey,
+    pub authority: Pubkey,
+    pub max_bps_per_epoch: u16,
+    pub timelock_seconds: u32,
+    pub execute_after: i64,
+}
+
+#[event]
+pub struct SlashingConfigured {
+    pub agent_pda: Pubkey,
+    pub authority: Pubkey,
+    pub max_bps_per_epoch: u16,
+    pub timelock_seconds: u32,
+}
+
+#[event]
+pub struct SlashingConfigCancelled {
+    pub agent_pda: Pubkey,
+    pub execute_after: i64,
+}
+
+#[event]
+pub struct SlashProposed {
+    pub agent_pda: Pubkey,
+    pub authority: Pubkey,
+    pub destination: Pubkey,
+    pub bps: u16,
+    pub reason_hash: [u8; 32],
+    pub execute_after: i64,
+}
+
+#[event]
+pub struct SlashCancelled {
+    pub agent_pda: Pubkey,
+    pub bps: u16,
+}
+
+#[event]
+pub struct Slashed {
+    pub agent_pda: Pubkey,
+    pub destination: Pubkey,
+    pub bps: u16,
+    pub amount: u64,
+    pub reason_hash: [u8; 32],
+}
+
+#[event]
+pub struct StakeAccountClosed {
+    pub staker: Pubkey,
+    pub agent_pda: Pubkey,
+}
+
+#[event]
+pub struct StakingPoolClosed {
+    pub agent_pda: Pubkey,
+    pub owner: Pubkey,
+    pub leftover: u64,
 }
 
 // REMOVED: AuthorityTransferred event - no central authority in zero-admin architecture
@@ -226,7 +226,7 @@ async function main() {
   log.info(`  Owner: ${poolAccount.owner.toBase58()}`);
   log.info(`  Token Mint: ${poolAccount.tokenMint.toBase58()}`);
   log.info(`  Min Stake: ${poolAccount.minStakeAmount.toString()} tokens`);
-  log.info(`  Total Staked: ${poolAccount.totalStaked.toString()} tokens`);
+  log.info(`  Total Shares: ${poolAccount.totalShares.toString()}`);
 
   console.log("\n" + "-".repeat(70));
   log.step("ÉTAPE 5: Stake des tokens");
@@ -249,7 +249,7 @@ async function main() {
     stakePda,
     agentStakingProgramId
   );
-  log.info(`  Staked Amount: ${stakeAccount?.stakedAmount.toString()} tokens`);
+  log.info(`  Shares: ${stakeAccount?.shares.toString()}`);
 
   console.log("\n" + "-".repeat(70));
   log.step("ÉTAPE 6: Lister tous les pools");
@@ -261,7 +261,7 @@ async function main() {
   });
   log.success(`Trouvé ${allPools.length} pool(s)`);
   for (const pool of allPools) {
-    log.info(`  ${pool.pubkey.toBase58()}: ${pool.account.totalStaked.toString()} staked`);
+    log.info(`  ${pool.pubkey.toBase58()}: ${pool.account.totalShares.toString()} shares`);
   }
 
   console.log("\n" + "-".repeat(70));
@@ -278,7 +278,7 @@ async function main() {
   );
   log.success(`Trouvé ${userStakes.length} stake(s) pour ce user`);
   for (const stake of userStakes) {
-    log.info(`  ${stake.pubkey.toBase58()}: ${stake.account.stakedAmount.toString()} tokens`);
+    log.info(`  ${stake.pubkey.toBase58()}: ${stake.account.shares.toString()} shares`);
   }
 
   console.log("\n" + "=".repeat(70));
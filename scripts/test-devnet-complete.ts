@@ -228,6 +228,10 @@ async function main() {
       .initProgramState()
       .accounts({
         programState: programStatePda,
+        programData: PublicKey.findProgramAddressSync(
+          [agentStakingProgramId.toBuffer()],
+          new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+        )[0],
         authority: wallet.publicKey,
         treasury: wallet.publicKey,
         systemProgram: SystemProgram.programId,
@@ -247,12 +251,14 @@ async function main() {
   log.info(`Agent PDA: ${agentPda.toBase58()}`);
 
   const createAgentTx = await agentRegistryProgram.methods
-    .createAgent(
-      creator.publicKey,
-      null, // card_uri
-      null, // card_hash
-      true  // has_staking = TRUE
-    )
+    .createAgent(creator.publicKey, {
+      cardUri: "https://example.com/card.json",
+      cardHash: Array.from(new Uint8Array(32)),
+      hasStaking: true,
+      memoryMode: null,
+      memoryPtr: null,
+      memoryHash: null,
+    })
     .accounts({
       agent: agentPda,
       admin: wallet.publicKey,
@@ -288,6 +294,7 @@ async function main() {
       stakingPool: poolPda,
       tokenVault: vaultPda,
       tokenMint: tokenMint,
+      receiptMint: null,
       owner: wallet.publicKey,
       systemProgram: SystemProgram.programId,
       tokenProgram: TOKEN_PROGRAM_ID,
@@ -301,7 +308,7 @@ async function main() {
   log.info(`  Token Mint: ${poolAccount.tokenMint.toBase58()}`);
   log.info(`  Token Vault: ${poolAccount.tokenVault.toBase58()}`);
   log.info(`  Min Stake: ${poolAccount.minStakeAmount.toString()} tokens`);
-  log.info(`  Total Staked: ${poolAccount.totalStaked.toString()}`);
+  log.info(`  Total Shares: ${poolAccount.totalShares.toString()}`);
 
   console.log("\n" + "-".repeat(70));
   log.step("ÉTAPE 5: Stake des tokens");
@@ -322,6 +329,7 @@ async function main() {
       agentPda: agentPda,
       stakeAccount: stakeAccountPda,
       tokenVault: vaultPda,
+      tokenMint: tokenMint,
       stakerTokenAccount: userTokenAccount.address,
       staker: wallet.publicKey,
       tokenProgram: TOKEN_PROGRAM_ID,
@@ -334,7 +342,7 @@ async function main() {
   const stakeAccountData = await agentStakingProgram.account.stakeAccount.fetch(
     stakeAccountPda
   );
-  log.info(`  Staked Amount: ${stakeAccountData.stakedAmount.toString()} tokens`);
+  log.info(`  Shares: ${stakeAccountData.shares.toString()}`);
   log.info(`  Staked At: ${new Date(stakeAccountData.stakedAt.toNumber() * 1000).toISOString()}`);
 
   // Verify vault balance
@@ -357,13 +365,14 @@ async function main() {
   log.info(`Balance SOL avant withdraw: ${balanceBefore / LAMPORTS_PER_SOL} SOL`);
 
   const withdrawTx = await agentStakingProgram.methods
-    .withdrawStake()
+    .withdrawStake(new anchor.BN(stakeAmount))
     .accounts({
       programState: programStatePda,
       stakingPool: poolPda,
       agentPda: agentPda,
       stakeAccount: stakeAccountPda,
       tokenVault: vaultPda,
+      tokenMint,
       stakerTokenAccount: userTokenAccount.address,
       staker: wallet.publicKey,
       treasury: wallet.publicKey, // On est le treasury aussi
@@ -377,16 +386,16 @@ async function main() {
   const feePaid = balanceBefore - balanceAfter;
   log.info(`Fee payée: ${feePaid / LAMPORTS_PER_SOL} SOL`);
 
-  // Verify stake account (should have 0 staked_amount)
+  // Verify stake account (should have 0 shares)
   const stakeAccountAfter = await agentStakingProgram.account.stakeAccount.fetch(
     stakeAccountPda
   );
-  log.info(`  Staked Amount after: ${stakeAccountAfter.stakedAmount.toString()}`);
+  log.info(`  Shares after: ${stakeAccountAfter.shares.toString()}`);
 
-  if (stakeAccountAfter.stakedAmount.toNumber() !== 0) {
-    log.error("Staked amount devrait être 0!");
+  if (stakeAccountAfter.shares.toNumber() !== 0) {
+    log.error("Shares devraient être à 0!");
   } else {
-    log.success("Staked amount correctement réinitialisé à 0");
+    log.success("Shares correctement réinitialisées à 0");
   }
 
   // Verify tokens returned
@@ -405,7 +414,7 @@ async function main() {
   console.log(`  ✅ Stake de ${stakeAmount} tokens`);
   console.log(`  ✅ Withdraw avec fee de ${feePaid / LAMPORTS_PER_SOL} SOL`);
   console.log(`  ✅ Tokens récupérés`);
-  console.log(`  ✅ Stake account réinitialisé (staked_amount=0)`);
+  console.log(`  ✅ Stake account réinitialisé (shares=0)`);
 
   console.log("\n📝 ADDRESSES:");
   console.log(`  Agent PDA: ${agentPda.toBase58()}`);
@@ -241,7 +241,7 @@ async function main() {
 
   // Fetch stake account
   const stakeAccountData = await (stakingProgram.account as any).stakeAccount.fetch(stakePda);
-  log.info(`  Staked Amount: ${stakeAccountData.stakedAmount.toString()} tokens`);
+  log.info(`  Shares: ${stakeAccountData.shares.toString()}`);
   log.info(`  Staked At: ${new Date(stakeAccountData.stakedAt.toNumber() * 1000).toISOString()}`);
 
   // Verify vault balance
@@ -280,12 +280,12 @@ async function main() {
 
   // Verify stake account
   const stakeAccountAfter = await (stakingProgram.account as any).stakeAccount.fetch(stakePda);
-  log.info(`  Staked Amount après: ${stakeAccountAfter.stakedAmount.toString()}`);
+  log.info(`  Shares après: ${stakeAccountAfter.shares.toString()}`);
 
-  if (stakeAccountAfter.stakedAmount.toNumber() !== 0) {
-    log.error("Staked amount devrait être 0!");
+  if (stakeAccountAfter.shares.toNumber() !== 0) {
+    log.error("Shares devraient être à 0!");
   } else {
-    log.success("✅ FIX C-02: Staked amount réinitialisé à 0 (compte existe toujours)");
+    log.success("✅ FIX C-02: Shares réinitialisées à 0 (compte existe toujours)");
   }
 
   // Verify tokens returned
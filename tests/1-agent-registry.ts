@@ -10,7 +10,34 @@ function deriveAgentPda(creator: web3.PublicKey, programId: web3.PublicKey) {
   return PublicKey.findProgramAddressSync([Buffer.from("agent"), creator.toBuffer()], programId);
 }
 
-// Staking PDAs are in a separate program; this test only toggles the flag via set_has_staking.
+function deriveAgentCounterPda(creator: web3.PublicKey, programId: web3.PublicKey) {
+  return PublicKey.findProgramAddressSync([Buffer.from("agent_counter"), creator.toBuffer()], programId);
+}
+
+function derivePendingOwnerPda(agentPda: web3.PublicKey, programId: web3.PublicKey) {
+  return PublicKey.findProgramAddressSync([Buffer.from("pending_owner"), agentPda.toBuffer()], programId);
+}
+
+function deriveDelegatePda(agentPda: web3.PublicKey, delegate: web3.PublicKey, programId: web3.PublicKey) {
+  return PublicKey.findProgramAddressSync([Buffer.from("delegate"), agentPda.toBuffer(), delegate.toBuffer()], programId);
+}
+
+function deriveAgentKeyPda(agentPda: web3.PublicKey, programId: web3.PublicKey) {
+  return PublicKey.findProgramAddressSync([Buffer.from("agent_key"), agentPda.toBuffer()], programId);
+}
+
+function deriveIndexedAgentPda(creator: web3.PublicKey, index: number, programId: web3.PublicKey) {
+  const indexLe = Buffer.alloc(4);
+  indexLe.writeUInt32LE(index);
+  return PublicKey.findProgramAddressSync([Buffer.from("agent"), creator.toBuffer(), indexLe], programId);
+}
+
+// Staking PDAs are in a separate program; this test only clears the flag via disable_staking.
+const AGENT_STAKING_ID = new PublicKey("j3WMvorrddakwt69dqrQ5cve5APpyd4bxUCb9UF9Aqj");
+
+function deriveStakingPoolPda(agentPda: web3.PublicKey) {
+  return PublicKey.findProgramAddressSync([Buffer.from("staking_pool"), agentPda.toBuffer()], AGENT_STAKING_ID);
+}
 
 describe("agent-registry", () => {
   anchor.setProvider(anchor.AnchorProvider.env());
@@ -104,7 +131,7 @@ describe("agent-registry", () => {
     const memHashArray = memoryHash ? (memoryHash instanceof Uint8Array ? Array.from(memoryHash) : memoryHash) : null;
     
     await ((program.methods as any)
-      .createAgent(creator, cardUri, hashArray, hasStaking, memoryMode, ptrBuffer, memHashArray) as any)
+      .createAgent(creator, { cardUri, cardHash: hashArray, hasStaking, memoryMode, memoryPtr: ptrBuffer, memoryHash: memHashArray }) as any)
       .accountsPartial({ 
         agent: agentPda, 
         creatorSigner: provider.wallet.publicKey,
@@ -235,7 +262,7 @@ describe("agent-registry", () => {
     );
 
     const acc = await program.account.agentRegistry.fetch(agentPda);
-    expect(acc.cardUriLen).to.be.greaterThan(0);
+    expect(acc.cardUri.length).to.be.greaterThan(0);
     expect(Array.from(acc.cardHash)).to.deep.equal(Array.from(initHash));
 
     await rpc(
@@ -271,13 +298,14 @@ describe("agent-registry", () => {
     const [agentPda] = deriveAgentPda(creator, program.programId);
     await rpc(
       ((program.methods as any)
-        .createAgent(
-          creator,
-          "https://example.com/test-card.json",
-          correctHashArray,
-          false,
-          null, null, null
-        ) as any)
+        .createAgent(creator, {
+          cardUri: "https://example.com/test-card.json",
+          cardHash: correctHashArray,
+          hasStaking: false,
+          memoryMode: null,
+          memoryPtr: null,
+          memoryHash: null,
+        }) as any)
         .accountsPartial({
           agent: agentPda,
           creatorSigner: provider.wallet.publicKey,
@@ -377,7 +405,7 @@ describe("agent-registry", () => {
 
     const acc = await program.account.agentRegistry.fetch(agentPda);
     expect(acc.memoryMode).to.eq(4);
-    expect(acc.memoryPtrLen).to.eq(ptr.length);
+    expect(acc.memoryPtr.length).to.eq(ptr.length);
     expect(Array.from(acc.memoryHash)).to.deep.equal(Array.from(hash));
 
     // Cleanup
@@ -413,6 +441,40 @@ describe("agent-registry", () => {
     );
   });
 
+  it("disableStaking lets an agent without staking pool be closed", async () => {
+    const creator = provider.wallet.publicKey;
+    const [agentPda] = deriveAgentPda(creator, program.programId);
+    const [stakingPool] = deriveStakingPoolPda(agentPda);
+    await createAgentHelper(creator, "https://example.com/card.json", Array.from(new Uint8Array(32)), true);
+    await rpc(
+      program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }),
+      "setActive(false)"
+    );
+
+    await expectFail(
+      program.methods
+        .closeAgent()
+        .accountsPartial({ agent: agentPda, owner: creator, recipient: creator })
+        .rpc(),
+      ["StakingEnabled"]
+    );
+
+    // No pool was ever created in agent_staking
+    await rpc(
+      program.methods.disableStaking().accountsPartial({ agent: agentPda, stakingPool, owner: creator }),
+      "disableStaking"
+    );
+    const acc = await program.account.agentRegistry.fetch(agentPda);
+    expect(acc.flags & 4).to.eq(0);
+
+    await rpc(
+      program.methods
+        .closeAgent()
+        .accountsPartial({ agent: agentPda, owner: creator, recipient: creator }),
+      "closeAgent"
+    );
+  });
+
   it("memory None zero-fills ptr and len=0", async () => {
     const creator = provider.wallet.publicKey; // Must be signer
     const [agentPda] = deriveAgentPda(creator, program.programId);
@@ -435,8 +497,7 @@ describe("agent-registry", () => {
     );
 
     const acc = await program.account.agentRegistry.fetch(agentPda);
-    expect(acc.memoryPtrLen).to.eq(0);
-    expect(Array.from(acc.memoryPtr)).to.deep.equal(Array.from(new Uint8Array(96).fill(0)));
+    expect(acc.memoryPtr.length).to.eq(0);
 
     // Cleanup
     await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: provider.wallet.publicKey }).rpc();
@@ -578,7 +639,7 @@ describe("agent-registry", () => {
 
     const acc = await program.account.agentRegistry.fetch(agentPda);
     expect(acc.memoryMode).to.eq(memoryMode);
-    expect(acc.memoryPtrLen).to.eq(memoryPtr.length);
+    expect(acc.memoryPtr.length).to.eq(memoryPtr.length);
     expect(Array.from(acc.memoryHash)).to.deep.equal(memoryHash);
     
     // Cleanup
@@ -628,6 +689,303 @@ describe("agent-registry", () => {
     await program.methods.closeAgent().accountsPartial({ agent: agentPda, owner: provider.wallet.publicKey, recipient: provider.wallet.publicKey }).rpc();
   });
 
+  it("propose_owner / accept_owner / cancel_owner_transfer two-step flow", async () => {
+    const creator = provider.wallet.publicKey;
+    const agentPda = await createAgentHelper(creator, "https://example.com/card.json", Array.from(new Uint8Array(32)), false);
+    const [pendingPda] = derivePendingOwnerPda(agentPda, program.programId);
+    const newOwnerKeypair = Keypair.generate();
+    const newOwner = newOwnerKeypair.publicKey;
+
+    // Propose then cancel: ownership unchanged, proposal closed
+    await rpc(
+      program.methods.proposeOwner(newOwner, null).accountsPartial({ agent: agentPda, pendingOwner: pendingPda, owner: creator }),
+      "proposeOwner"
+    );
+    await rpc(
+      program.methods.cancelOwnerTransfer().accountsPartial({ agent: agentPda, pendingOwner: pendingPda, owner: creator, proposer: creator }),
+      "cancelOwnerTransfer"
+    );
+    expect(await program.account.pendingOwner.fetchNullable(pendingPda)).to.eq(null);
+    let acc = await program.account.agentRegistry.fetch(agentPda);
+    expect(acc.owner.toBase58()).to.eq(creator.toBase58());
+
+    // Propose again; only the proposed key can accept
+    await program.methods.proposeOwner(newOwner, null).accountsPartial({ agent: agentPda, pendingOwner: pendingPda, owner: creator }).rpc();
+    const intruder = Keypair.generate();
+    await expectFail(
+      program.methods.acceptOwner()
+        .accountsPartial({ agent: agentPda, pendingOwner: pendingPda, newOwner: intruder.publicKey, proposer: creator })
+        .signers([intruder])
+        .rpc(),
+      ["Unauthorized"]
+    );
+    await rpc(
+      program.methods.acceptOwner()
+        .accountsPartial({ agent: agentPda, pendingOwner: pendingPda, newOwner, proposer: creator })
+        .signers([newOwnerKeypair]),
+      "acceptOwner"
+    );
+    acc = await program.account.agentRegistry.fetch(agentPda);
+    expect(acc.owner.toBase58()).to.eq(newOwner.toBase58());
+
+    // transfer_owner closes a pending proposal, so it cannot be accepted if ownership comes back
+    await program.methods.proposeOwner(intruder.publicKey, null).accountsPartial({ agent: agentPda, pendingOwner: pendingPda, owner: newOwner }).signers([newOwnerKeypair]).rpc();
+    await expectFail(
+      program.methods.transferOwner(creator).accountsPartial({ agent: agentPda, owner: newOwner }).signers([newOwnerKeypair]).rpc(),
+      ["Proposer of the pending owner transfer is required"]
+    );
+    await program.methods.transferOwner(creator).accountsPartial({ agent: agentPda, owner: newOwner, proposer: newOwner }).signers([newOwnerKeypair]).rpc();
+    expect(await program.account.pendingOwner.fetchNullable(pendingPda)).to.eq(null);
+    await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }).rpc();
+    await program.methods.closeAgent().accountsPartial({ agent: agentPda, owner: creator, recipient: creator }).rpc();
+  });
+
+  it("delegate with memory-only permission can set_memory but not set_card", async () => {
+    const creator = provider.wallet.publicKey;
+    const agentPda = await createAgentHelper(creator, "https://example.com/card.json", Array.from(new Uint8Array(32)), false);
+    const operator = Keypair.generate();
+    const [delegatePda] = deriveDelegatePda(agentPda, operator.publicKey, program.programId);
+    const PERM_CARD = 1;
+    const PERM_MEMORY = 2;
+
+    await rpc(
+      program.methods.addDelegate(operator.publicKey, PERM_MEMORY, null)
+        .accountsPartial({ agent: agentPda, delegate: delegatePda, owner: creator }),
+      "addDelegate (memory only)"
+    );
+
+    const ptr = Buffer.from("QmT5NvUtoM5nWFfrQdVrFtvGfKFmG7AHE8P34isapyhCxX");
+    await rpc(
+      program.methods.setMemory(1, ptr, null)
+        .accountsPartial({ agent: agentPda, delegate: delegatePda, owner: operator.publicKey })
+        .signers([operator]),
+      "setMemory by delegate"
+    );
+    const acc = await program.account.agentRegistry.fetch(agentPda);
+    expect(acc.memoryMode).to.eq(1);
+
+    await expectFail(
+      program.methods.setCard("https://example.com/other.json", Array.from(new Uint8Array(32)))
+        .accountsPartial({ agent: agentPda, delegate: delegatePda, owner: operator.publicKey })
+        .signers([operator])
+        .rpc(),
+      ["MissingPermission"]
+    );
+
+    // Grant card rights too, then revoke the delegate entirely
+    await program.methods.updateDelegate(PERM_CARD | PERM_MEMORY, null)
+      .accountsPartial({ agent: agentPda, delegate: delegatePda, owner: creator })
+      .rpc();
+    await program.methods.setCard("https://example.com/other.json", Array.from(new Uint8Array(32)))
+      .accountsPartial({ agent: agentPda, delegate: delegatePda, owner: operator.publicKey })
+      .signers([operator])
+      .rpc();
+
+    // Shrinking the card as a delegate refunds the freed rent to the owner, not the delegate
+    const operatorBefore = await provider.connection.getBalance(operator.publicKey);
+    await program.methods.setCard("https://e.co/c.json", Array.from(new Uint8Array(32)))
+      .accountsPartial({ agent: agentPda, delegate: delegatePda, owner: operator.publicKey, agentOwner: creator })
+      .signers([operator])
+      .rpc();
+    const agentInfo = await provider.connection.getAccountInfo(agentPda);
+    const minRent = await provider.connection.getMinimumBalanceForRentExemption(agentInfo!.data.length);
+    expect(agentInfo!.lamports).to.eq(minRent);
+    expect(await provider.connection.getBalance(operator.publicKey)).to.be.at.most(operatorBefore);
+    await rpc(
+      program.methods.removeDelegate().accountsPartial({ agent: agentPda, delegate: delegatePda, owner: creator }),
+      "removeDelegate"
+    );
+    expect(await program.account.delegate.fetchNullable(delegatePda)).to.eq(null);
+
+    await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }).rpc();
+    await program.methods.closeAgent().accountsPartial({ agent: agentPda, owner: creator, recipient: creator }).rpc();
+  });
+
+  it("set_memory_signed accepts the bound agent key's Ed25519 signature and rejects replays", async () => {
+    const creator = provider.wallet.publicKey; // also acts as the relayer paying fees
+    const agentPda = await createAgentHelper(creator, "https://example.com/card.json", Array.from(new Uint8Array(32)), false);
+    const [agentKeyPda] = deriveAgentKeyPda(agentPda, program.programId);
+    const runtimeKey = Keypair.generate();
+    await program.methods.bindAgentKey()
+      .accountsPartial({ agent: agentPda, agentKey: agentKeyPda, owner: creator, runtimeKey: runtimeKey.publicKey })
+      .signers([runtimeKey])
+      .rpc();
+
+    const mode = 3; // URL
+    const ptr = Buffer.from("https://memory.example/agent-state.json");
+    const hash = Buffer.alloc(32, 7);
+    const signedUpdate = (nonce: number, signer: Keypair = runtimeKey) => {
+      const nonceLe = Buffer.alloc(8);
+      nonceLe.writeBigUInt64LE(BigInt(nonce));
+      const message = Buffer.concat([
+        Buffer.from("agent_registry:set_memory:v1"),
+        agentPda.toBuffer(),
+        nonceLe,
+        Buffer.from([mode]),
+        hash,
+        ptr,
+      ]);
+      const edIx = web3.Ed25519Program.createInstructionWithPrivateKey({ privateKey: signer.secretKey, message });
+      return program.methods
+        .setMemorySigned(mode, ptr, Array.from(hash), new anchor.BN(nonce))
+        .accountsPartial({ agent: agentPda, agentKey: agentKeyPda, relayer: provider.wallet.publicKey, instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY })
+        .preInstructions([edIx]);
+    };
+
+    // A delegate holding PERM_MEMORY is not the agent key
+    const delegateKey = Keypair.generate();
+    const [delegatePda] = deriveDelegatePda(agentPda, delegateKey.publicKey, program.programId);
+    await program.methods.addDelegate(delegateKey.publicKey, 2 /* PERM_MEMORY */, null)
+      .accountsPartial({ agent: agentPda, delegate: delegatePda, owner: creator })
+      .rpc();
+    await expectFail(signedUpdate(0, delegateKey).rpc(), ["InvalidSignature"]);
+
+    await rpc(signedUpdate(0), "setMemorySigned nonce=0");
+    const acc = await program.account.agentRegistry.fetch(agentPda);
+    expect(acc.memoryMode).to.eq(mode);
+    expect(Array.from(acc.memoryHash)).to.deep.equal(Array.from(hash));
+    const binding = await program.account.agentKey.fetch(agentKeyPda);
+    expect(binding.nonce.toNumber()).to.eq(1);
+
+    // Replaying the same signed payload must fail
+    await expectFail(signedUpdate(0).rpc(), ["InvalidNonce"]);
+
+    await program.methods.removeDelegate().accountsPartial({ agent: agentPda, delegate: delegatePda, owner: creator }).rpc();
+    await program.methods.revokeAgentKey().accountsPartial({ agent: agentPda, agentKey: agentKeyPda, owner: creator }).rpc();
+    await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }).rpc();
+    await program.methods.closeAgent().accountsPartial({ agent: agentPda, owner: creator, recipient: creator }).rpc();
+  });
+
+  it("bind_agent_key requires both signatures; rotate and revoke", async () => {
+    const creator = provider.wallet.publicKey;
+    const agentPda = await createAgentHelper(creator, "https://example.com/card.json", Array.from(new Uint8Array(32)), false);
+    const [agentKeyPda] = deriveAgentKeyPda(agentPda, program.programId);
+    const runtimeKey = Keypair.generate();
+
+    await rpc(
+      program.methods.bindAgentKey()
+        .accountsPartial({ agent: agentPda, agentKey: agentKeyPda, owner: creator, runtimeKey: runtimeKey.publicKey })
+        .signers([runtimeKey]),
+      "bindAgentKey"
+    );
+    let binding = await program.account.agentKey.fetch(agentKeyPda);
+    expect(binding.key.toBase58()).to.eq(runtimeKey.publicKey.toBase58());
+    expect(binding.agent.toBase58()).to.eq(agentPda.toBase58());
+
+    const nextKey = Keypair.generate();
+    await rpc(
+      program.methods.rotateAgentKey()
+        .accountsPartial({ agent: agentPda, agentKey: agentKeyPda, owner: creator, newKey: nextKey.publicKey })
+        .signers([nextKey]),
+      "rotateAgentKey"
+    );
+    binding = await program.account.agentKey.fetch(agentKeyPda);
+    expect(binding.key.toBase58()).to.eq(nextKey.publicKey.toBase58());
+
+    await rpc(
+      program.methods.revokeAgentKey().accountsPartial({ agent: agentPda, agentKey: agentKeyPda, owner: creator }),
+      "revokeAgentKey"
+    );
+    expect(await program.account.agentKey.fetchNullable(agentKeyPda)).to.eq(null);
+
+    await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }).rpc();
+    await program.methods.closeAgent().accountsPartial({ agent: agentPda, owner: creator, recipient: creator }).rpc();
+  });
+
+  it("create_indexed_agent registers several agents per creator", async () => {
+    const creator = provider.wallet.publicKey;
+    const [counterPda] = deriveAgentCounterPda(creator, program.programId);
+
+    if (!(await program.account.agentCounter.fetchNullable(counterPda))) {
+      await rpc(
+        program.methods.initAgentCounter().accountsPartial({ agentCounter: counterPda, creator }),
+        "initAgentCounter"
+      );
+    }
+
+    const created: web3.PublicKey[] = [];
+    for (let i = 0; i < 2; i++) {
+      const { count } = await program.account.agentCounter.fetch(counterPda);
+      const [agentPda] = deriveIndexedAgentPda(creator, count, program.programId);
+      await rpc(
+        ((program.methods as any)
+          .createIndexedAgent(creator, { cardUri: "https://example.com/card.json", cardHash: Array.from(new Uint8Array(32)), hasStaking: false, memoryMode: null, memoryPtr: null, memoryHash: null }) as any)
+          .accountsPartial({ agentCounter: counterPda, agent: agentPda, creatorSigner: creator } as any),
+        `createIndexedAgent #${count}`
+      );
+      const acc = await program.account.agentRegistry.fetch(agentPda);
+      expect(acc.creator.toBase58()).to.eq(creator.toBase58());
+      expect(acc.flags & 8).to.eq(8); // FLAG_INDEXED
+      expect(Buffer.from(acc.agentIndex).readUInt32LE()).to.eq(count);
+      created.push(agentPda);
+    }
+    expect(created[0].toBase58()).to.not.eq(created[1].toBase58());
+
+    // Indexed agents go through the same UpdateAgent / CloseAgent contexts
+    for (const agentPda of created) {
+      await program.methods
+        .setCard("ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi", Array.from(new Uint8Array(32).fill(3)))
+        .accountsPartial({ agent: agentPda, owner: creator })
+        .rpc();
+      await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }).rpc();
+      await program.methods.closeAgent().accountsPartial({ agent: agentPda, owner: creator, recipient: creator }).rpc();
+    }
+  });
+
+  it("set_card / set_memory realloc the account to the URI length", async () => {
+    const creator = provider.wallet.publicKey;
+    const agentPda = await createAgentHelper(creator, "https://example.com/card.json", Array.from(new Uint8Array(32)), false);
+    const sizeOf = async () => (await provider.connection.getAccountInfo(agentPda))!.data.length;
+    const initial = await sizeOf();
+
+    // Longer than the old 96-byte buffers
+    const longUri = "https://example.com/" + "a".repeat(300) + ".json";
+    await rpc(
+      program.methods.setCard(longUri, Array.from(new Uint8Array(32).fill(1))).accountsPartial({ agent: agentPda, owner: creator }),
+      "setCard(long)"
+    );
+    expect(await sizeOf()).to.eq(initial + longUri.length - "https://example.com/card.json".length);
+    let acc = await program.account.agentRegistry.fetch(agentPda);
+    expect(Buffer.from(acc.cardUri).toString()).to.eq(longUri);
+
+    const ptr = Buffer.from("https://memory.example/" + "m".repeat(200));
+    await rpc(
+      program.methods.setMemory(3, ptr, Array.from(new Uint8Array(32).fill(2))).accountsPartial({ agent: agentPda, owner: creator }),
+      "setMemory(long)"
+    );
+    expect(await sizeOf()).to.eq(initial + longUri.length - "https://example.com/card.json".length + ptr.length);
+
+    // Clearing memory shrinks the account back
+    await rpc(
+      program.methods.setMemory(0, Buffer.alloc(0), null).accountsPartial({ agent: agentPda, owner: creator }),
+      "setMemory(None)"
+    );
+    expect(await sizeOf()).to.eq(initial + longUri.length - "https://example.com/card.json".length);
+
+    await expectFail(
+      program.methods.setCard("https://example.com/" + "x".repeat(600), Array.from(new Uint8Array(32))).accountsPartial({ agent: agentPda, owner: creator }).rpc(),
+      ["InvalidLength"]
+    );
+
+    await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }).rpc();
+    await program.methods.closeAgent().accountsPartial({ agent: agentPda, owner: creator, recipient: creator }).rpc();
+  });
+
+  it("migrate_agent rejects accounts already on the current layout", async () => {
+    const creator = provider.wallet.publicKey;
+    const agentPda = await createAgentHelper(creator, "https://example.com/card.json", Array.from(new Uint8Array(32)), false);
+    const acc = await program.account.agentRegistry.fetch(agentPda);
+    expect(acc.version).to.eq(2);
+
+    await expectFail(
+      program.methods.migrateAgent().accountsPartial({ agent: agentPda, owner: creator }).rpc(),
+      ["AlreadyMigrated"]
+    );
+
+    await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }).rpc();
+    await program.methods.closeAgent().accountsPartial({ agent: agentPda, owner: creator, recipient: creator }).rpc();
+  });
+
   // NOTE: Test "staking enabled blocks closeAgent" has been moved to 2-staking.ts
   // to ensure proper test execution order. This allows agent-registry tests to
   // complete cleanly before staking tests create agents that cannot be closed.
@@ -1,6 +1,16 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program, web3 } from "@coral-xyz/anchor";
-import { createMint, mintTo, getOrCreateAssociatedTokenAccount } from "@solana/spl-token";
+import {
+  createMint,
+  mintTo,
+  getOrCreateAssociatedTokenAccount,
+  transfer,
+  ExtensionType,
+  TOKEN_2022_PROGRAM_ID,
+  getMintLen,
+  createInitializeTransferFeeConfigInstruction,
+  createInitializeMintInstruction,
+} from "@solana/spl-token";
 
 describe("agent-staking", () => {
   anchor.setProvider(anchor.AnchorProvider.env());
@@ -13,10 +23,14 @@ describe("agent-staking", () => {
   
   let tokenMint: web3.PublicKey;
   let userTokenAccount: web3.PublicKey;
+  const VIRTUAL_SHARES = 1_000n; // Virtual shares and tokens of the share price
 
   function deriveProgramState(programId: web3.PublicKey) {
     return web3.PublicKey.findProgramAddressSync([Buffer.from("program_state")], programId)[0];
   }
+  function deriveProgramData(programId: web3.PublicKey) {
+    return web3.PublicKey.findProgramAddressSync([programId.toBuffer()], new web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111"))[0];
+  }
   function derivePool(agentPda: web3.PublicKey, programId: web3.PublicKey) {
     return web3.PublicKey.findProgramAddressSync([Buffer.from("staking_pool"), agentPda.toBuffer()], programId)[0];
   }
@@ -48,15 +62,14 @@ describe("agent-staking", () => {
     
     // Agent doesn't exist - create it
     await (agentRegistryProgram.methods as any)
-      .createAgent(
-        creator, 
-        "https://example.com/card.json",  // cardUri (obligatoire)
-        Array.from(new Uint8Array(32)),   // cardHash (obligatoire)
-        true,   // has_staking
-        null,   // memory_mode
-        null,   // memory_ptr
-        null    // memory_hash
-      )
+      .createAgent(creator, {
+        cardUri: "https://example.com/card.json",  // obligatoire
+        cardHash: Array.from(new Uint8Array(32)),  // obligatoire
+        hasStaking: true,
+        memoryMode: null,
+        memoryPtr: null,
+        memoryHash: null,
+      })
       .accounts({
         agent: agentPda,
         creatorSigner: creator,  // Same as creator parameter
@@ -91,6 +104,7 @@ describe("agent-staking", () => {
         stakingPool: poolPda, 
         tokenVault: vaultPda, 
         tokenMint, 
+        receiptMint: null, 
         owner: provider.wallet.publicKey, 
         systemProgram: web3.SystemProgram.programId, 
         tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, 
@@ -102,6 +116,24 @@ describe("agent-staking", () => {
     return { poolPda, vaultPda, existed: false };
   }
   
+  // Helper: Create a fresh indexed agent with staking (one pool per test that needs its own mint)
+  async function createIndexedAgentWithStaking() {
+    const creator = provider.wallet.publicKey;
+    const [counterPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("agent_counter"), creator.toBuffer()], agentRegistryProgram.programId);
+    if (!(await agentRegistryProgram.account.agentCounter.fetchNullable(counterPda))) {
+      await agentRegistryProgram.methods.initAgentCounter().accountsPartial({ agentCounter: counterPda, creator }).rpc();
+    }
+    const { count } = await agentRegistryProgram.account.agentCounter.fetch(counterPda);
+    const indexLe = Buffer.alloc(4);
+    indexLe.writeUInt32LE(count);
+    const [agentPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("agent"), creator.toBuffer(), indexLe], agentRegistryProgram.programId);
+    await agentRegistryProgram.methods
+      .createIndexedAgent(creator, { cardUri: "https://example.com/card.json", cardHash: Array.from(new Uint8Array(32)), hasStaking: true, memoryMode: null, memoryPtr: null, memoryHash: null })
+      .accountsPartial({ agentCounter: counterPda, agent: agentPda, creatorSigner: creator })
+      .rpc();
+    return agentPda;
+  }
+
   // Helper: Initialize stake account if needed
   async function initStakeAccountIfNeeded(agentPda: web3.PublicKey, staker: web3.PublicKey) {
     if (!program) throw new Error("agent-staking program not found");
@@ -296,7 +328,8 @@ Has payer: ${hasPayer}
         .initProgramState()
         .accounts({ 
           programState: statePda, 
-          initializer: provider.wallet.publicKey,  // Just pays for init, no ongoing control
+          programData: deriveProgramData(program.programId), 
+          initializer: provider.wallet.publicKey,  // Program upgrade authority (anchor test deploys with the wallet)
           treasury: provider.wallet.publicKey, 
           systemProgram: web3.SystemProgram.programId 
         })
@@ -321,7 +354,7 @@ Has payer: ${hasPayer}
       await expectFail(
         program.methods
           .createStakingPool(new anchor.BN(0))
-          .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+          .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
           .rpc(),
         "Invalid minimum stake"
       );
@@ -330,7 +363,7 @@ Has payer: ${hasPayer}
       // Create pool with valid min_stake
       await program.methods
         .createStakingPool(new anchor.BN(1))
-        .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+        .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
         .rpc();
       console.log("  ✓ Pool created with min_stake=1");
     } else {
@@ -357,14 +390,14 @@ Has payer: ${hasPayer}
     await expectFail(
       program.methods
         .stake(new anchor.BN(0))
-        .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+        .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
         .rpc(),
       "Invalid stake amount"
     );
 
     await program.methods
       .stake(new anchor.BN(1000))
-      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
       .rpc();
   });
 
@@ -386,11 +419,11 @@ Has payer: ${hasPayer}
     // If stake account existed, it might have stake from previous test - withdraw it first
     if (existed) {
       const existingStake = await program.account.stakeAccount.fetch(stakePda);
-      if (existingStake.stakedAmount.toNumber() > 0) {
-        console.log(`  ♻️  StakeAccount has ${existingStake.stakedAmount.toNumber()} staked, withdrawing first`);
+      if (existingStake.shares.toNumber() > 0) {
+        console.log(`  ♻️  StakeAccount has ${existingStake.shares.toNumber()} staked, withdrawing first`);
         await program.methods
-          .withdrawStake()
-          .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+          .withdrawStake(existingStake.shares)
+          .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
           .rpc();
         console.log("  ✓ Withdrew existing stake, now starting test with empty account");
       }
@@ -399,27 +432,27 @@ Has payer: ${hasPayer}
     // Now test: withdraw with no stake should fail
     await expectFail(
       program.methods
-        .withdrawStake()
-        .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+        .withdrawStake(new anchor.BN(1))
+        .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
         .rpc(),
       "No stake"
     );
 
     await program.methods
       .stake(new anchor.BN(3000))
-      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
       .rpc();
 
     await program.methods
-      .withdrawStake()
-      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .withdrawStake(new anchor.BN(3000))
+      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
       .rpc();
 
-    // FIX C-02: Account NOT closed anymore, just staked_amount = 0
+    // FIX C-02: Account NOT closed anymore, just shares = 0
     const info = await provider.connection.getAccountInfo(stakePda);
     if (!info) throw new Error("stake account should still exist");
     const stakeData = await program.account.stakeAccount.fetch(stakePda);
-    if (stakeData.stakedAmount.toNumber() !== 0) throw new Error("staked_amount should be 0");
+    if (stakeData.shares.toNumber() !== 0) throw new Error("shares should be 0");
   });
 
   it("update_min_stake modifies pool setting", async () => {
@@ -462,15 +495,14 @@ Has payer: ${hasPayer}
     } else {
       // Create agent WITHOUT staking (has_staking=false)
       await (agentRegistryProgram.methods as any)
-        .createAgent(
-          creator, 
-          "https://example.com/card.json",  // cardUri (obligatoire)
-          Array.from(new Uint8Array(32)),   // cardHash (obligatoire)
-          false,  // has_staking=false
-          null,   // memory_mode
-          null,   // memory_ptr
-          null    // memory_hash
-        )
+        .createAgent(creator, {
+          cardUri: "https://example.com/card.json",  // obligatoire
+          cardHash: Array.from(new Uint8Array(32)),  // obligatoire
+          hasStaking: false,
+          memoryMode: null,
+          memoryPtr: null,
+          memoryHash: null,
+        })
         .accounts({
           agent: agentPda,
           creatorSigner: provider.wallet.publicKey,
@@ -486,7 +518,7 @@ Has payer: ${hasPayer}
     await expectFail(
       program.methods
         .createStakingPool(new anchor.BN(1000))
-        .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+        .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
         .rpc(),
       "staking"
     );
@@ -514,7 +546,7 @@ Has payer: ${hasPayer}
       const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
       await program.methods
         .createStakingPool(new anchor.BN(1000))
-        .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+        .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
         .rpc();
       console.log("  ✓ Created pool with min_stake=1000");
     }
@@ -528,7 +560,7 @@ Has payer: ${hasPayer}
     await expectFail(
       program.methods
         .stake(new anchor.BN(500))
-        .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+        .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
         .rpc(),
       "below minimum"
     );
@@ -536,17 +568,17 @@ Has payer: ${hasPayer}
     // Stake 1000 (at min) → should succeed
     await program.methods
       .stake(new anchor.BN(1000))
-      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
       .rpc();
 
     // Re-stake 100 (below min but OK for re-stake) → should succeed
     await program.methods
       .stake(new anchor.BN(100))
-      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
       .rpc();
     
     const stakeData = await program.account.stakeAccount.fetch(stakePda);
-    if (stakeData.stakedAmount.toNumber() !== 1100) throw new Error("re-stake should work");
+    if (stakeData.shares.toNumber() !== 1100) throw new Error("re-stake should work");
   });
 
   it("FIX C-02: staked_at preserved after withdraw+re-stake", async () => {
@@ -567,22 +599,22 @@ Has payer: ${hasPayer}
     // Initial stake
     await program.methods
       .stake(new anchor.BN(5000))
-      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
       .rpc();
 
     const initialStake = await program.account.stakeAccount.fetch(stakePda);
     const originalStakedAt = initialStake.stakedAt;
 
-    // Withdraw (now sets staked_amount=0 but preserves account)
+    // Withdraw (now sets shares=0 but preserves account)
     await program.methods
-      .withdrawStake()
-      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .withdrawStake(initialStake.shares)
+      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
       .rpc();
 
     // Re-stake immediately
     await program.methods
       .stake(new anchor.BN(5000))
-      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
       .rpc();
 
     const reStake = await program.account.stakeAccount.fetch(stakePda);
@@ -596,6 +628,885 @@ Has payer: ${hasPayer}
   // FINAL TEST: Verify agent with staking cannot be closed
   // NOTE: This test was moved from 1-agent-registry.ts to here because it intentionally
   // leaves an agent with staking enabled that cannot be closed.
+  it("rewards accrue pro-rata to stake and can be claimed", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const agentPda = await createAgentWithStaking();
+    const { poolPda, vaultPda } = await createStakingPoolIfNeeded(agentPda, new anchor.BN(1));
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, provider.wallet.publicKey);
+    const [rewardVault] = web3.PublicKey.findProgramAddressSync([Buffer.from("reward_vault"), poolPda.toBuffer()], program.programId);
+
+    const pool = await program.account.stakingPool.fetch(poolPda);
+    if (pool.rewardVault.equals(web3.PublicKey.default)) {
+      await program.methods
+        .initRewards(new anchor.BN(1_000))
+        .accounts({ stakingPool: poolPda, rewardMint: tokenMint, rewardVault, owner: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+        .rpc();
+    }
+
+    await expectFail(
+      program.methods
+        .fundRewards(new anchor.BN(0))
+        .accounts({ stakingPool: poolPda, rewardVault, rewardMint: tokenMint, funderTokenAccount: userTokenAccount, funder: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+        .rpc(),
+      "Invalid reward amount"
+    );
+    await program.methods
+      .fundRewards(new anchor.BN(1_000_000))
+      .accounts({ stakingPool: poolPda, rewardVault, rewardMint: tokenMint, funderTokenAccount: userTokenAccount, funder: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+
+    await program.methods
+      .stake(new anchor.BN(5000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    await new Promise((r) => setTimeout(r, 2000));
+
+    const before = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+    await program.methods
+      .claimRewards()
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, rewardVault, rewardMint: tokenMint, stakerRewardAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    const after = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+    if (BigInt(after) <= BigInt(before)) throw new Error("claim_rewards should pay out accrued rewards");
+
+    const stake = await program.account.stakeAccount.fetch(stakePda);
+    if (stake.rewardsOwed.toNumber() !== 0) throw new Error("rewards_owed should be reset after claim");
+  });
+
+  it("withdraw_stake supports partial exits that keep min_stake", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const agentPda = await createAgentWithStaking();
+    const { poolPda, vaultPda } = await createStakingPoolIfNeeded(agentPda, new anchor.BN(1));
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, provider.wallet.publicKey);
+    const withdrawAccounts = { programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId };
+
+    await program.methods
+      .updateMinStake(new anchor.BN(1000))
+      .accounts({ stakingPool: poolPda, owner: provider.wallet.publicKey })
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(5000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    const staked = (await program.account.stakeAccount.fetch(stakePda)).shares.toNumber();
+
+    await expectFail(
+      program.methods.withdrawStake(new anchor.BN(staked + 1)).accounts(withdrawAccounts).rpc(),
+      "exceeds staked amount"
+    );
+    // Leaving 500 < min_stake is rejected
+    await expectFail(
+      program.methods.withdrawStake(new anchor.BN(staked - 500)).accounts(withdrawAccounts).rpc(),
+      "below minimum"
+    );
+
+    const countBefore = (await program.account.stakingPool.fetch(poolPda)).stakerCount;
+    await program.methods.withdrawStake(new anchor.BN(2000)).accounts(withdrawAccounts).rpc();
+    let stake = await program.account.stakeAccount.fetch(stakePda);
+    if (stake.shares.toNumber() !== staked - 2000) throw new Error("partial withdraw should keep the remainder staked");
+    if ((await program.account.stakingPool.fetch(poolPda)).stakerCount !== countBefore) throw new Error("staker_count should not change on partial exit");
+
+    await program.methods.withdrawStake(stake.shares).accounts(withdrawAccounts).rpc();
+    stake = await program.account.stakeAccount.fetch(stakePda);
+    if (stake.shares.toNumber() !== 0) throw new Error("full exit should leave nothing staked");
+    if ((await program.account.stakingPool.fetch(poolPda)).stakerCount !== countBefore - 1) throw new Error("staker_count should drop on full exit");
+  });
+
+  it("unstake_request queues stake until the unbonding period ends", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const agentPda = await createAgentWithStaking();
+    const { poolPda, vaultPda } = await createStakingPoolIfNeeded(agentPda, new anchor.BN(1));
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, provider.wallet.publicKey);
+    const completeAccounts = { stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID };
+
+    await program.methods
+      .updateUnbondingPeriod(2)
+      .accounts({ stakingPool: poolPda, owner: provider.wallet.publicKey })
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(5000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+
+    await expectFail(
+      program.methods
+        .withdrawStake(new anchor.BN(2000))
+        .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+        .rpc(),
+      "use unstake_request"
+    );
+
+    const requestAccounts = { programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, systemProgram: web3.SystemProgram.programId };
+    const poolBefore = await program.account.stakingPool.fetch(poolPda);
+    await program.methods.unstakeRequest(new anchor.BN(2000)).accounts(requestAccounts).rpc();
+    const poolAfter = await program.account.stakingPool.fetch(poolPda);
+    if (!poolBefore.totalShares.sub(poolAfter.totalShares).eqn(2000)) throw new Error("pending shares should leave total_shares");
+    if (!poolAfter.totalUnbonding.sub(poolBefore.totalUnbonding).eqn(2000)) throw new Error("pending shares should move to total_unbonding");
+    if (!poolAfter.totalWeightedStake.lt(poolBefore.totalWeightedStake)) throw new Error("pending shares should stop earning rewards");
+    let stake = await program.account.stakeAccount.fetch(stakePda);
+    if (stake.unbonding[0].shares.toNumber() !== 2000) throw new Error("the request should take the first slot");
+    const firstEndsAt = stake.unbonding[0].endsAt.toNumber();
+
+    await expectFail(program.methods.completeUnstake().accounts(completeAccounts).rpc(), "has not ended");
+    // A second request gets its own cooldown and leaves the first one's untouched
+    await new Promise((r) => setTimeout(r, 1500));
+    await program.methods.unstakeRequest(new anchor.BN(1000)).accounts(requestAccounts).rpc();
+    stake = await program.account.stakeAccount.fetch(stakePda);
+    if (stake.unbonding[0].endsAt.toNumber() !== firstEndsAt) throw new Error("a new request should not restart the earlier cooldown");
+    if (stake.unbonding[1].shares.toNumber() !== 1000) throw new Error("the second request should take its own slot");
+
+    // Only the first request has matured when its cooldown ends
+    while ((await provider.connection.getBlockTime(await provider.connection.getSlot())) < firstEndsAt) {
+      await new Promise((r) => setTimeout(r, 400));
+    }
+    let before = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+    await program.methods.completeUnstake().accounts(completeAccounts).rpc();
+    let after = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+    stake = await program.account.stakeAccount.fetch(stakePda);
+    if (stake.unbonding[1].shares.toNumber() === 1000) {
+      if (BigInt(after) - BigInt(before) !== 2000n) throw new Error("complete_unstake should release the matured request");
+      await new Promise((r) => setTimeout(r, 3000));
+      before = after;
+      await program.methods.completeUnstake().accounts(completeAccounts).rpc();
+      after = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+      if (BigInt(after) - BigInt(before) !== 1000n) throw new Error("complete_unstake should release the second request");
+    } else if (BigInt(after) - BigInt(before) !== 3000n) {
+      throw new Error("complete_unstake should release every matured request");
+    }
+    if (!(await program.account.stakingPool.fetch(poolPda)).totalUnbonding.eq(poolBefore.totalUnbonding)) throw new Error("released shares should leave total_unbonding");
+    await expectFail(program.methods.completeUnstake().accounts(completeAccounts).rpc(), "No pending unstake");
+
+    // Back to instant withdrawals for the remaining tests
+    await program.methods
+      .updateUnbondingPeriod(0)
+      .accounts({ stakingPool: poolPda, owner: provider.wallet.publicKey })
+      .rpc();
+  });
+
+  it("set_lockup boosts weight and gates early exits behind the penalty", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const agentPda = await createAgentWithStaking();
+    const { poolPda, vaultPda } = await createStakingPoolIfNeeded(agentPda, new anchor.BN(1));
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, provider.wallet.publicKey);
+    const withdrawAccounts = { programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId };
+    const lockupAccounts = { stakingPool: poolPda, agentPda, stakeAccount: stakePda, staker: provider.wallet.publicKey };
+
+    await program.methods
+      .stake(new anchor.BN(4000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+
+    await expectFail(program.methods.setLockup(9).accounts(lockupAccounts).rpc(), "Invalid lockup tier");
+
+    // Tier 1 (30 days, 1.25x) with no penalty configured: early exits are rejected
+    await program.methods.setLockup(1).accounts(lockupAccounts).rpc();
+    let stake = await program.account.stakeAccount.fetch(stakePda);
+    if (stake.lockTier !== 1) throw new Error("lock_tier should be recorded");
+    if (stake.weightedAmount.toNumber() !== Math.floor((stake.shares.toNumber() * 12_500) / 10_000)) throw new Error("weighted_amount should apply the 1.25x tier");
+    const pool = await program.account.stakingPool.fetch(poolPda);
+    if (pool.totalWeightedStake.lt(stake.weightedAmount)) throw new Error("total_weighted_stake should include the position");
+
+    await expectFail(program.methods.setLockup(0).accounts(lockupAccounts).rpc(), "cannot be shortened");
+    // Anyone can end a lockup once it has run out, but not before
+    await expectFail(program.methods.expireLockup().accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda }).rpc(), "Lockup has not ended");
+    await expectFail(program.methods.withdrawStake(new anchor.BN(1000)).accounts(withdrawAccounts).rpc(), "Stake is locked");
+
+    // Penalties only apply to lockups chosen afterwards: re-lock at the next tier to pick it up
+    await program.methods
+      .updateEarlyUnlockPenalty(1000)
+      .accounts({ stakingPool: poolPda, owner: provider.wallet.publicKey })
+      .rpc();
+    await program.methods.setLockup(2).accounts(lockupAccounts).rpc();
+
+    const supplyBefore = (await provider.connection.getTokenSupply(tokenMint)).value.amount;
+    const before = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+    await program.methods.withdrawStake(new anchor.BN(1000)).accounts(withdrawAccounts).rpc();
+    const after = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+    const supplyAfter = (await provider.connection.getTokenSupply(tokenMint)).value.amount;
+    if (BigInt(after) - BigInt(before) !== 900n) throw new Error("10% early unlock penalty should be withheld");
+    if (BigInt(supplyBefore) - BigInt(supplyAfter) !== 100n) throw new Error("penalty should be burned");
+
+    stake = await program.account.stakeAccount.fetch(stakePda);
+    if (stake.weightedAmount.toNumber() !== Math.floor((stake.shares.toNumber() * 15_000) / 10_000)) throw new Error("weight should follow the remaining stake");
+  });
+
+  it("slashing is configured by the upgrade authority behind a timelock, never by the pool owner", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const agentPda = await createAgentWithStaking();
+    const { poolPda } = await createStakingPoolIfNeeded(agentPda, new anchor.BN(1));
+    const programData = deriveProgramData(program.programId);
+    const authority = provider.wallet.publicKey; // upgrade authority and pool owner in tests
+    const [pendingConfigPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("pending_slashing_config"), poolPda.toBuffer()], program.programId);
+    const [pendingSlashPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("pending_slash"), poolPda.toBuffer()], program.programId);
+    const proposeAccounts = { stakingPool: poolPda, pendingSlashingConfig: pendingConfigPda, programData, authority, systemProgram: web3.SystemProgram.programId };
+    const arbiter = web3.Keypair.generate();
+    await provider.sendAndConfirm(
+      new web3.Transaction().add(web3.SystemProgram.transfer({ fromPubkey: authority, toPubkey: arbiter.publicKey, lamports: 10_000_000 }))
+    );
+    const day = 86_400;
+
+    // Only the upgrade authority can propose, and never with the pool owner as arbiter
+    await expectFail(
+      program.methods
+        .proposeSlashingConfig(arbiter.publicKey, 1000, day)
+        .accounts({ ...proposeAccounts, authority: arbiter.publicKey })
+        .signers([arbiter])
+        .rpc(),
+      "Unauthorized"
+    );
+    await expectFail(program.methods.proposeSlashingConfig(authority, 1000, day).accounts(proposeAccounts).rpc(), "cannot be the pool owner");
+    await expectFail(program.methods.proposeSlashingConfig(arbiter.publicKey, 9000, day).accounts(proposeAccounts).rpc(), "Invalid slashing configuration");
+
+    // The timelock must leave stakers the unbonding period plus a day to exit
+    await program.methods.updateUnbondingPeriod(day).accounts({ stakingPool: poolPda, owner: authority }).rpc();
+    await expectFail(program.methods.proposeSlashingConfig(arbiter.publicKey, 1000, day).accounts(proposeAccounts).rpc(), "exceed the unbonding period");
+    await program.methods.proposeSlashingConfig(arbiter.publicKey, 1000, 2 * day).accounts(proposeAccounts).rpc();
+
+    await expectFail(
+      program.methods.configureSlashing().accounts({ stakingPool: poolPda, pendingSlashingConfig: pendingConfigPda, programData, authority }).rpc(),
+      "Slashing config timelock has not elapsed"
+    );
+    await expectFail(
+      program.methods
+        .proposeSlash(500, Array(32).fill(7))
+        .accounts({ stakingPool: poolPda, pendingSlash: pendingSlashPda, destination: userTokenAccount, authority: arbiter.publicKey, systemProgram: web3.SystemProgram.programId })
+        .signers([arbiter])
+        .rpc(),
+      "Slashing is not configured"
+    );
+    await program.methods.cancelSlashingConfig().accounts({ stakingPool: poolPda, pendingSlashingConfig: pendingConfigPda, programData, authority }).rpc();
+
+    const pool = await program.account.stakingPool.fetch(poolPda);
+    if (!pool.slashAuthority.equals(web3.PublicKey.default)) throw new Error("slashing should stay disabled until the timelock passes");
+    if (await program.account.pendingSlashingConfig.fetchNullable(pendingConfigPda)) throw new Error("pending config should be closed");
+    await program.methods.updateUnbondingPeriod(0).accounts({ stakingPool: poolPda, owner: authority }).rpc();
+  });
+
+  it("direct transfers into the vault raise every staker's redeemable amount", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const agentPda = await createAgentWithStaking();
+    const { poolPda, vaultPda } = await createStakingPoolIfNeeded(agentPda, new anchor.BN(1));
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, provider.wallet.publicKey);
+    const vaultBalance = async () => BigInt((await provider.connection.getTokenAccountBalance(vaultPda)).value.amount);
+
+    // Donation: no shares minted, so the price per share goes up
+    const poolBefore = await program.account.stakingPool.fetch(poolPda);
+    const donation = await vaultBalance();  // Roughly doubles the share price
+    await transfer(provider.connection, (provider.wallet as any).payer, userTokenAccount, vaultPda, provider.wallet.publicKey, donation);
+    const pool = await program.account.stakingPool.fetch(poolPda);
+    if (!pool.totalShares.eq(poolBefore.totalShares)) throw new Error("donations should not mint shares");
+
+    // New deposits are priced against the grown vault
+    const totalShares = BigInt(pool.totalShares.toString());
+    const sharesBefore = BigInt((await program.account.stakeAccount.fetch(stakePda)).shares.toString());
+    const deposit = 5_000n;
+    const expectedShares = (deposit * (totalShares + VIRTUAL_SHARES)) / ((await vaultBalance()) + VIRTUAL_SHARES);
+    await program.methods
+      .stake(new anchor.BN(deposit.toString()))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    let stake = await program.account.stakeAccount.fetch(stakePda);
+    if (BigInt(stake.shares.toString()) - sharesBefore !== expectedShares) throw new Error("stake should mint shares at the vault price");
+    if (expectedShares >= deposit) throw new Error("shares should be worth more than one token each");
+
+    // Redemptions follow the same price (minus the early unlock penalty of the current lockup)
+    const redeemed = 1_000n;
+    const total = BigInt((await program.account.stakingPool.fetch(poolPda)).totalShares.toString());
+    const vault = await vaultBalance();
+    const virtualValue = (redeemed * (vault + VIRTUAL_SHARES)) / (total + VIRTUAL_SHARES);
+    const proRata = (redeemed * vault) / total;
+    const value = virtualValue < proRata ? virtualValue : proRata;
+    const penalty = (value * BigInt(stake.lockPenaltyBps)) / 10_000n;
+    const before = BigInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
+    await program.methods
+      .withdrawStake(new anchor.BN(redeemed.toString()))
+      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: provider.wallet.publicKey, treasury: provider.wallet.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .rpc();
+    const after = BigInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
+    if (after - before !== value - penalty) throw new Error("withdraw should redeem shares at the vault price");
+    if (value <= redeemed) throw new Error("redeemed value should include the donation");
+  });
+
+  it("a donation into a nearly empty pool cannot round the next deposit down", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const owner = provider.wallet.publicKey;
+    const payer = (provider.wallet as any).payer;
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    const { treasury } = await program.account.programState.fetch(statePda);
+    const tokenBalance = async (account: web3.PublicKey) => BigInt((await provider.connection.getTokenAccountBalance(account)).value.amount);
+
+    // Attacker takes the first share, then donates to inflate the share price
+    const attacker = web3.Keypair.generate();
+    await provider.sendAndConfirm(
+      new web3.Transaction().add(web3.SystemProgram.transfer({ fromPubkey: owner, toPubkey: attacker.publicKey, lamports: 100_000_000 }))
+    );
+    const attackerAta = (await getOrCreateAssociatedTokenAccount(provider.connection, payer, tokenMint, attacker.publicKey)).address;
+    const donation = 1_000_000n;
+    await transfer(provider.connection, payer, userTokenAccount, attackerAta, payer, 1n + donation);
+    const attackerStake = deriveStake(attacker.publicKey, agentPda, program.programId);
+    await program.methods
+      .initStake()
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: attackerStake, staker: attacker.publicKey, systemProgram: web3.SystemProgram.programId })
+      .signers([attacker])
+      .rpc();
+    await program.methods
+      .stake(new anchor.BN(1))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: attackerStake, tokenVault: vaultPda, tokenMint, stakerTokenAccount: attackerAta, staker: attacker.publicKey, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .signers([attacker])
+      .rpc();
+    await transfer(provider.connection, payer, attackerAta, vaultPda, attacker, donation);
+
+    // Without virtual shares the victim would get 1 share for 1.5x the donation and lose a third of it
+    const deposit = 1_500_000n;
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, owner);
+    await program.methods
+      .stake(new anchor.BN(deposit.toString()))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    const victimShares = (await program.account.stakeAccount.fetch(stakePda)).shares;
+    if (victimShares.toNumber() < 1_000) throw new Error("the victim should be priced against the virtual shares too");
+
+    const withdraw = (stake: web3.PublicKey, staker: web3.PublicKey, account: web3.PublicKey, shares: anchor.BN) => program.methods
+      .withdrawStake(shares)
+      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stake, tokenVault: vaultPda, tokenMint, stakerTokenAccount: account, staker, treasury, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId });
+    const attackerBefore = await tokenBalance(attackerAta);
+    await withdraw(attackerStake, attacker.publicKey, attackerAta, new anchor.BN(1)).signers([attacker]).rpc();
+    if ((await tokenBalance(attackerAta)) - attackerBefore > donation / 100n) throw new Error("the donation should mostly be lost to the virtual shares");
+
+    const victimBefore = await tokenBalance(userTokenAccount);
+    await withdraw(stakePda, owner, userTokenAccount, victimShares).rpc();
+    if ((await tokenBalance(userTokenAccount)) - victimBefore < (deposit * 999n) / 1_000n) throw new Error("the victim should get back nearly all of the deposit");
+  });
+
+  it("receipt-mode pools mint receipts on stake and let any holder redeem them", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    // Fresh indexed agent so the pool can be created in receipt mode
+    const creator = provider.wallet.publicKey;
+    const agentPda = await createIndexedAgentWithStaking();
+
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    const [receiptMint] = web3.PublicKey.findProgramAddressSync([Buffer.from("receipt_mint"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint, owner: creator, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    const pool = await program.account.stakingPool.fetch(poolPda);
+    if (!pool.receiptMint.equals(receiptMint)) throw new Error("pool should record its receipt mint");
+
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, creator);
+    const receiptAta = await getOrCreateAssociatedTokenAccount(provider.connection, (provider.wallet as any).payer, receiptMint, creator);
+    const receiptBalance = async () => BigInt((await provider.connection.getTokenAccountBalance(receiptAta.address)).value.amount);
+
+    const stakeAccounts = { stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: creator, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID };
+    await expectFail(program.methods.stake(new anchor.BN(3000)).accounts(stakeAccounts).rpc(), "Receipt mint and receipt token account are required");
+    await program.methods
+      .stake(new anchor.BN(3000))
+      .accounts({ ...stakeAccounts, receiptMint, stakerReceiptAccount: receiptAta.address })
+      .rpc();
+    const shares = BigInt((await program.account.stakingPool.fetch(poolPda)).totalShares.toString());
+    if ((await receiptBalance()) !== shares) throw new Error("receipts should be minted 1:1 with shares");
+    if (!(await program.account.stakeAccount.fetch(stakePda)).shares.isZero()) throw new Error("the receipts, not the stake account, should carry the shares");
+    await expectFail(program.methods.setLockup(1).accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, staker: creator }).rpc(), "No stake");
+
+    const withdrawAccounts = { programState: deriveProgramState(program.programId), stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: creator, treasury: creator, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId };
+    await expectFail(program.methods.withdrawStake(new anchor.BN(1000)).accounts(withdrawAccounts).rpc(), "Receipt mint and receipt token account are required");
+    await program.methods
+      .withdrawStake(new anchor.BN(1000))
+      .accounts({ ...withdrawAccounts, receiptMint, stakerReceiptAccount: receiptAta.address })
+      .rpc();
+    if ((await receiptBalance()) !== shares - 1000n) throw new Error("withdraw should burn the redeemed receipts");
+
+    // A buyer of the receipts redeems them through their own stake account
+    const payer = (provider.wallet as any).payer;
+    const buyer = web3.Keypair.generate();
+    await provider.sendAndConfirm(
+      // Enough for the full SOL unstake fee of a fresh stake account, plus rent
+      new web3.Transaction().add(web3.SystemProgram.transfer({ fromPubkey: creator, toPubkey: buyer.publicKey, lamports: 300_000_000 }))
+    );
+    const buyerReceipts = (await getOrCreateAssociatedTokenAccount(provider.connection, payer, receiptMint, buyer.publicKey)).address;
+    const buyerTokens = (await getOrCreateAssociatedTokenAccount(provider.connection, payer, tokenMint, buyer.publicKey)).address;
+    await transfer(provider.connection, payer, receiptAta.address, buyerReceipts, payer, shares - 1000n);
+    const buyerStake = deriveStake(buyer.publicKey, agentPda, program.programId);
+    await program.methods
+      .initStake()
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: buyerStake, staker: buyer.publicKey, systemProgram: web3.SystemProgram.programId })
+      .signers([buyer])
+      .rpc();
+    await program.methods
+      .withdrawStake(new anchor.BN((shares - 1000n).toString()))
+      .accounts({ ...withdrawAccounts, stakeAccount: buyerStake, stakerTokenAccount: buyerTokens, staker: buyer.publicKey, treasury: creator, receiptMint, stakerReceiptAccount: buyerReceipts })
+      .signers([buyer])
+      .rpc();
+    const redeemed = BigInt((await provider.connection.getTokenAccountBalance(buyerTokens)).value.amount);
+    if (redeemed === 0n || redeemed > shares - 1000n) throw new Error("the buyer should redeem the receipts for their vault amount");
+    const drained = await program.account.stakingPool.fetch(poolPda);
+    if (!drained.totalShares.isZero()) throw new Error("redeeming every receipt should leave no shares behind");
+    if (drained.stakerCount !== 0) throw new Error("receipt exits should not touch staker_count");
+
+    // The receipt mint outlives the pool: a pool recreated at the same address reuses it
+    await program.methods
+      .closeStakingPool()
+      .accounts({ programState: deriveProgramState(program.programId), stakingPool: poolPda, tokenVault: vaultPda, tokenMint, ownerTokenAccount: userTokenAccount, feeVault: null, treasuryTokenAccount: null, rewardVault: null, rewardMint: null, ownerRewardAccount: null, rewardTokenProgram: null, owner: creator, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint, owner: creator, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    if (!(await program.account.stakingPool.fetch(poolPda)).receiptMint.equals(receiptMint)) throw new Error("the recreated pool should reuse its receipt mint");
+  });
+
+  it("Token-2022 pools credit only what a transfer-fee mint delivers", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!hasPayer) throw new Error("Token-2022 test needs a payer");
+
+    // Token-2022 mint with a 1% transfer fee
+    const payer = (provider.wallet as any).payer;
+    const staker = provider.wallet.publicKey;
+    const mint = web3.Keypair.generate();
+    const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+    const lamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+    await provider.sendAndConfirm(
+      new web3.Transaction().add(
+        web3.SystemProgram.createAccount({ fromPubkey: staker, newAccountPubkey: mint.publicKey, space: mintLen, lamports, programId: TOKEN_2022_PROGRAM_ID }),
+        createInitializeTransferFeeConfigInstruction(mint.publicKey, staker, staker, 100, BigInt(1_000_000), TOKEN_2022_PROGRAM_ID),
+        createInitializeMintInstruction(mint.publicKey, 6, staker, null, TOKEN_2022_PROGRAM_ID)
+      ),
+      [mint]
+    );
+    const stakerAta = await getOrCreateAssociatedTokenAccount(provider.connection, payer, mint.publicKey, staker, false, undefined, undefined, TOKEN_2022_PROGRAM_ID);
+    await mintTo(provider.connection, payer, mint.publicKey, stakerAta.address, payer, 1_000_000, [], undefined, TOKEN_2022_PROGRAM_ID);
+
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint: mint.publicKey, receiptMint: null, owner: staker, systemProgram: web3.SystemProgram.programId, tokenProgram: TOKEN_2022_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, staker);
+
+    // 10_000 sent, 100 withheld by the mint: only 9_900 reach the vault and mint shares
+    await program.methods
+      .stake(new anchor.BN(10_000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint: mint.publicKey, stakerTokenAccount: stakerAta.address, staker, tokenProgram: TOKEN_2022_PROGRAM_ID })
+      .rpc();
+    const vault = (await provider.connection.getTokenAccountBalance(vaultPda)).value.amount;
+    const stake = await program.account.stakeAccount.fetch(stakePda);
+    if (vault !== "9900") throw new Error(`vault should hold the received amount, got ${vault}`);
+    if (stake.shares.toNumber() !== 9_900) throw new Error("shares should be minted for the received amount only");
+
+    // Exits go through transfer_checked too
+    const before = BigInt((await provider.connection.getTokenAccountBalance(stakerAta.address)).value.amount);
+    await program.methods
+      .withdrawStake(stake.shares)
+      .accounts({ programState: deriveProgramState(program.programId), stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint: mint.publicKey, stakerTokenAccount: stakerAta.address, staker, treasury: staker, tokenProgram: TOKEN_2022_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .rpc();
+    const after = BigInt((await provider.connection.getTokenAccountBalance(stakerAta.address)).value.amount);
+    if (after - before !== 9_801n) throw new Error("withdraw should pay out the vault share minus the transfer fee");
+  });
+
+  it("token accounts are checked against the pool mint and staker, and the vault can be an ATA", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const payer = (provider.wallet as any).payer;
+    const staker = provider.wallet.publicKey;
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+
+    // Pool vault = the pool's associated token account, created before the pool
+    const vaultAta = await getOrCreateAssociatedTokenAccount(provider.connection, payer, tokenMint, poolPda, true);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultAta.address, tokenMint, receiptMint: null, owner: staker, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    if (!(await program.account.stakingPool.fetch(poolPda)).tokenVault.equals(vaultAta.address)) throw new Error("pool should use the ATA as vault");
+
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, staker);
+    const stakeAccounts = { stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultAta.address, tokenMint, stakerTokenAccount: userTokenAccount, staker, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID };
+    await program.methods.stake(new anchor.BN(2000)).accounts(stakeAccounts).rpc();
+    if ((await provider.connection.getTokenAccountBalance(vaultAta.address)).value.amount !== "2000") throw new Error("stake should land in the ATA vault");
+
+    // Wrong mint, then an account of someone else
+    const otherMint = await createMint(provider.connection, payer, staker, null, 9);
+    const otherMintAccount = await getOrCreateAssociatedTokenAccount(provider.connection, payer, otherMint, staker);
+    const strangerAccount = await getOrCreateAssociatedTokenAccount(provider.connection, payer, tokenMint, web3.Keypair.generate().publicKey);
+    const withdrawAccounts = { programState: deriveProgramState(program.programId), stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultAta.address, tokenMint, stakerTokenAccount: userTokenAccount, staker, treasury: staker, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId };
+    await expectFail(
+      program.methods.stake(new anchor.BN(100)).accounts({ ...stakeAccounts, stakerTokenAccount: otherMintAccount.address }).rpc(),
+      "Token account does not hold the pool token"
+    );
+    await expectFail(
+      program.methods.withdrawStake(new anchor.BN(500)).accounts({ ...withdrawAccounts, stakerTokenAccount: strangerAccount.address }).rpc(),
+      "Token account is not owned by the staker"
+    );
+    await expectFail(
+      program.methods.withdrawStake(new anchor.BN(500)).accounts({ ...withdrawAccounts, tokenMint: otherMint }).rpc(),
+      "Token mint does not match the pool"
+    );
+    await program.methods.withdrawStake(new anchor.BN(500)).accounts(withdrawAccounts).rpc();
+  });
+
+  it("fee config changes are timelocked and limited to the upgrade authority", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+
+    const statePda = deriveProgramState(program.programId);
+    const programData = deriveProgramData(program.programId);
+    const [pendingPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("pending_fee_config")], program.programId);
+    const authority = provider.wallet.publicKey;
+    const proposeAccounts = { programState: statePda, pendingFeeConfig: pendingPda, programData, authority, systemProgram: web3.SystemProgram.programId };
+
+    // regular > immediate is rejected
+    await expectFail(
+      program.methods.proposeFeeConfig({ feeImmediateLamports: new anchor.BN(1_000), feeRegularLamports: new anchor.BN(2_000), feeMaxLamports: new anchor.BN(5_000), decayDurationSeconds: 3_600, feeMode: 1, feeImmediateBps: 500, feeRegularBps: 10 }).accounts(proposeAccounts).rpc(),
+      "Invalid fee configuration"
+    );
+
+    // Only the upgrade authority can propose
+    const stranger = web3.Keypair.generate();
+    await provider.sendAndConfirm(
+      new web3.Transaction().add(web3.SystemProgram.transfer({ fromPubkey: authority, toPubkey: stranger.publicKey, lamports: 10_000_000 }))
+    );
+    await expectFail(
+      program.methods
+        .proposeFeeConfig({ feeImmediateLamports: new anchor.BN(2_000), feeRegularLamports: new anchor.BN(1_000), feeMaxLamports: new anchor.BN(5_000), decayDurationSeconds: 3_600, feeMode: 1, feeImmediateBps: 500, feeRegularBps: 10 })
+        .accounts({ ...proposeAccounts, authority: stranger.publicKey })
+        .signers([stranger])
+        .rpc(),
+      "Unauthorized"
+    );
+
+    await program.methods.proposeFeeConfig({ feeImmediateLamports: new anchor.BN(2_000), feeRegularLamports: new anchor.BN(1_000), feeMaxLamports: new anchor.BN(5_000), decayDurationSeconds: 3_600, feeMode: 1, feeImmediateBps: 500, feeRegularBps: 10 }).accounts(proposeAccounts).rpc();
+    const before = await program.account.programState.fetch(statePda);
+    await expectFail(
+      program.methods.updateFeeConfig().accounts({ programState: statePda, pendingFeeConfig: pendingPda, programData, authority }).rpc(),
+      "Fee config timelock has not elapsed"
+    );
+    await program.methods.cancelFeeConfig().accounts({ pendingFeeConfig: pendingPda, programData, authority }).rpc();
+
+    const after = await program.account.programState.fetch(statePda);
+    if (!after.feeImmediateLamports.eq(before.feeImmediateLamports)) throw new Error("fees should be unchanged until the timelock passes");
+    if (await program.account.pendingFeeConfig.fetchNullable(pendingPda)) throw new Error("pending config should be closed");
+  });
+
+  it("pool owners can set their own fee curve and take a share of unstake fees", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const owner = provider.wallet.publicKey;
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    const feeAccounts = { programState: statePda, stakingPool: poolPda, owner };
+
+    // Regular fee below the global one, then an owner share above the cap
+    const state = await program.account.programState.fetch(statePda);
+    const immediate = state.feeMaxLamports.divn(2);
+    await expectFail(
+      program.methods.updatePoolFees(immediate, state.feeRegularLamports.subn(1), 3_600, 2_000).accounts(feeAccounts).rpc(),
+      "Invalid fee configuration"
+    );
+    await expectFail(
+      program.methods.updatePoolFees(immediate, state.feeRegularLamports, 3_600, 6_000).accounts(feeAccounts).rpc(),
+      "Invalid fee configuration"
+    );
+    await program.methods.updatePoolFees(immediate, state.feeRegularLamports, 3_600, 5_000).accounts(feeAccounts).rpc();
+    const pool = await program.account.stakingPool.fetch(poolPda);
+    if (pool.feeOwnerShareBps !== 5_000) throw new Error("pool should keep the owner share");
+
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, owner);
+    await program.methods
+      .stake(new anchor.BN(2000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+
+    // The fee is split, so the owner account must be passed
+    const withdrawAccounts = { programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, treasury: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId };
+    await expectFail(
+      program.methods.withdrawStake(new anchor.BN(500)).accounts(withdrawAccounts).rpc(),
+      "Pool owner account is required for the fee split"
+    );
+    await program.methods.withdrawStake(new anchor.BN(500)).accounts({ ...withdrawAccounts, agentOwner: owner }).rpc();
+
+    // Back to the global curve
+    await program.methods.updatePoolFees(new anchor.BN(0), new anchor.BN(0), 0, 0).accounts(feeAccounts).rpc();
+    await program.methods.withdrawStake(new anchor.BN(500)).accounts(withdrawAccounts).rpc();
+  });
+
+  it("token fee mode takes a share of the withdrawn tokens into the pool fee vault", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const owner = provider.wallet.publicKey;
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    const [feeVaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("fee_vault"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+
+    // Token mode needs the fee vault, which anyone can create
+    await expectFail(
+      program.methods.updatePoolFeeMode(2).accounts({ stakingPool: poolPda, owner }).rpc(),
+      "Fee vault is required in token fee mode"
+    );
+    await program.methods
+      .initFeeVault()
+      .accounts({ stakingPool: poolPda, tokenMint, feeVault: feeVaultPda, payer: owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    await program.methods.updatePoolFeeMode(2).accounts({ stakingPool: poolPda, owner }).rpc();
+    const state = await program.account.programState.fetch(statePda);
+    await program.methods
+      .updatePoolFees(state.feeMaxLamports.divn(2), state.feeRegularLamports, 3_600, 5_000)
+      .accounts({ programState: statePda, stakingPool: poolPda, owner })
+      .rpc();
+
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, owner);
+    await program.methods
+      .stake(new anchor.BN(10_000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+
+    // Fresh stake: the full immediate rate applies, in tokens instead of SOL
+    const tokenBalance = async (account: web3.PublicKey) => BigInt((await provider.connection.getTokenAccountBalance(account)).value.amount);
+    const before = await tokenBalance(userTokenAccount);
+    await program.methods
+      .withdrawStake(new anchor.BN(1_000))
+      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, feeVault: feeVaultPda, staker: owner, treasury: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .rpc();
+    const fee = (1_000n * BigInt(state.feeImmediateBps)) / 10_000n;
+    if ((await tokenBalance(userTokenAccount)) - before !== 1_000n - fee) throw new Error("staker should receive the withdrawal minus the token fee");
+    if ((await tokenBalance(feeVaultPda)) !== fee) throw new Error("token fee should land in the fee vault");
+    if ((await program.account.stakingPool.fetch(poolPda)).ownerTokenFees.toNumber() !== Number(fee / 2n)) throw new Error("owner share should be set aside");
+
+    // Owner and treasury are the same wallet here
+    await program.methods
+      .collectPoolFees()
+      .accounts({ programState: statePda, stakingPool: poolPda, feeVault: feeVaultPda, tokenMint, ownerTokenAccount: userTokenAccount, treasuryTokenAccount: userTokenAccount, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    if ((await tokenBalance(feeVaultPda)) !== 0n) throw new Error("collect should empty the fee vault");
+    if ((await program.account.stakingPool.fetch(poolPda)).ownerTokenFees.toNumber() !== 0) throw new Error("owner share should be paid out");
+  });
+
+  it("empty stake accounts and pools can be closed to reclaim rent", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const owner = provider.wallet.publicKey;
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    const [feeVaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("fee_vault"), poolPda.toBuffer()], program.programId);
+    const [rewardVault] = web3.PublicKey.findProgramAddressSync([Buffer.from("reward_vault"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    await program.methods
+      .initFeeVault()
+      .accounts({ stakingPool: poolPda, tokenMint, feeVault: feeVaultPda, payer: owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    await program.methods
+      .initRewards(new anchor.BN(1))
+      .accounts({ stakingPool: poolPda, rewardMint: tokenMint, rewardVault, owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    await program.methods
+      .fundRewards(new anchor.BN(1_000))
+      .accounts({ stakingPool: poolPda, rewardVault, rewardMint: tokenMint, funderTokenAccount: userTokenAccount, funder: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, owner);
+    await program.methods
+      .stake(new anchor.BN(2000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+
+    // The owner can take back reserve that has not been emitted
+    const reserveAccounts = { stakingPool: poolPda, rewardVault, rewardMint: tokenMint, ownerRewardAccount: userTokenAccount, owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID };
+    await expectFail(program.methods.withdrawRewardReserve(new anchor.BN(1_000)).accounts(reserveAccounts).rpc(), "exceeds the unemitted reward reserve");
+    await program.methods.withdrawRewardReserve(new anchor.BN(500)).accounts(reserveAccounts).rpc();
+    await new Promise((r) => setTimeout(r, 2000));
+
+    const closePoolAccounts = { programState: statePda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, ownerTokenAccount: userTokenAccount, feeVault: feeVaultPda, treasuryTokenAccount: userTokenAccount, rewardVault, rewardMint: tokenMint, ownerRewardAccount: userTokenAccount, owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID };
+    await expectFail(program.methods.closeStakeAccount().accounts({ stakeAccount: stakePda, staker: owner }).rpc(), "still holds shares");
+    await expectFail(program.methods.closeStakingPool().accounts(closePoolAccounts).rpc(), "Pool still has shares");
+
+    await program.methods
+      .withdrawStake(new anchor.BN(2000))
+      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, treasury: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .rpc();
+    // An emptied position can only be closed (and re-opened) after the delay
+    await expectFail(program.methods.closeStakeAccount().accounts({ stakeAccount: stakePda, staker: owner }).rpc(), "changed too recently");
+
+    // Settled rewards must be claimed before the reward vault goes
+    await expectFail(program.methods.closeStakingPool().accounts(closePoolAccounts).rpc(), "unclaimed rewards");
+    await program.methods
+      .claimRewards()
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, rewardVault, rewardMint: tokenMint, stakerRewardAccount: userTokenAccount, staker: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+
+    // Direct transfers into the fee and reward vaults do not block closing: they are swept
+    const payer = (provider.wallet as any).payer;
+    await transfer(provider.connection, payer, userTokenAccount, feeVaultPda, payer, 1);
+    await transfer(provider.connection, payer, userTokenAccount, rewardVault, payer, 1);
+    const before = BigInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
+    const swept = BigInt((await provider.connection.getTokenAccountBalance(rewardVault)).value.amount) + 1n;
+    await program.methods.closeStakingPool().accounts(closePoolAccounts).rpc();
+    const after = BigInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
+    if (after - before !== swept) throw new Error("reward and fee vault balances should go to the owner and treasury");
+    if (await provider.connection.getAccountInfo(poolPda)) throw new Error("pool should be closed");
+    if (await provider.connection.getAccountInfo(vaultPda)) throw new Error("vault should be closed");
+    if (await provider.connection.getAccountInfo(feeVaultPda)) throw new Error("fee vault should be closed");
+    if (await provider.connection.getAccountInfo(rewardVault)) throw new Error("reward vault should be closed");
+  });
+
+  it("pool ownership follows the agent owner through sync_pool_owner", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const owner = provider.wallet.publicKey;
+    const newOwner = web3.Keypair.generate();
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+
+    await agentRegistryProgram.methods.transferOwner(newOwner.publicKey).accountsPartial({ agent: agentPda, owner }).rpc();
+    // Anyone can sync, the previous owner loses control of the pool
+    await program.methods.syncPoolOwner().accounts({ agent: agentPda, stakingPool: poolPda }).rpc();
+    const pool = await program.account.stakingPool.fetch(poolPda);
+    if (!pool.owner.equals(newOwner.publicKey)) throw new Error("pool owner should follow the agent owner");
+    await expectFail(
+      program.methods.updateMinStake(new anchor.BN(5)).accounts({ stakingPool: poolPda, owner }).rpc(),
+      "Unauthorized"
+    );
+    await program.methods
+      .updateMinStake(new anchor.BN(5))
+      .accounts({ stakingPool: poolPda, owner: newOwner.publicKey })
+      .signers([newOwner])
+      .rpc();
+  });
+
+  it("paused pools refuse stake and deprecated pools exit without fees", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const owner = provider.wallet.publicKey;
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, owner);
+    const stakeIx = () => program.methods
+      .stake(new anchor.BN(2000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID });
+
+    await program.methods.setPoolPaused(true).accounts({ stakingPool: poolPda, owner }).rpc();
+    await expectFail(stakeIx().rpc(), "Pool is paused");
+    await program.methods.setPoolPaused(false).accounts({ stakingPool: poolPda, owner }).rpc();
+    await stakeIx().rpc();
+
+    // Stake follows the agent's active flag
+    await agentRegistryProgram.methods.setActive(false).accountsPartial({ agent: agentPda, owner }).rpc();
+    await expectFail(stakeIx().rpc(), "Agent is not active");
+    await agentRegistryProgram.methods.setActive(true).accountsPartial({ agent: agentPda, owner }).rpc();
+
+    await program.methods.deprecatePool().accounts({ stakingPool: poolPda, owner }).rpc();
+    await expectFail(stakeIx().rpc(), "Pool is paused");
+    await expectFail(program.methods.setPoolPaused(false).accounts({ stakingPool: poolPda, owner }).rpc(), "Pool is deprecated");
+
+    // A fresh position would pay the immediate fee, the deprecated pool waives it
+    const { treasury } = await program.account.programState.fetch(statePda);
+    const treasuryBefore = await provider.connection.getBalance(treasury);
+    await program.methods
+      .withdrawStake(new anchor.BN(2000))
+      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, treasury, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId })
+      .rpc();
+    if ((await provider.connection.getBalance(treasury)) !== treasuryBefore) throw new Error("deprecated pool should not charge a fee");
+  });
+
+  it("locked stakers can exit a deprecated pool without the lock or its penalty", async () => {
+    if (!program) throw new Error("agent-staking program not found");
+    if (!tokenMint) throw new Error("SPL token not initialized - check before() hook logs");
+
+    const statePda = deriveProgramState(program.programId);
+    const owner = provider.wallet.publicKey;
+    const agentPda = await createIndexedAgentWithStaking();
+    const poolPda = derivePool(agentPda, program.programId);
+    const [vaultPda] = web3.PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], program.programId);
+    await program.methods
+      .createStakingPool(new anchor.BN(1))
+      .accounts({ agent: agentPda, stakingPool: poolPda, tokenVault: vaultPda, tokenMint, receiptMint: null, owner, systemProgram: web3.SystemProgram.programId, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, rent: web3.SYSVAR_RENT_PUBKEY })
+      .rpc();
+    const { stakePda } = await initStakeAccountIfNeeded(agentPda, owner);
+    await program.methods
+      .stake(new anchor.BN(2000))
+      .accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID })
+      .rpc();
+
+    // Tier 1 without a penalty: early exits are rejected outright
+    await program.methods.setLockup(1).accounts({ stakingPool: poolPda, agentPda, stakeAccount: stakePda, staker: owner }).rpc();
+    const { treasury } = await program.account.programState.fetch(statePda);
+    const withdrawIx = (shares: number) => program.methods
+      .withdrawStake(new anchor.BN(shares))
+      .accounts({ programState: statePda, stakingPool: poolPda, agentPda, stakeAccount: stakePda, tokenVault: vaultPda, tokenMint, stakerTokenAccount: userTokenAccount, staker: owner, treasury, tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID, systemProgram: web3.SystemProgram.programId });
+    await expectFail(withdrawIx(2000).rpc(), "Stake is locked");
+
+    await program.methods.deprecatePool().accounts({ stakingPool: poolPda, owner }).rpc();
+    const supplyBefore = (await provider.connection.getTokenSupply(tokenMint)).value.amount;
+    const before = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+    await withdrawIx(2000).rpc();
+    const after = (await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount;
+    const supplyAfter = (await provider.connection.getTokenSupply(tokenMint)).value.amount;
+    if (BigInt(after) - BigInt(before) !== 2000n) throw new Error("the whole locked stake should be returned");
+    if (supplyAfter !== supplyBefore) throw new Error("no penalty should be burned");
+    const stake = await program.account.stakeAccount.fetch(stakePda);
+    if (!stake.shares.isZero()) throw new Error("position should be empty");
+  });
+
   it("FIX: Agent with staking flag cannot be closed", async () => {
     if (!program) throw new Error("agent-staking program not found");
     
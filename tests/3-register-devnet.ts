@@ -24,12 +24,12 @@ describe("register-devnet", () => {
       console.log("✓ Agent already exists from previous test suite");
       console.log(`  Creator: ${creator.toBase58()}`);
       console.log(`  PDA: ${agentPda.toBase58()}`);
-      console.log(`  Card URI: ${Buffer.from(existingAccount.cardUri.slice(0, existingAccount.cardUriLen)).toString('utf8')}`);
+      console.log(`  Card URI: ${Buffer.from(existingAccount.cardUri).toString('utf8')}`);
       console.log(`  Flags: ${existingAccount.flags} (${(existingAccount.flags & 0x04) ? 'HAS_STAKING' : 'no staking'})`);
       
       // Verify the agent is properly structured
       expect(existingAccount.creator.toBase58()).to.equal(creator.toBase58());
-      expect(existingAccount.cardUriLen).to.be.greaterThan(0);
+      expect(existingAccount.cardUri.length).to.be.greaterThan(0);
       
       console.log("✓ Verified existing agent structure");
       console.log("  (This agent cannot be closed because staking is enabled - expected behavior)\n");
@@ -45,15 +45,14 @@ describe("register-devnet", () => {
     };
     
     const sig = await ((program.methods as any)
-      .createAgent(
-        creator, 
-        testAgent.uri, 
-        Array.from(new Uint8Array(32)), 
-        false,  // has_staking=false to allow cleanup
-        null,   // memory_mode
-        null,   // memory_ptr
-        null    // memory_hash
-      ) as any)
+      .createAgent(creator, {
+        cardUri: testAgent.uri,
+        cardHash: Array.from(new Uint8Array(32)),
+        hasStaking: false,  // has_staking=false to allow cleanup
+        memoryMode: null,
+        memoryPtr: null,
+        memoryHash: null,
+      }) as any)
       .accountsPartial({ 
         agent: agentPda, 
         creatorSigner: provider.wallet.publicKey,
@@ -70,7 +69,7 @@ describe("register-devnet", () => {
     // Verify the created agent
     const account = await program.account.agentRegistry.fetch(agentPda);
     expect(account.creator.toBase58()).to.equal(creator.toBase58());
-    expect(account.cardUriLen).to.be.greaterThan(0);
+    expect(account.cardUri.length).to.be.greaterThan(0);
     
     // Cleanup
     await program.methods
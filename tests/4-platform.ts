@@ -2,6 +2,7 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program, web3 } from "@coral-xyz/anchor";
 import { expect } from "chai";
 import { AgentPlatform } from "../target/types/agent_platform";
+import { AgentRegistry } from "../target/types/agent_registry";
 import { sha3_256 } from "js-sha3";
 import { createMint, mintTo, getOrCreateAssociatedTokenAccount } from "@solana/spl-token";
 
@@ -26,6 +27,11 @@ function deriveProgramState(programId: web3.PublicKey) {
   return PublicKey.findProgramAddressSync([Buffer.from("program_state")], programId);
 }
 
+// Upgradeable-loader ProgramData account (its upgrade authority governs ProgramState)
+function deriveProgramData(programId: web3.PublicKey) {
+  return PublicKey.findProgramAddressSync([programId.toBuffer()], new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111"));
+}
+
 function deriveTokenVault(poolPda: web3.PublicKey, programId: web3.PublicKey) {
   return PublicKey.findProgramAddressSync([Buffer.from("token_vault"), poolPda.toBuffer()], programId);
 }
@@ -130,15 +136,14 @@ describe("agent-platform (merged)", () => {
     } catch (e) {}
     
     return await program.methods
-      .createAgent(
-        creator,
+      .createAgent(creator, {
         cardUri,
-        Array.from(cardHash),
+        cardHash: Array.from(cardHash),
         hasStaking,
         memoryMode,
-        memoryPtr ? Array.from(memoryPtr) : null,
-        memoryHash ? Array.from(memoryHash) : null
-      )
+        memoryPtr: memoryPtr ? Array.from(memoryPtr) : null,
+        memoryHash: memoryHash ? Array.from(memoryHash) : null,
+      })
       .accountsPartial({
         agent: agentPda,
         creatorSigner: provider.wallet.publicKey,
@@ -185,15 +190,14 @@ describe("agent-platform (merged)", () => {
       const cardHash = new Uint8Array(32);
 
       await rpc(
-        program.methods.createAgent(
-          creator,
+        program.methods.createAgent(creator, {
           cardUri,
-          Array.from(cardHash),
-          false,
-          null,
-          null,
-          null
-        ).accountsPartial({
+          cardHash: Array.from(cardHash),
+          hasStaking: false,
+          memoryMode: null,
+          memoryPtr: null,
+          memoryHash: null,
+        }).accountsPartial({
           agent: agentPda,
           creatorSigner: creator,
           systemProgram: web3.SystemProgram.programId,
@@ -228,7 +232,7 @@ describe("agent-platform (merged)", () => {
       );
 
       const agent = await program.account.agentRegistry.fetch(agentPda);
-      const storedUri = Buffer.from(agent.cardUri).slice(0, agent.cardUriLen).toString();
+      const storedUri = Buffer.from(agent.cardUri).toString();
       expect(storedUri).eq(newUri);
       
       // Cleanup
@@ -327,6 +331,61 @@ describe("agent-platform (merged)", () => {
       // Cleanup
       await cleanupAgent(creator);
     });
+
+    it("imports an agent from agent_registry", async () => {
+      const registry = anchor.workspace.AgentRegistry as Program<AgentRegistry>;
+      const creator = Keypair.generate();
+      await provider.sendAndConfirm(
+        new web3.Transaction().add(
+          web3.SystemProgram.transfer({ fromPubkey: provider.wallet.publicKey, toPubkey: creator.publicKey, lamports: 100_000_000 })
+        )
+      );
+
+      const [legacyAgent] = deriveAgentPda(creator.publicKey, registry.programId);
+      const [agentPda] = deriveAgentPda(creator.publicKey, program.programId);
+      const cardUri = "https://example.com/legacy-card.json";
+      const ptr = Buffer.from("https://memory.example/legacy.json");
+      await registry.methods
+        .createAgent(creator.publicKey, {
+          cardUri,
+          cardHash: Array.from(new Uint8Array(32).fill(5)),
+          hasStaking: false,
+          memoryMode: 3,
+          memoryPtr: ptr,
+          memoryHash: Array.from(new Uint8Array(32).fill(6)),
+        })
+        .accountsPartial({ agent: legacyAgent, creatorSigner: creator.publicKey })
+        .signers([creator])
+        .rpc();
+
+      await rpc(
+        program.methods.importAgent()
+          .accountsPartial({ legacyAgent, agent: agentPda, agentCounter: null, owner: creator.publicKey })
+          .signers([creator]),
+        "import_agent"
+      );
+
+      const agent = await program.account.agentRegistry.fetch(agentPda);
+      expect(agent.creator.toBase58()).eq(creator.publicKey.toBase58());
+      expect(agent.owner.toBase58()).eq(creator.publicKey.toBase58());
+      expect(Buffer.from(agent.cardUri).toString()).eq(cardUri);
+      expect(Buffer.from(agent.memoryPtr).toString()).eq(ptr.toString());
+      expect(agent.memoryMode).eq(3);
+      expect(agent.flags & 1).gt(0); // FLAG_ACTIVE carried over
+
+      // Same legacy agent cannot be imported twice
+      await expectFail(
+        program.methods.importAgent()
+          .accountsPartial({ legacyAgent, agent: agentPda, agentCounter: null, owner: creator.publicKey })
+          .signers([creator])
+          .rpc(),
+        ["already in use"]
+      );
+
+      await program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator.publicKey }).signers([creator]).rpc();
+      await program.methods.closeAgent()
+        .accountsPartial({ agent: agentPda, owner: creator.publicKey, recipient: creator.publicKey }).signers([creator]).rpc();
+    });
   });
 
   // =========================================================================
@@ -384,6 +443,7 @@ describe("agent-platform (merged)", () => {
           program.methods.initProgramState()
             .accountsPartial({
               programState: statePda,
+              programData: deriveProgramData(program.programId)[0],
               initializer: provider.wallet.publicKey,
               treasury,
               systemProgram: web3.SystemProgram.programId,
@@ -414,6 +474,7 @@ describe("agent-platform (merged)", () => {
             stakingPool: poolPda,
             tokenVault: vaultPda,
             tokenMint,
+            receiptMint: null,
             owner: creator,
             systemProgram: web3.SystemProgram.programId,
             tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
@@ -458,6 +519,7 @@ describe("agent-platform (merged)", () => {
             agentPda,
             stakeAccount: stakePda,
             tokenVault: vaultPda,
+            tokenMint,
             stakerTokenAccount: userTokenAccount,
             staker,
             tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
@@ -466,7 +528,7 @@ describe("agent-platform (merged)", () => {
       );
 
       const stake = await program.account.stakeAccount.fetch(stakePda);
-      expect(stake.stakedAmount.toNumber()).gte(5000);
+      expect(stake.shares.toNumber()).gte(5000);
     });
 
     it("withdraws stake with fee", async function () {
@@ -478,19 +540,20 @@ describe("agent-platform (merged)", () => {
       const state = await program.account.programState.fetch(statePda);
 
       const stakeBefore = await program.account.stakeAccount.fetch(stakePda);
-      if (stakeBefore.stakedAmount.toNumber() === 0) {
+      if (stakeBefore.shares.toNumber() === 0) {
         console.log("  ⚠️  No stake to withdraw, skipping");
         return;
       }
 
       await rpc(
-        program.methods.withdrawStake()
+        program.methods.withdrawStake(stakeBefore.shares)
           .accountsPartial({
             programState: statePda,
             stakingPool: poolPda,
             agentPda,
             stakeAccount: stakePda,
             tokenVault: vaultPda,
+            tokenMint,
             stakerTokenAccount: userTokenAccount,
             staker,
             treasury: state.treasury,
@@ -501,7 +564,7 @@ describe("agent-platform (merged)", () => {
       );
 
       const stakeAfter = await program.account.stakeAccount.fetch(stakePda);
-      expect(stakeAfter.stakedAmount.toNumber()).eq(0);
+      expect(stakeAfter.shares.toNumber()).eq(0);
     });
 
     it("updates min stake amount", async function () {
@@ -538,6 +601,107 @@ describe("agent-platform (merged)", () => {
         ["StakingEnabled"]
       );
     });
+
+    it("closes the agent together with its empty staking pool", async function () {
+      if (!hasPayer) return this.skip();
+
+      const creator = provider.wallet.publicKey;
+      const [vaultPda] = deriveTokenVault(poolPda, program.programId);
+      const pool = await program.account.stakingPool.fetch(poolPda);
+      expect(pool.totalShares.toNumber()).eq(0);
+
+      // An existing pool blocks disable_staking
+      await expectFail(
+        program.methods.disableStaking()
+          .accountsPartial({ agent: agentPda, stakingPool: poolPda, owner: creator }).rpc(),
+        ["StakingPoolExists"]
+      );
+
+      await rpc(
+        program.methods.setActive(false).accountsPartial({ agent: agentPda, owner: creator }),
+        "set_active(false)"
+      );
+      await rpc(
+        program.methods.closeAgent()
+          .accountsPartial({
+            agent: agentPda,
+            owner: creator,
+            recipient: creator,
+            stakingPool: poolPda,
+            tokenVault: vaultPda,
+            tokenMint,
+            ownerTokenAccount: userTokenAccount,
+            tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          }),
+        "close_agent(staking_pool)"
+      );
+
+      expect(await program.account.agentRegistry.fetchNullable(agentPda)).to.be.null;
+      expect(await program.account.stakingPool.fetchNullable(poolPda)).to.be.null;
+      expect(await provider.connection.getAccountInfo(vaultPda)).to.be.null;
+    });
+
+    it("creates an agent with its pool and stakes without init_stake", async function () {
+      if (!hasPayer) return this.skip();
+
+      const creator = Keypair.generate();
+      await provider.sendAndConfirm(
+        new web3.Transaction().add(
+          web3.SystemProgram.transfer({ fromPubkey: provider.wallet.publicKey, toPubkey: creator.publicKey, lamports: 100_000_000 })
+        )
+      );
+      const [newAgentPda] = deriveAgentPda(creator.publicKey, program.programId);
+      const [newPoolPda] = derivePool(newAgentPda, program.programId);
+      const [vaultPda] = deriveTokenVault(newPoolPda, program.programId);
+
+      await rpc(
+        program.methods
+          .createAgentWithPool(
+            creator.publicKey,
+            { cardUri: "https://example.com/card.json", cardHash: Array.from(new Uint8Array(32)), hasStaking: null, memoryMode: null, memoryPtr: null, memoryHash: null },
+            new anchor.BN(1000)
+          )
+          .accountsPartial({
+            agent: newAgentPda,
+            stakingPool: newPoolPda,
+            tokenVault: vaultPda,
+            tokenMint,
+            receiptMint: null,
+            creatorSigner: creator.publicKey,
+            tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          })
+          .signers([creator]),
+        "create_agent_with_pool"
+      );
+      const agent = await program.account.agentRegistry.fetch(newAgentPda);
+      expect(agent.flags & 4).eq(4);
+      const pool = await program.account.stakingPool.fetch(newPoolPda);
+      expect(pool.owner.toBase58()).eq(creator.publicKey.toBase58());
+      expect(pool.tokenVault.toBase58()).eq(vaultPda.toBase58());
+
+      // The first stake creates the stake account
+      const staker = provider.wallet.publicKey;
+      const [stakePda] = deriveStake(staker, newAgentPda, program.programId);
+      expect(await program.account.stakeAccount.fetchNullable(stakePda)).to.be.null;
+      await rpc(
+        program.methods.stake(new anchor.BN(5000))
+          .accountsPartial({
+            stakingPool: newPoolPda,
+            agentPda: newAgentPda,
+            stakeAccount: stakePda,
+            tokenVault: vaultPda,
+            tokenMint,
+            stakerTokenAccount: userTokenAccount,
+            staker,
+            tokenProgram: anchor.utils.token.TOKEN_PROGRAM_ID,
+          }),
+        "stake(init)"
+      );
+      const stake = await program.account.stakeAccount.fetch(stakePda);
+      expect(stake.staker.toBase58()).eq(staker.toBase58());
+      expect(stake.shares.toNumber()).gte(5000);
+      expect(stake.imported).eq(false);
+    });
   });
 });
 